@@ -108,3 +108,28 @@ fn vm_load_big(b: &mut Bencher) {
     }
 }
 
+
+//虚拟机从已加载大字节码的模板虚拟机复制
+#[cfg(feature = "vm-template")]
+#[bench]
+fn vm_clone_big(b: &mut Bencher) {
+    register_native_object();
+
+    let file_name = &String::from("core.js");
+    if let Ok(mut file) = File::open("benches/core.js") {
+        let mut contents = String::new();
+        if let Ok(_) = file.read_to_string(&mut contents) {
+            if let Some(ref js) = JS::new(1, Atom::from("test_shell"), Arc::new(NativeObjsAuth::new(None, None)), None) {
+                if let Some(ref code) = js.compile(file_name.clone(), (&contents).clone()) {
+                    js.load(code);
+                    while !js.is_ran() {}
+                    b.iter(|| {
+                        if let None = JS::clone_from(js, 2, Atom::from("test_shell"), Arc::new(NativeObjsAuth::new(None, None)), None) {
+                            panic!("!!!> Vm Clone Error");
+                        }
+                    });
+                }
+            }
+        }
+    }
+}
@@ -18,6 +18,7 @@ extern crate task_pool;
 extern crate worker;
 
 use std::sync::{Arc, RwLock};
+use std::sync::mpsc::channel;
 use std::fs::File;
 use std::io::prelude::*;
 
@@ -27,7 +28,7 @@ use atom::Atom;
 use handler::{GenType, Handler, Args};
 use gray::{GrayVersion, Gray, GrayTab};
 
-use pi_vm::pi_vm_impl::{VMFactory, BlockError, block_set_global_var, block_reply, block_throw, push_callback, register_async_request, async_request};
+use pi_vm::pi_vm_impl::{VMFactory, CallOptions, BatchError, BlockError, block_set_global_var, block_reply, block_throw, push_callback, register_async_request, async_request};
 use pi_vm::adapter::{register_native_object, JS, JSType};
 use pi_vm::channel_map::VMChannel;
 use pi_vm::bonmgr::{BON_MGR, NativeObjsAuth, FnMeta, CallResult, ptr_jstype, jstype_ptr};
@@ -60,6 +61,40 @@ fn empty_call(b: &mut Bencher) {
     });
 }
 
+//在一次虚拟机任务中批量空调用
+#[bench]
+fn empty_batch_call(b: &mut Bencher) {
+    let worker_pool = Box::new(WorkerPool::new("Test Wrap VM".to_string(), WorkerType::Js, 1, 1024 * 1024, 100000, JS_WORKER_WALKER.clone()));
+    worker_pool.run(JS_TASK_POOL.clone());
+
+    register_native_object();
+
+    let js = create_js();
+    let mut factory = VMFactory::new("wrap vm batch benches", 1, 1000, 8388608, 67108864, Arc::new(NativeObjsAuth::new(None, None)));
+    for file in &["benches/core.js", "benches/pref/js_sync_call_small_bigtest-empty-call.js"] {
+        let mut contents = String::new();
+        File::open(file).unwrap().read_to_string(&mut contents).unwrap();
+        factory = factory.append(Arc::new(js.compile(file.to_string(), contents).unwrap()));
+    }
+    factory.produce(1).unwrap();
+
+    b.iter(|| {
+        let (sender, receiver) = channel();
+        let mut batch: Vec<Box<FnOnce(Arc<JS>) -> usize>> = Vec::with_capacity(10000);
+        for _ in 0..10000 {
+            batch.push(Box::new(|_js: Arc<JS>| 0usize));
+        }
+        let handler = Box::new(move |_js: Arc<JS>, index: usize, _result: Result<&JSType, BatchError>| {
+            if index == 9999 {
+                sender.send(()).unwrap();
+            }
+        });
+
+        factory.call_batch(CallOptions::new(), Atom::from("test"), batch, handler, Atom::from("empty batch call task")).expect("batch call refused");
+        receiver.recv().unwrap();
+    });
+}
+
 //有参数和返回值的空调用
 #[bench]
 fn base_call(b: &mut Bencher) {
@@ -576,7 +611,7 @@ fn init_async_request_env(port: &str, file: &str) {
                 ptr_jstype(vm.get_objs(), vm.clone(), ptr, 3366364668);
                 6
             });
-            gray.factory.call(None, Atom::from("_$async"), real_args, Atom::from((*name).to_string() + " rpc task"));
+            gray.factory.call(None, Atom::from("_$async"), real_args, Atom::from((*name).to_string() + " rpc task")).expect("async request refused");
         }
     }
 
@@ -5,7 +5,9 @@ use std::fmt::{Debug, Formatter, Result as FmtResult};
 use std::string::FromUtf8Error;
 use std::ffi::{CStr, CString};
 use std::collections::{VecDeque, HashMap};
-use std::mem::transmute;
+use std::mem::{transmute, size_of, align_of};
+use std::ptr::{self, read_unaligned, write_unaligned};
+use std::io::{Error, ErrorKind};
 use std::time::{Duration, SystemTime, Instant};
 use std::cell::RefCell;
 use std::sync::{Arc, Mutex, RwLock};
@@ -15,12 +17,9 @@ use std::thread;
 #[cfg(not(unix))]
 use kernel32;
 
-use rand::prelude::*;
-use rand::{Rng, SeedableRng};
-use rand::rngs::SmallRng;
 
 use worker::task::TaskType;
-use worker::impls::{create_js_task_queue, js_static_sync_task_size, js_dyn_sync_task_size, js_static_async_task_size, js_dyn_async_task_size, lock_js_task_queue, unlock_js_task_queue, cast_js_task, cast_js_delay_task};
+use worker::impls::{js_static_sync_task_size, js_dyn_sync_task_size, js_static_async_task_size, js_dyn_async_task_size};
 use apm::common::SysStat;
 use apm::allocator::{VM_ALLOCATED, get_max_alloced_limit, is_alloced_limit, vm_alloced_size, all_alloced_size};
 use apm::counter::{GLOBAL_PREF_COLLECT, PrefCounter, PrefTimer};
@@ -30,7 +29,14 @@ use lfstack::{CollectResult, LFStack};
 
 use native_object_impl::*;
 use bonmgr::{NativeObjs, NObject, NativeObjsAuth};
-use pi_vm_impl::VMFactory;
+use pi_vm_impl::{VMFactory, CallOptions, SourceQueue};
+use buffer::{ExternalBytes, register_external_buffer, external_buffer_free};
+use heap;
+use reuse::{ReuseDecision, VmStats};
+use metrics::{FactoryMetrics, factory_metrics};
+use event::{VmEvent, ThrowReason, is_listening, emit, emit_thrown};
+use slow::{SlowKind, SlowStart, start_slow, finish_slow};
+use scheduler::{create_js_task_queue, lock_js_task_queue, unlock_js_task_queue, cast_js_task, cast_js_delay_task};
 
 /*
 * 多余的空闲内存上限，单位B，默认512MB
@@ -47,26 +53,53 @@ const JS_ASYNC_MSG_QUEUE_PRIORITY: usize = 1000;
 */
 const JS_THREAD_GLOBAL_VAR_NAME: &'static str = "__curr_block_thread";
 
+/*
+* 虚拟机记录的最近任务信息的最大数量
+*/
+const MAX_TASK_INFO_COUNT: usize = 8;
+
+/*
+* 虚拟机替换任务的优先级
+*/
+const VM_RECREATE_TASK_PRIORITY: usize = 100;
+
+thread_local! {
+    //当前线程正在初始化的虚拟机所属虚拟机工厂的指标，初始化异常的回应没有虚拟机，通过它记录到虚拟机工厂
+    static INIT_METRICS: RefCell<Option<Arc<FactoryMetrics>>> = RefCell::new(None);
+}
+
 lazy_static! {
     //虚拟机超时时长，单位us, 默认5分钟
     static ref VM_TIMEOUT: AtomicUsize = AtomicUsize::new(300000000);
+    //全局虚拟机整理定时器是否已停止
+    static ref VM_COLLECT_STOPPED: AtomicBool = AtomicBool::new(false);
+    //全局虚拟机整理定时器的整理间隔，单位ms，0表示未注册
+    static ref VM_COLLECT_INTERVAL: AtomicUsize = AtomicUsize::new(0);
+    //全局虚拟机整理定时器的最近注册时间，单位us
+    static ref VM_COLLECT_REGISTER_TIME: AtomicUsize = AtomicUsize::new(0);
+    //全局虚拟机整理的最近运行时间，单位us，0表示未运行
+    static ref VM_COLLECT_LAST_TIME: AtomicUsize = AtomicUsize::new(0);
     //虚拟机工厂注册表
     pub static ref VM_FACTORY_REGISTERS: Arc<RwLock<HashMap<String, Arc<VMFactory>>>> = Arc::new(RwLock::new(HashMap::new()));
     //虚拟机整理队列
     pub static ref VM_COLLECT_QUEUE: Arc<Mutex<VecDeque<String>>> = Arc::new(Mutex::new(VecDeque::new()));
+    //虚拟机堆超限处理器列表
+    static ref VM_HEAP_LIMIT_HANDLERS: Arc<RwLock<Vec<Arc<Fn(Arc<JS>, usize, usize) + Send + Sync>>>> = Arc::new(RwLock::new(Vec::new()));
 }
 
 lazy_static! {
     //虚拟机初始化异常数量
-    static ref VM_INIT_PANIC_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_init_panic_count"), 0).unwrap();
+    pub(crate) static ref VM_INIT_PANIC_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_init_panic_count"), 0).unwrap();
     //虚拟机运行异常数量
-    static ref VM_RUN_PANIC_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_run_panic_count"), 0).unwrap();
+    pub(crate) static ref VM_RUN_PANIC_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_run_panic_count"), 0).unwrap();
     //虚拟机等待同步阻塞调用的数量
-    static ref VM_WAIT_BLOCK_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_wait_block_count"), 0).unwrap();
+    pub(crate) static ref VM_WAIT_BLOCK_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_wait_block_count"), 0).unwrap();
     //虚拟机完成同步任务、异步任务或异步回调的数量
-    static ref VM_FINISH_TASK_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_finish_task_count"), 0).unwrap();
+    pub(crate) static ref VM_FINISH_TASK_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_finish_task_count"), 0).unwrap();
     //虚拟机弹出异步回调的数量
-    static ref VM_POP_CALLBACK_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_pop_callback_count"), 0).unwrap();
+    pub(crate) static ref VM_POP_CALLBACK_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_pop_callback_count"), 0).unwrap();
+    //虚拟机分配内存超过堆限制的数量
+    pub(crate) static ref VM_HEAP_LIMIT_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_heap_limit_count"), 0).unwrap();
 }
 
 #[link(name = "dukc")]
@@ -74,15 +107,18 @@ extern "C" {
     fn dukc_manual_free() -> c_int;
     fn dukc_register_native_object_function_call(func: extern fn(*const c_void_ptr, u32, u32, *const c_void_ptr, *const c_void_ptr) -> c_int);
     fn dukc_register_native_object_free(func: extern fn(*const c_void_ptr, u32));
+    fn dukc_register_heap_limit(func: extern fn(*const c_void_ptr, size_t, size_t));
     fn dukc_heap_create() -> *const c_void_ptr;
     fn dukc_heap_init(vm: *const c_void_ptr, reply: extern fn(*const c_void_ptr, c_int, *const c_uchar)) -> u32;
     fn dukc_init_char_output(vm: *const c_void_ptr, func: extern fn(*const c_char));
     // fn dukc_vm_create(heap: *const c_void_ptr) -> *const c_void_ptr;
     fn dukc_vm_size(vm: *const c_void_ptr) -> size_t;
+    fn dukc_vm_set_heap_limit(vm: *const c_void_ptr, limit: size_t);
+    fn dukc_vm_heap_walk(vm: *const c_void_ptr, context: *mut c_void_ptr, func: extern fn(*mut c_void_ptr, u64, u8, size_t, *const u64, u32)) -> u32;
     fn dukc_compile_script(vm: *const c_void_ptr, file: *const c_char, code: *const c_char, size: *mut u32, reply: extern fn(*const c_void_ptr, c_int, *const c_uchar)) -> *const c_void_ptr;
     fn dukc_load_code(vm: *const c_void_ptr, size: u32, bytes: *const c_void_ptr, reply: extern fn(*const c_void_ptr, c_int, *const c_uchar)) -> u32;
     fn dukc_bind_vm(vm: *const c_void_ptr, handler: *const c_void_ptr);
-    // fn dukc_vm_clone(size: u32, bytes: *const c_void_ptr, reply: extern fn(*const c_void_ptr, c_int, *const c_char)) -> *const c_void_ptr;
+    fn dukc_vm_clone(size: u32, bytes: *const c_void_ptr, reply: extern fn(*const c_void_ptr, c_int, *const c_char)) -> *const c_void_ptr;
     fn dukc_vm_run(vm: *const c_void_ptr, reply: extern fn(*const c_void_ptr, c_int, *const c_uchar));
     fn dukc_vm_global_template(vm: *const c_void_ptr) -> u32;
     fn dukc_vm_global_swap(vm: *const c_void_ptr) -> u32;
@@ -105,6 +141,11 @@ extern "C" {
     fn dukc_set_array_index(vm: *const c_void_ptr, array: u32, index: u32, value: u32) -> u32;
     fn dukc_new_array_buffer(vm: *const c_void_ptr, length: u32) -> u32;
     fn dukc_new_uint8_array(vm: *const c_void_ptr, length: u32) -> u32;
+    fn dukc_new_typed_array(vm: *const c_void_ptr, type_id: u8, length: u32) -> u32;
+    fn dukc_new_typed_array_view(vm: *const c_void_ptr, type_id: u8, buffer: u32, offset: u32, length: u32) -> u32;
+    fn dukc_register_external_buffer_free(func: extern fn(u64));
+    fn dukc_new_external_array_buffer(vm: *const c_void_ptr, ptr: *const c_void_ptr, length: u32, handle: u64, read_only: c_int) -> u32;
+    fn dukc_new_external_uint8_array(vm: *const c_void_ptr, ptr: *const c_void_ptr, length: u32, handle: u64, read_only: c_int) -> u32;
     fn dukc_new_native_object(vm: *const c_void_ptr, ptr: u64) -> u32;
     pub fn dukc_new_error(vm: *const c_void_ptr, reason: *const c_char) -> u32;
     pub fn dukc_remove_value(vm: *const c_void_ptr, value: u32);
@@ -140,6 +181,18 @@ extern "C" {
     fn dukc_vm_destroy(vm: *const c_void_ptr);
 }
 
+/*
+* 复制虚拟机堆的接口，需要dukc提供堆快照复制，所以只在启用vm-template时链接
+*
+* dukc_vm_clone_heap复制指定模板虚拟机的完整堆状态，并返回新的虚拟机堆，新堆未绑定虚拟机，失败返回空指针
+* 调用者必须保证模板虚拟机空闲且没有本地对象，复制期间不会修改模板虚拟机，复制失败时通过reply以空句柄回应，与初始化异常相同处理
+*/
+#[cfg(feature = "vm-template")]
+#[link(name = "dukc")]
+extern "C" {
+    fn dukc_vm_clone_heap(template: *const c_void_ptr, reply: extern fn(*const c_void_ptr, c_int, *const c_uchar)) -> *const c_void_ptr;
+}
+
 #[cfg(all(feature="unstable", any(target_arch = "x86", target_arch = "x86_64")))]
 #[inline(always)]
 pub fn pause() {
@@ -170,9 +223,19 @@ pub extern "C" fn js_reply_callback(handler: *const c_void_ptr, status: c_int, e
         //处理初始化异常
         if status != 0 {
             VM_INIT_PANIC_COUNT.sum(1);
+            INIT_METRICS.with(|current| {
+                if let Some(metrics) = &*current.borrow() {
+                    metrics.incr_init_panic();
+                }
+            });
 
+            let error_info = unsafe { CStr::from_ptr(err as *const c_char).to_string_lossy().into_owned() };
             warn!("!!!> JS Init Error, status: {}, err: {}",
-                     status, unsafe { CStr::from_ptr(err as *const c_char).to_string_lossy().into_owned() });
+                     status, error_info);
+            emit(VmEvent::InitPanic {
+                status: status as i32,
+                error: error_info,
+            });
         }
         return;
     }
@@ -187,13 +250,22 @@ pub extern "C" fn js_reply_callback(handler: *const c_void_ptr, status: c_int, e
         if status != 0 {
             //有异常，则重置虚拟机线程全局变量，保证虚拟机可以继续运行
             VM_RUN_PANIC_COUNT.sum(1);
+            vm_factory_metrics(&js).incr_run_panic();
+            js.error_count.fetch_add(1, Ordering::Relaxed);
 
             let error_info = CStr::from_ptr(err as *const c_char).to_string_lossy().into_owned();
+            if is_listening() {
+                emit(VmEvent::RunError {
+                    factory: (&js.name).to_string(),
+                    vm_id: js.get_id(),
+                    error: error_info.clone(),
+                });
+            }
             match js.catcher.load(Ordering::Relaxed) {
                 catcher if catcher < 0 => {
                     //没有设置异常捕获回调
-                    warn!("!!!> JS Run Error, vm: {:?}, err: {}",
-                          js, error_info);
+                    warn!("!!!> JS Run Error, vm: {:?}, options: {:?}, err: {}",
+                          js, js.call_options.borrow(), error_info);
                 },
                 catcher => {
                     //设置了异常捕获回调
@@ -206,6 +278,7 @@ pub extern "C" fn js_reply_callback(handler: *const c_void_ptr, status: c_int, e
             }
         }
 
+        finish_slow(&js); //在处理消息队列和整理虚拟机前检查慢调用，以保证获取的是当前调用的js栈
         js.update_last_heap_size(); //在js当前任务执行完成后，更新虚拟机堆大小和内存占用
         js.queue.size.fetch_sub(1, Ordering::SeqCst); //减少消息队列长度
         if dukc_vm_status_check(vm, JSStatus::WaitBlock as i8) > 0 {
@@ -219,9 +292,14 @@ pub extern "C" fn js_reply_callback(handler: *const c_void_ptr, status: c_int, e
                 *js.ret.borrow_mut() = js.stack_top_string(); //返回值缓存不为空，则将当前执行结果更新返回值缓存
             }
             dukc_pop(vm); //移除上次同步任务、异步任务或回调函数的执行结果
+            if let Some((_, factory)) = &js.collection {
+                //在虚拟机回收前记录完成的任务，以保证关闭虚拟机工厂时可以统计到
+                factory.finish_task();
+            }
             handle_async_callback(js.clone(), vm);
 
             VM_FINISH_TASK_COUNT.sum(1);
+            vm_factory_metrics(&js).incr_finish_task();
         } else if dukc_vm_status_check(vm, JSStatus::WaitCallBack as i8) > 0 {
             //当前虚拟机任务已执行完成且当前虚拟机状态是等待回调状态，则处理消息队列
             handle_async_callback(js.clone(), vm);
@@ -230,6 +308,76 @@ pub extern "C" fn js_reply_callback(handler: *const c_void_ptr, status: c_int, e
     Arc::into_raw(js);
 }
 
+//获取指定虚拟机所属虚拟机工厂的指标
+fn vm_factory_metrics(js: &JS) -> Arc<FactoryMetrics> {
+    match &js.collection {
+        Some((_, factory)) => factory.metrics(),
+        None => factory_metrics(&(&js.name).to_string()),
+    }
+}
+
+//在初始化指定虚拟机工厂的虚拟机期间，设置当前线程正在初始化的虚拟机工厂的指标，以记录初始化异常
+fn with_init_metrics<R, F: FnOnce() -> R>(name: &Atom, collection: &Option<(Arc<AtomicBool>, Arc<VMFactory>)>, func: F) -> R {
+    let metrics = match collection {
+        Some((_, factory)) => factory.metrics(),
+        None => factory_metrics(&name.to_string()),
+    };
+    let last = INIT_METRICS.with(|current| current.replace(Some(metrics)));
+    let result = func();
+    INIT_METRICS.with(move |current| *current.borrow_mut() = last);
+    result
+}
+
+/*
+* js堆超限回调函数
+*
+* 虚拟机分配内存后的堆大小超过虚拟机堆限制时，分配会失败，并在js中抛出RangeError，同时标记虚拟机为等待丢弃，并通知所有堆超限处理器
+*/
+#[no_mangle]
+pub extern "C" fn js_heap_limit_callback(handler: *const c_void_ptr, size: size_t, limit: size_t) {
+    if handler.is_null() {
+        return;
+    }
+
+    let js = unsafe { JS::from_raw(handler) };
+    VM_HEAP_LIMIT_COUNT.sum(1);
+    js.wait_throw.store(true, Ordering::Relaxed); //标记为等待丢弃，在当前任务执行完成后丢弃
+
+    warn!("!!!> JS Heap Limit, vm: {:?}, size: {}, limit: {}", js, size, limit);
+    if is_listening() {
+        emit(VmEvent::WaitThrow {
+            factory: (&js.name).to_string(),
+            vm_id: js.get_id(),
+            heap_size: size,
+        });
+    }
+
+    let handlers = VM_HEAP_LIMIT_HANDLERS.read().unwrap().clone();
+    for handler in handlers {
+        handler(js.clone(), size, limit);
+    }
+    Arc::into_raw(js);
+}
+
+/*
+* js堆遍历回调函数
+*/
+extern "C" fn heap_walk_callback(context: *mut c_void_ptr, id: u64, type_id: u8, size: size_t, refs: *const u64, len: u32) {
+    unsafe {
+        let func = &mut *(context as *mut &mut FnMut(u64, u8, usize, &[u64]));
+        if refs.is_null() || len == 0 {
+            func(id, type_id, size, &[]);
+        } else {
+            func(id, type_id, size, from_raw_parts(refs, len as usize));
+        }
+    }
+}
+
+//注册虚拟机堆超限处理器，处理器参数分别为超限的虚拟机、分配后的堆大小和堆限制，处理器在分配内存时同步调用，不允许操作虚拟机的值栈
+pub fn register_heap_limit_handler(handler: Arc<Fn(Arc<JS>, usize, usize) + Send + Sync>) {
+    VM_HEAP_LIMIT_HANDLERS.write().unwrap().push(handler);
+}
+
 /*
 * 处理异步回调，只有虚拟机当前同步任务、异步任务或异步回调已执行完成，才允许开始处理其它异步回调，特别的如果正在处理异步任务时，调用任何关于异步回调的非安全函数，都会导致异常
 */
@@ -275,6 +423,11 @@ pub unsafe fn handle_async_callback(js: Arc<JS>, vm: *const c_void_ptr) {
         if !unlock_js_task_queue(tasks) {
             warn!("!!!> Handle Callback Error, unlock js task queue failed, tasks: {:?}", tasks);
         }
+
+        if let Some(source) = js.take_source() {
+            //同步任务队列已解锁，则减少源的等待任务数量，源的同步任务队列在锁住时不会被整理
+            source.finish();
+        }
     }
 
     //更新当前虚拟机最近运行时间
@@ -296,13 +449,24 @@ pub fn now_utc() -> usize {
 }
 
 //整理虚拟机，处理虚拟机丢弃和复用
-fn collect_vm(js: Arc<JS>) {
+pub(crate) fn collect_vm(js: Arc<JS>) {
+    heap::take_requested_snapshot(&js); //虚拟机空闲，则导出被请求的堆快照
+
+    if js.session().is_some() {
+        //已绑定会话的虚拟机，不重置全局环境，由虚拟机工厂继续执行会话的后续任务
+        if let Some((_, factory)) = js.collection.clone() {
+            factory.release_session(js);
+            return;
+        }
+    }
+
     if js.wait_throw.load(Ordering::Relaxed) {
         //丢弃标记为等待丢弃的虚拟机
         if let Some((lock, factory)) = js.collection.clone() {
             if lock.load(Ordering::SeqCst) {
                 factory.throw(1);
                 info!("===> Vm Throw Ok, vm: {:?}", js);
+                emit_thrown(&js, ThrowReason::WaitThrow);
                 return;
             }
         }
@@ -313,37 +477,83 @@ fn collect_vm(js: Arc<JS>) {
 
     if let Some((lock, factory)) = js.collection.clone() {
         if lock.load(Ordering::SeqCst) {
-            //回收器已解锁，则检查是否需要复用
-            match js.check_reuse() {
-                0 => {
+            //回收器已解锁，则根据虚拟机工厂的复用策略检查是否需要复用
+            let policy = factory.reuse_policy();
+            match policy.decide(&VmStats::new(&js, &factory)) {
+                ReuseDecision::Throw => {
                     //需要立即丢弃当前虚拟机
                     factory.throw(1);
                     info!("===> Vm Throw Ok, vm: {:?}", js);
+                    emit_thrown(&js, ThrowReason::Policy);
                 },
-                state => {
+                ReuseDecision::Recreate => {
+                    //需要立即丢弃当前虚拟机，并构建新的虚拟机
+                    recreate_vm(&js, &factory);
+                },
+                decision => {
                     //需要继续整理当前虚拟机，并复用
                     let copy = js.clone();
                     if js.clear_global() {
                         //清理成功，则重置当前虚拟机的全局环境
                         if js.alloc_global() {
                             //虚拟机已重置全局环境
-                            if state == 1 {
+                            if decision == ReuseDecision::Free {
                                 //需要释放当前虚拟机可回收内存
                                 if js.free_global() {
-                                    let max_heap_size = factory.max_heap_size();
-                                    if (max_heap_size > 0) && (js.heap_size() >= ((max_heap_size as f64 * 0.75).ceil() as usize)) {
-                                        //释放后，仍然大于虚拟机堆限制的75%，则标记为等待丢弃，等待下次执行后丢弃
-                                        js.wait_throw.store(true, Ordering::Relaxed);
-                                    } else {
-                                        //释放后，小于虚拟机堆限制
-                                        info!("===> Vm Free Ok, vm: {:?}", js);
+                                    match policy.after_free(&VmStats::new(&js, &factory)) {
+                                        ReuseDecision::Throw => {
+                                            //释放后，仍然需要丢弃，则标记为等待丢弃，等待下次执行后丢弃
+                                            js.wait_throw.store(true, Ordering::Relaxed);
+                                            heap::take_auto_snapshot(&js);
+                                            if is_listening() {
+                                                emit(VmEvent::WaitThrow {
+                                                    factory: (&js.name).to_string(),
+                                                    vm_id: js.get_id(),
+                                                    heap_size: js.heap_size(),
+                                                });
+                                            }
+                                        },
+                                        ReuseDecision::Recreate => {
+                                            //释放后，需要立即替换
+                                            recreate_vm(&js, &factory);
+                                            return;
+                                        },
+                                        _ => {
+                                            //释放后，可以复用
+                                            info!("===> Vm Free Ok, vm: {:?}", js);
+                                            if is_listening() {
+                                                emit(VmEvent::Freed {
+                                                    factory: (&js.name).to_string(),
+                                                    vm_id: js.get_id(),
+                                                    heap_size: js.heap_size(),
+                                                });
+                                            }
+                                        },
                                     }
                                 } else {
                                     warn!("!!!> Vm Collection Error, vm: {:?}, e: free global failed", js);
                                 }
                             }
 
+                            if factory.check_leak(&js) {
+                                //复用后堆持续增长，且需要主动丢弃，则立即丢弃当前虚拟机
+                                factory.throw(1);
+                                info!("===> Vm Throw Ok by Leak, vm: {:?}", js);
+                                emit_thrown(&js, ThrowReason::Leak);
+                                return;
+                            }
+
+                            js.reused_count.fetch_add(1, Ordering::Relaxed); //增加虚拟机复用次数
+                            js.set_call_options(None); //重置虚拟机当前任务的调用选项
                             js.queue.size.store(0, Ordering::Relaxed); //重置虚拟机当前消息队列
+                            if is_listening() {
+                                emit(VmEvent::Reused {
+                                    factory: (&js.name).to_string(),
+                                    vm_id: js.get_id(),
+                                    heap_size: js.heap_size(),
+                                    reused_count: js.reused_count(),
+                                });
+                            }
                             factory.reuse(js); //复用当前虚拟机
                         } else {
                             warn!("!!!> Vm Collection Error, vm: {:?}, e: alloc global failed", copy);
@@ -358,6 +568,33 @@ fn collect_vm(js: Arc<JS>) {
     }
 }
 
+//丢弃指定虚拟机，并异步为虚拟机工厂构建新的虚拟机，以避免在整理虚拟机时加载字节码
+fn recreate_vm(js: &Arc<JS>, factory: &Arc<VMFactory>) {
+    factory.throw(1);
+    emit_thrown(js, ThrowReason::Recreate);
+
+    let vm_id = js.get_id();
+    let factory = factory.clone();
+    let func = Box::new(move |_lock: Option<isize>| {
+        match factory.collect_produce() {
+            Err(e) => warn!("!!!> Vm Recreate Error, factory: {:?}, vm: {:?}, e: {:?}", factory.name(), vm_id, e),
+            Ok(_) => info!("===> Vm Recreate Ok, factory: {:?}, vm: {:?}", factory.name(), vm_id),
+        }
+    });
+    cast_js_task(TaskType::Async(false), VM_RECREATE_TASK_PRIORITY, None, func, Atom::from("vm recreate task"));
+}
+
+//将外部数据注册为外部缓冲区，返回数据指针、数据长度、外部缓冲区句柄和是否只读，数据长度超过u32返回错误
+fn external_buffer<B: ExternalBytes>(bytes: B) -> Result<(*const c_void_ptr, u32, u64, c_int), Error> {
+    let (owner, ptr, len, read_only) = bytes.into_external();
+    if len > u32::max_value() as usize {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("external buffer too large, len: {}", len)));
+    }
+
+    let handle = register_external_buffer(owner, len);
+    Ok((ptr as *const c_void_ptr, len as u32, handle, read_only as c_int))
+}
+
 /*
 * 初始化注入NativeObject关联函数
 */
@@ -365,6 +602,8 @@ pub fn register_native_object() {
     unsafe {
         dukc_register_native_object_function_call(native_object_function_call);
         dukc_register_native_object_free(native_object_function_free);
+        dukc_register_external_buffer_free(external_buffer_free);
+        dukc_register_heap_limit(js_heap_limit_callback);
     }
 }
 
@@ -390,6 +629,7 @@ pub fn load_lib_backtrace() {
 /*
 * js状态
 */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum JSStatus {
     Destroy = -1,
     NoTask,
@@ -423,11 +663,20 @@ pub struct JS {
     id:                 usize,                                      //虚拟机id
     name:               Atom,                                       //虚拟机名
     last_heap_size:     Arc<AtomicIsize>,                           //虚拟机最近堆大小
-    rng:                Arc<RefCell<SmallRng>>,                     //虚拟机随机数生成器
     collection:         Option<(Arc<AtomicBool>, Arc<VMFactory>)>,  //虚拟机回收器
     last_time:          Arc<AtomicUsize>,                           //虚拟机最近运行时间
     wait_throw:         Arc<AtomicBool>,                            //虚拟机等待被丢弃，下次运行后丢弃
     catcher:            Arc<AtomicI32>,                             //虚拟机异常捕获器
+    heap_limit:         Arc<AtomicUsize>,                           //虚拟机堆限制，0表示无限制
+    reset_heap_sizes:   Arc<RefCell<VecDeque<usize>>>,              //虚拟机最近重置全局环境后的堆大小列表
+    task_infos:         Arc<RefCell<VecDeque<Atom>>>,               //虚拟机最近执行的任务信息列表
+    reused_count:       Arc<AtomicUsize>,                           //虚拟机已复用次数
+    error_count:        Arc<AtomicUsize>,                           //虚拟机运行异常次数
+    call_options:       Arc<RefCell<Option<CallOptions>>>,          //虚拟机当前任务的调用选项
+    session:            Arc<RefCell<Option<usize>>>,                //虚拟机绑定的会话，为空表示未绑定
+    block_start:        Arc<RefCell<Option<(u32, Instant)>>>,       //虚拟机当前同步阻塞调用的本地函数hash和开始时间
+    source:             Arc<RefCell<Option<Arc<SourceQueue>>>>,     //虚拟机当前任务所属源的同步任务队列，在解锁同步任务队列时完成
+    slow_start:         Arc<RefCell<Option<SlowStart>>>,            //虚拟机正在执行的调用，在调用完成的回应中检查慢调用
 }
 
 /*
@@ -480,46 +729,106 @@ impl JS {
         if ptr.is_null() {
             None
         } else {
-            unsafe {
+            let is_init = with_init_metrics(&name, &collection, || unsafe {
                 if dukc_heap_init(ptr, js_reply_callback) == 0 {
                     dukc_vm_destroy(ptr);
-                    return None;
+                    return false;
                 }
                 dukc_vm_run(ptr, js_reply_callback);
                 dukc_pop(ptr); //在初始化时需要弹出执行的结果
-            }
-            let id = create_js_task_queue(JS_ASYNC_MSG_QUEUE_PRIORITY, true); //为指定虚拟机创建对应的消息队列
-            //初始化时锁住虚拟机消息队列
-            if !lock_js_task_queue(id) {
-                panic!("!!!> New Vm Error, lock async callback queue failed");
-            }
-            let arc = Arc::new(JS {
-                vm: ptr as usize,
-                tasks: Arc::new(AtomicIsize::new(0)),
-                queue: JSMsgQueue {
-                    id: Arc::new(AtomicIsize::new(id)),
-                    size: Arc::new(AtomicUsize::new(0)),
-                },
-                auth: auth.clone(),
-                objs: NativeObjs::new(),
-                objs_ref: Arc::new(RefCell::new(HashMap::new())),
-                ret: Arc::new(RefCell::new(None)),
-                id: vm_id,
-                name,
-                last_heap_size: Arc::new(AtomicIsize::new(0)),
-                rng: Arc::new(RefCell::new(SmallRng::from_entropy())),
-                collection,
-                last_time: Arc::new(AtomicUsize::new(now_utc())),
-                wait_throw: Arc::new(AtomicBool::new(false)),
-                catcher: Arc::new(AtomicI32::new(-1)),
+                true
             });
-            unsafe {
-                let handler = Arc::into_raw(arc.clone()) as *const c_void_ptr;
-                dukc_bind_vm(ptr, handler);
-                Arc::from_raw(handler); //保证被clone的js的释放
+            if !is_init {
+                return None;
             }
-            Some(arc)
+            Some(JS::with_heap(ptr, vm_id, name, auth, collection))
+        }
+    }
+
+    //从指定的模板虚拟机复制一个虚拟机，复制模板虚拟机的堆状态，不需要重新加载字节码，模板虚拟机在复制时必须空闲
+    //本地对象由模板虚拟机独占，无法复制，所以模板虚拟机已有本地对象时不允许复制
+    #[cfg(feature = "vm-template")]
+    pub fn clone_from(template: &JS,
+                      vm_id: usize,
+                      name: Atom,
+                      auth: Arc<NativeObjsAuth>,
+                      collection: Option<(Arc<AtomicBool>, Arc<VMFactory>)>) -> Option<Arc<Self>> {
+        if !template.is_ran() {
+            //模板虚拟机正在运行，则忽略
+            return None;
+        }
+
+        if template.has_native_objects() {
+            //模板虚拟机已有本地对象，则忽略
+            warn!("!!!> Vm Clone Error, template has native objects, template: {:?}", template);
+            return None;
+        }
+
+        let ptr: *const c_void_ptr;
+        ptr = with_init_metrics(&name, &collection, || unsafe { dukc_vm_clone_heap(template.vm as *const c_void_ptr, js_reply_callback) });
+        if ptr.is_null() {
+            None
+        } else {
+            Some(JS::with_heap(ptr, vm_id, name, auth, collection))
+        }
+    }
+
+    //未启用vm-template时，dukc不提供堆复制，总是复制失败
+    #[cfg(not(feature = "vm-template"))]
+    pub fn clone_from(_template: &JS,
+                      _vm_id: usize,
+                      _name: Atom,
+                      _auth: Arc<NativeObjsAuth>,
+                      _collection: Option<(Arc<AtomicBool>, Arc<VMFactory>)>) -> Option<Arc<Self>> {
+        None
+    }
+
+    //使用已初始化的虚拟机堆构建虚拟机
+    fn with_heap(ptr: *const c_void_ptr,
+                 vm_id: usize,
+                 name: Atom,
+                 auth: Arc<NativeObjsAuth>,
+                 collection: Option<(Arc<AtomicBool>, Arc<VMFactory>)>) -> Arc<Self> {
+        let id = create_js_task_queue(JS_ASYNC_MSG_QUEUE_PRIORITY, true); //为指定虚拟机创建对应的消息队列
+        //初始化时锁住虚拟机消息队列
+        if !lock_js_task_queue(id) {
+            panic!("!!!> New Vm Error, lock async callback queue failed");
+        }
+        let arc = Arc::new(JS {
+            vm: ptr as usize,
+            tasks: Arc::new(AtomicIsize::new(0)),
+            queue: JSMsgQueue {
+                id: Arc::new(AtomicIsize::new(id)),
+                size: Arc::new(AtomicUsize::new(0)),
+            },
+            auth: auth.clone(),
+            objs: NativeObjs::new(),
+            objs_ref: Arc::new(RefCell::new(HashMap::new())),
+            ret: Arc::new(RefCell::new(None)),
+            id: vm_id,
+            name,
+            last_heap_size: Arc::new(AtomicIsize::new(0)),
+            collection,
+            last_time: Arc::new(AtomicUsize::new(now_utc())),
+            wait_throw: Arc::new(AtomicBool::new(false)),
+            catcher: Arc::new(AtomicI32::new(-1)),
+            heap_limit: Arc::new(AtomicUsize::new(0)),
+            reset_heap_sizes: Arc::new(RefCell::new(VecDeque::new())),
+            task_infos: Arc::new(RefCell::new(VecDeque::with_capacity(MAX_TASK_INFO_COUNT))),
+            reused_count: Arc::new(AtomicUsize::new(0)),
+            error_count: Arc::new(AtomicUsize::new(0)),
+            call_options: Arc::new(RefCell::new(None)),
+            session: Arc::new(RefCell::new(None)),
+            block_start: Arc::new(RefCell::new(None)),
+            source: Arc::new(RefCell::new(None)),
+            slow_start: Arc::new(RefCell::new(None)),
+        });
+        unsafe {
+            let handler = Arc::into_raw(arc.clone()) as *const c_void_ptr;
+            dukc_bind_vm(ptr, handler);
+            Arc::from_raw(handler); //保证被clone的js的释放
         }
+        arc
     }
 
     //从指针构建指定虚拟机
@@ -531,6 +840,9 @@ impl JS {
     pub fn callback(js: Arc<JS>, task_type: TaskType, callback: u32,
                 args: Box<FnOnce(Arc<JS>) -> usize>, timeout: Option<u32>, info: Atom) -> Option<isize> {
         let js_copy = js.clone();
+        let task_info = info.clone();
+        let delay = Duration::from_millis(timeout.unwrap_or(0) as u64);
+        let queue_time = Instant::now();
         let func = Box::new(move |_lock| {
             let vm: *const c_void_ptr;
             //不需要改变虚拟机状态，以保证当前虚拟机可以线程安全的执行回调函数
@@ -543,7 +855,9 @@ impl JS {
                 dukc_remove_callback(vm, callback); //移除虚拟机注册的指定回调函数
             }
 
-            //将回调函数的参数压栈，并执行回调函数
+            //将回调函数的参数压栈，并执行回调函数，延迟异步回调的等待时长不包括延迟时长
+            let queue_wait = queue_time.elapsed().checked_sub(delay).unwrap_or(Duration::new(0, 0));
+            start_slow(&js_copy, SlowKind::Callback, || format!("callback {}", callback), &task_info, queue_wait);
             let args_len = (args)(js_copy.clone());
             unsafe { dukc_call(vm, args_len as u8, js_reply_callback); }
         });
@@ -561,6 +875,8 @@ impl JS {
     //向指定虚拟机的消息队列中推送消息，由指定的回调函数处理，处理后默认不移除回调函数
     pub fn push(js: Arc<JS>, task_type: TaskType, callback: u32, args: Box<FnOnce(Arc<JS>) -> usize>, info: Atom) -> Option<isize> {
         let js_copy = js.clone();
+        let task_info = info.clone();
+        let queue_time = Instant::now();
         let func = Box::new(move |_lock| {
             let vm: *const c_void_ptr;
             //不需要改变虚拟机状态，以保证当前虚拟机可以线程安全的执行回调函数
@@ -573,6 +889,7 @@ impl JS {
             }
 
             //将回调函数的参数压栈，并执行回调函数
+            start_slow(&js_copy, SlowKind::Callback, || format!("push {}", callback), &task_info, queue_time.elapsed());
             let args_len = (args)(js_copy.clone());
             unsafe { dukc_call(vm, args_len as u8, js_reply_callback); }
         });
@@ -603,6 +920,16 @@ impl JS {
         cast_js_task(task_type, 0, Some(js.get_queue()), func, info)
     }
 
+    //获取虚拟机id
+    pub fn get_id(&self) -> usize {
+        self.id
+    }
+
+    //获取虚拟机名
+    pub fn get_name(&self) -> Atom {
+        self.name.clone()
+    }
+
     //获取内部虚拟机
     pub unsafe fn get_vm(&self) -> *const c_void_ptr {
         self.vm as *const c_void_ptr
@@ -627,6 +954,111 @@ impl JS {
         }
     }
 
+    //获取虚拟机堆限制
+    pub fn heap_limit(&self) -> usize {
+        self.heap_limit.load(Ordering::Relaxed)
+    }
+
+    //设置虚拟机堆限制，分配内存后的堆大小超过限制，则分配失败，并在js中抛出RangeError，0表示无限制
+    pub fn set_heap_limit(&self, limit: usize) {
+        self.heap_limit.store(limit, Ordering::SeqCst);
+        unsafe { dukc_vm_set_heap_limit(self.vm as *const c_void_ptr, limit); }
+    }
+
+    //判断虚拟机是否等待丢弃
+    pub fn is_wait_throw(&self) -> bool {
+        self.wait_throw.load(Ordering::Relaxed)
+    }
+
+    //获取虚拟机已复用次数
+    pub fn reused_count(&self) -> usize {
+        self.reused_count.load(Ordering::Relaxed)
+    }
+
+    //获取虚拟机运行异常次数
+    pub fn error_count(&self) -> usize {
+        self.error_count.load(Ordering::Relaxed)
+    }
+
+    //获取虚拟机当前任务的调用选项
+    pub fn call_options(&self) -> Option<CallOptions> {
+        self.call_options.borrow().clone()
+    }
+
+    //设置虚拟机当前任务的调用选项
+    pub fn set_call_options(&self, options: Option<CallOptions>) {
+        *self.call_options.borrow_mut() = options;
+    }
+
+    //设置虚拟机当前任务所属源的同步任务队列
+    pub(crate) fn set_source(&self, source: Option<Arc<SourceQueue>>) {
+        *self.source.borrow_mut() = source;
+    }
+
+    //取出虚拟机当前任务所属源的同步任务队列
+    pub(crate) fn take_source(&self) -> Option<Arc<SourceQueue>> {
+        self.source.borrow_mut().take()
+    }
+
+    //记录虚拟机正在执行的调用
+    pub(crate) fn set_slow_start(&self, slow: SlowStart) {
+        *self.slow_start.borrow_mut() = Some(slow);
+    }
+
+    //取出虚拟机正在执行的调用
+    pub(crate) fn take_slow_start(&self) -> Option<SlowStart> {
+        self.slow_start.borrow_mut().take()
+    }
+
+    //获取虚拟机绑定的会话
+    pub fn session(&self) -> Option<usize> {
+        *self.session.borrow()
+    }
+
+    //设置虚拟机绑定的会话，绑定会话的虚拟机在完成任务后不会重置全局环境
+    pub fn set_session(&self, session: Option<usize>) {
+        *self.session.borrow_mut() = session;
+    }
+
+    //设置虚拟机当前同步阻塞调用的本地函数hash，并记录开始时间
+    pub(crate) fn set_block_start(&self, hash: u32) {
+        *self.block_start.borrow_mut() = Some((hash, Instant::now()));
+    }
+
+    //取出虚拟机当前同步阻塞调用的本地函数hash和开始时间
+    pub(crate) fn take_block_start(&self) -> Option<(u32, Instant)> {
+        self.block_start.borrow_mut().take()
+    }
+
+    //记录虚拟机重置全局环境后的堆大小，最多保留指定数量，返回已记录的堆大小列表，从旧到新排列
+    pub fn record_reset_heap_size(&self, size: usize, count: usize) -> Vec<usize> {
+        let mut sizes = self.reset_heap_sizes.borrow_mut();
+        sizes.push_back(size);
+        while sizes.len() > count {
+            sizes.pop_front();
+        }
+        sizes.iter().cloned().collect()
+    }
+
+    //清空虚拟机记录的重置全局环境后的堆大小
+    pub fn clear_reset_heap_sizes(&self) {
+        self.reset_heap_sizes.borrow_mut().clear();
+    }
+
+    //记录虚拟机执行的任务信息，最多保留最近的8个
+    pub fn push_task_info(&self, info: Atom) {
+        let mut infos = self.task_infos.borrow_mut();
+        if infos.len() >= MAX_TASK_INFO_COUNT {
+            infos.pop_front();
+        }
+        infos.push_back(info);
+    }
+
+    //获取虚拟机最近执行的任务信息列表，从旧到新排列
+    pub fn task_infos(&self) -> Vec<Atom> {
+        self.task_infos.borrow().iter().cloned().collect()
+    }
+
     //获取虚拟机上次运行时间
     pub fn last_time(&self) -> usize {
         self.last_time.load(Ordering::Relaxed)
@@ -722,6 +1154,22 @@ impl JS {
         }
     }
 
+    //遍历当前虚拟机堆中的所有对象，遍历函数的参数分别为对象id、对象类型、对象大小和对象引用的对象id列表，虚拟机必须空闲
+    pub fn walk_heap(&self, func: &mut FnMut(u64, u8, usize, &[u64])) -> bool {
+        unsafe {
+            let status = dukc_vm_status_switch(self.vm as *const c_void_ptr, JSStatus::NoTask as i8, JSStatus::SingleTask as i8);
+            if status == JSStatus::SingleTask as i8 {
+                //当前虚拟机状态错误，无法遍历
+                false
+            } else {
+                let mut context: &mut FnMut(u64, u8, usize, &[u64]) = func;
+                let result = dukc_vm_heap_walk(self.vm as *const c_void_ptr, &mut context as *mut &mut FnMut(u64, u8, usize, &[u64]) as *mut c_void_ptr, heap_walk_callback) != 0;
+                dukc_vm_status_switch(self.vm as *const c_void_ptr, JSStatus::SingleTask as i8, JSStatus::NoTask as i8);
+                result
+            }
+        }
+    }
+
     //判断虚拟机是否绑定了同步任务队列
     pub fn exist_tasks(&self) -> bool {
         self.tasks.load(Ordering::SeqCst) != 0
@@ -772,6 +1220,11 @@ impl JS {
         self.objs_ref.clone()
     }
 
+    //判断虚拟机是否有本地对象或本地对象引用
+    pub fn has_native_objects(&self) -> bool {
+        !self.objs.0.borrow().is_empty() || !self.objs_ref.borrow().is_empty()
+    }
+
     //获取虚拟机最近执行结果
     pub fn get_ret(&self) -> Option<String> {
         if self.ret.borrow().is_none() {
@@ -802,6 +1255,17 @@ impl JS {
         unsafe { dukc_vm_status_check(self.vm as *const c_void_ptr, JSStatus::WaitCallBack as i8) > 0 }
     }
 
+    //获取js虚拟机当前状态，不匹配任何运行状态则表示正在destroy
+    pub fn status(&self) -> JSStatus {
+        for status in &[JSStatus::NoTask, JSStatus::SingleTask, JSStatus::MultiTask, JSStatus::WaitBlock, JSStatus::WaitCallBack] {
+            if unsafe { dukc_vm_status_check(self.vm as *const c_void_ptr, *status as i8) > 0 } {
+                return *status;
+            }
+        }
+
+        JSStatus::Destroy
+    }
+
     //编译指定脚本
     pub fn compile(&self, file: String, script: String) -> Option<Vec<u8>> {
         let mut len = 0u32;
@@ -1090,6 +1554,14 @@ impl JS {
             "Array" => JSValueType::Array as u8,
             "ArrayBuffer" => JSValueType::ArrayBuffer as u8,
             "Uint8Array" => JSValueType::Uint8Array as u8,
+            "Int8Array" => JSValueType::Int8Array as u8,
+            "Int16Array" => JSValueType::Int16Array as u8,
+            "Uint16Array" => JSValueType::Uint16Array as u8,
+            "Int32Array" => JSValueType::Int32Array as u8,
+            "Uint32Array" => JSValueType::Uint32Array as u8,
+            "Float32Array" => JSValueType::Float32Array as u8,
+            "Float64Array" => JSValueType::Float64Array as u8,
+            "DataView" => JSValueType::DataView as u8,
             _ => JSValueType::Object as u8,
         };
         unsafe { ptr = dukc_new_type(self.vm as *const c_void_ptr, len as u8) }
@@ -1180,6 +1652,161 @@ impl JS {
         }
     }
 
+    //构建使用指定外部数据的ArrayBuffer，外部数据不会复制，共享的外部数据只读，外部数据在虚拟机回收ArrayBuffer后释放，数据长度超过4GB返回错误
+    pub fn new_external_array_buffer<B: ExternalBytes>(&self, bytes: B) -> Result<JSType, Error> {
+        let (ptr, length, handle, read_only) = external_buffer(bytes)?;
+        let value: u32;
+        unsafe { value = dukc_new_external_array_buffer(self.vm as *const c_void_ptr, ptr, length, handle, read_only) }
+        Ok(JSType {
+            type_id: JSValueType::ArrayBuffer as u8,
+            is_drop: false,
+            vm: self.vm,
+            value: value as usize,
+        })
+    }
+
+    //构建使用指定外部数据的Uint8Array，外部数据不会复制，共享的外部数据只读，外部数据在虚拟机回收Uint8Array后释放，数据长度超过4GB返回错误
+    pub fn new_external_uint8_array<B: ExternalBytes>(&self, bytes: B) -> Result<JSType, Error> {
+        let (ptr, length, handle, read_only) = external_buffer(bytes)?;
+        let value: u32;
+        unsafe { value = dukc_new_external_uint8_array(self.vm as *const c_void_ptr, ptr, length, handle, read_only) }
+        Ok(JSType {
+            type_id: JSValueType::Uint8Array as u8,
+            is_drop: false,
+            vm: self.vm,
+            value: value as usize,
+        })
+    }
+
+    //构建Int8Array
+    pub fn new_int8_array(&self, length: u32) -> JSType {
+        self.new_typed_array(JSValueType::Int8Array, length)
+    }
+
+    //构建Int16Array，长度为元素数量
+    pub fn new_int16_array(&self, length: u32) -> JSType {
+        self.new_typed_array(JSValueType::Int16Array, length)
+    }
+
+    //构建Uint16Array，长度为元素数量
+    pub fn new_uint16_array(&self, length: u32) -> JSType {
+        self.new_typed_array(JSValueType::Uint16Array, length)
+    }
+
+    //构建Int32Array，长度为元素数量
+    pub fn new_int32_array(&self, length: u32) -> JSType {
+        self.new_typed_array(JSValueType::Int32Array, length)
+    }
+
+    //构建Uint32Array，长度为元素数量
+    pub fn new_uint32_array(&self, length: u32) -> JSType {
+        self.new_typed_array(JSValueType::Uint32Array, length)
+    }
+
+    //构建Float32Array，长度为元素数量
+    pub fn new_float32_array(&self, length: u32) -> JSType {
+        self.new_typed_array(JSValueType::Float32Array, length)
+    }
+
+    //构建Float64Array，长度为元素数量
+    pub fn new_float64_array(&self, length: u32) -> JSType {
+        self.new_typed_array(JSValueType::Float64Array, length)
+    }
+
+    //在指定ArrayBuffer上构建Uint8Array视图，偏移单位为字节，长度为元素数量
+    pub fn new_uint8_array_view(&self, buffer: &JSType, offset: u32, length: u32) -> Result<JSType, Error> {
+        self.new_typed_array_view(JSValueType::Uint8Array, buffer, offset, length)
+    }
+
+    //在指定ArrayBuffer上构建Int8Array视图，偏移单位为字节，长度为元素数量
+    pub fn new_int8_array_view(&self, buffer: &JSType, offset: u32, length: u32) -> Result<JSType, Error> {
+        self.new_typed_array_view(JSValueType::Int8Array, buffer, offset, length)
+    }
+
+    //在指定ArrayBuffer上构建Int16Array视图，偏移单位为字节，长度为元素数量
+    pub fn new_int16_array_view(&self, buffer: &JSType, offset: u32, length: u32) -> Result<JSType, Error> {
+        self.new_typed_array_view(JSValueType::Int16Array, buffer, offset, length)
+    }
+
+    //在指定ArrayBuffer上构建Uint16Array视图，偏移单位为字节，长度为元素数量
+    pub fn new_uint16_array_view(&self, buffer: &JSType, offset: u32, length: u32) -> Result<JSType, Error> {
+        self.new_typed_array_view(JSValueType::Uint16Array, buffer, offset, length)
+    }
+
+    //在指定ArrayBuffer上构建Int32Array视图，偏移单位为字节，长度为元素数量
+    pub fn new_int32_array_view(&self, buffer: &JSType, offset: u32, length: u32) -> Result<JSType, Error> {
+        self.new_typed_array_view(JSValueType::Int32Array, buffer, offset, length)
+    }
+
+    //在指定ArrayBuffer上构建Uint32Array视图，偏移单位为字节，长度为元素数量
+    pub fn new_uint32_array_view(&self, buffer: &JSType, offset: u32, length: u32) -> Result<JSType, Error> {
+        self.new_typed_array_view(JSValueType::Uint32Array, buffer, offset, length)
+    }
+
+    //在指定ArrayBuffer上构建Float32Array视图，偏移单位为字节，长度为元素数量
+    pub fn new_float32_array_view(&self, buffer: &JSType, offset: u32, length: u32) -> Result<JSType, Error> {
+        self.new_typed_array_view(JSValueType::Float32Array, buffer, offset, length)
+    }
+
+    //在指定ArrayBuffer上构建Float64Array视图，偏移单位为字节，长度为元素数量
+    pub fn new_float64_array_view(&self, buffer: &JSType, offset: u32, length: u32) -> Result<JSType, Error> {
+        self.new_typed_array_view(JSValueType::Float64Array, buffer, offset, length)
+    }
+
+    //在指定ArrayBuffer上构建DataView，偏移和长度单位都为字节
+    pub fn new_data_view(&self, buffer: &JSType, offset: u32, length: u32) -> Result<JSType, Error> {
+        self.new_typed_array_view(JSValueType::DataView, buffer, offset, length)
+    }
+
+    //构建指定类型的类型数组，长度为元素数量
+    fn new_typed_array(&self, t: JSValueType, length: u32) -> JSType {
+        let type_id = t as u8;
+        let ptr: u32;
+        unsafe { ptr = dukc_new_typed_array(self.vm as *const c_void_ptr, type_id, length) }
+        JSType {
+            type_id,
+            is_drop: false,
+            vm: self.vm,
+            value: ptr as usize,
+        }
+    }
+
+    //在指定ArrayBuffer上构建指定类型的视图，缓冲区无效、偏移未按元素大小对齐或视图超出缓冲区返回错误
+    fn new_typed_array_view(&self, t: JSValueType, buffer: &JSType, offset: u32, length: u32) -> Result<JSType, Error> {
+        if (self.vm != buffer.vm) || !buffer.is_array_buffer() {
+            //如果缓冲区不是在指定虚拟机上创建的ArrayBuffer，则忽略
+            return Err(Error::new(ErrorKind::InvalidInput, "not ArrayBuffer of current vm"));
+        }
+
+        let size = match t {
+            JSValueType::Int16Array | JSValueType::Uint16Array => 2,
+            JSValueType::Int32Array | JSValueType::Uint32Array | JSValueType::Float32Array => 4,
+            JSValueType::Float64Array => 8,
+            _ => 1,
+        };
+        if offset as usize % size != 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("view offset unaligned, offset: {}, size: {}", offset, size)));
+        }
+
+        let buffer_len = unsafe { dukc_get_buffer_length(self.vm as *const c_void_ptr, buffer.value as u32) as usize };
+        match (length as usize).checked_mul(size).and_then(|len| len.checked_add(offset as usize)) {
+            Some(end) if end <= buffer_len => (),
+            _ => {
+                return Err(Error::new(ErrorKind::InvalidInput, format!("view out of bounds, offset: {}, length: {}, size: {}, buffer length: {}", offset, length, size, buffer_len)));
+            },
+        }
+
+        let type_id = t as u8;
+        let ptr: u32;
+        unsafe { ptr = dukc_new_typed_array_view(self.vm as *const c_void_ptr, type_id, buffer.value as u32, offset, length) }
+        Ok(JSType {
+            type_id,
+            is_drop: false,
+            vm: self.vm,
+            value: ptr as usize,
+        })
+    }
+
     //构建NativeObject
     pub fn new_native_object(&self, instance: usize) -> JSType {
         let ptr: u32;
@@ -1250,6 +1877,26 @@ impl JS {
         }
     }
 
+    //结束当前任务，不调用任何函数，与调用完成一样在回应中处理消息队列并整理虚拟机，用于在任务中已同步完成所有调用的批量调用
+    pub fn finish(js: Arc<JS>) {
+        let vm = js.vm as *const c_void_ptr;
+        unsafe {
+            let status = dukc_vm_status_switch(vm, JSStatus::NoTask as i8, JSStatus::SingleTask as i8);
+            if status == JSStatus::SingleTask as i8 {
+                //当前虚拟机正在destroy或有其它任务
+                println!("invalid vm status with finish");
+                return;
+            }
+
+            //增加当前虚拟机消息队列长度，并以undefined作为任务的执行结果，执行结果会在回应中移除
+            js.add_queue_len();
+            dukc_new_undefined(vm);
+            let handler = Arc::into_raw(js) as *const c_void_ptr;
+            js_reply_callback(handler, 0, ptr::null());
+            JS::from_raw(handler); //回应不改变引用计数，所以需要释放当前引用
+        }
+    }
+
     //设置指定全局变量的值，需要传递值的所有权，所以只读的值不允许设置为全局变量
     pub fn set_global_var(&self, key: String, value: JSType) -> bool {
         unsafe {
@@ -1294,6 +1941,28 @@ impl JS {
         }
     }
 
+    //调用指定函数，并返回，调用异常则返回异常信息
+    pub fn try_invoke(&self, len: usize) -> Result<JSType, String> {
+        let vm = self.vm as *const c_void_ptr;
+        let top = unsafe { dukc_top(vm) } - len as i32 - 1; //调用前被调用函数下的栈顶
+        let result = self.invoke(len);
+        if !result.is_none() {
+            return Ok(result);
+        }
+
+        unsafe {
+            if dukc_top(vm) > top {
+                //调用异常保留在栈顶，则获取异常信息后移除
+                let reason = self.stack_top_string();
+                dukc_pop(vm);
+                if let Some(reason) = reason {
+                    return Err(reason);
+                }
+            }
+        }
+        Err("invoke function failed".to_string())
+    }
+
     //执行指定脚本，返回值无法绑定全局变量，为了使用安全返回只读值
     pub fn eval(&self, script: String) -> AJSType {
         let ptr: i32;
@@ -1359,75 +2028,19 @@ impl JS {
         }
     }
 
-    //检查当前虚拟机是否需要启动全局虚拟机整理、返回丢弃0、释放1或忽略2
+    //根据虚拟机工厂的复用策略，检查当前虚拟机是否需要启动全局虚拟机整理、返回丢弃0、释放1或忽略2
     pub fn check_reuse(&self) -> usize {
         if let Some((_, factory)) = &self.collection {
-            let max_heap_size = factory.max_heap_size();
-            if (max_heap_size > 0) && self.heap_size() >= max_heap_size {
-                //已达虚拟机最大堆限制，则释放
-                1
-            } else {
-                //未达虚拟机最大堆限制，则忽略
-                2
+            match factory.reuse_policy().decide(&VmStats::new(self, factory)) {
+                ReuseDecision::Throw | ReuseDecision::Recreate => 0,
+                ReuseDecision::Free => 1,
+                ReuseDecision::Reuse => 2,
             }
         } else {
             //未设置回收器，则会丢弃当前虚拟机
             0
         }
     }
-
-    //判断当前虚拟机是否需要被丢弃
-    pub fn is_throw(&self) -> usize {
-        if let Some((_, factory)) = &self.collection {
-            //设置了回收器，则有机率丢弃
-            let heap_limit = factory.heap_size();
-            let heap_size = self.heap_size();
-            let mut vm_heap_size = vm_alloced_size();
-            let max_heap_limit = get_max_alloced_limit();
-
-            if vm_alloced_size() < 0 {
-                //如果当前堆最大大小小于0，则重置为0
-                VM_ALLOCATED.store(0, Ordering::Relaxed);
-                vm_heap_size = 0;
-            }
-
-            let n: f64;
-            if heap_size >= heap_limit {
-                //当前堆大小超过堆限制，则堆大小余量比例为0
-                n = 0.0;
-            } else {
-                //当前堆大小未超过堆限制，则余量为(1 - 当前堆限制比例)
-                n = 1.0 - heap_size as f64 / heap_limit as f64;
-            }
-
-            let s: f64;
-            if is_alloced_limit() {
-                //当前所有虚拟机内存占用超过最大堆限制，则内存占用比例为1
-                s = 1.0;
-            } else {
-                //当前所有虚拟机内存占用未超过最大堆限制
-                s = vm_heap_size as f64 / max_heap_limit as f64;
-            }
-
-            //求虚拟机丢弃机率
-            let r = s.powf(8.0).powf(n);
-            if self.rng.borrow_mut().gen_bool(r) {
-                if is_alloced_limit() {
-                    //丢弃当前虚拟机
-                    0
-                } else {
-                    //丢弃当前虚拟机，且创建新的虚拟机
-                    1
-                }
-            } else {
-                //复用
-                2
-            }
-        } else {
-            //没有设置回收器，则一定会被丢弃
-            0
-        }
-    }
 }
 
 /*
@@ -1445,6 +2058,14 @@ pub enum JSValueType {
     Array,
     ArrayBuffer,
     Uint8Array,
+    Int8Array,
+    Int16Array,
+    Uint16Array,
+    Int32Array,
+    Uint32Array,
+    Float32Array,
+    Float64Array,
+    DataView,
 }
 
 /*
@@ -1590,6 +2211,51 @@ impl JSType {
         }
     }
 
+    //判断是否是Int8Array
+	pub fn is_int8_array(&self) -> bool {
+        self.type_id == JSValueType::Int8Array as u8
+    }
+
+    //判断是否是Int16Array
+	pub fn is_int16_array(&self) -> bool {
+        self.type_id == JSValueType::Int16Array as u8
+    }
+
+    //判断是否是Uint16Array
+	pub fn is_uint16_array(&self) -> bool {
+        self.type_id == JSValueType::Uint16Array as u8
+    }
+
+    //判断是否是Int32Array
+	pub fn is_int32_array(&self) -> bool {
+        self.type_id == JSValueType::Int32Array as u8
+    }
+
+    //判断是否是Uint32Array
+	pub fn is_uint32_array(&self) -> bool {
+        self.type_id == JSValueType::Uint32Array as u8
+    }
+
+    //判断是否是Float32Array
+	pub fn is_float32_array(&self) -> bool {
+        self.type_id == JSValueType::Float32Array as u8
+    }
+
+    //判断是否是Float64Array
+	pub fn is_float64_array(&self) -> bool {
+        self.type_id == JSValueType::Float64Array as u8
+    }
+
+    //判断是否是DataView
+	pub fn is_data_view(&self) -> bool {
+        self.type_id == JSValueType::DataView as u8
+    }
+
+    //判断是否是任意类型数组
+	pub fn is_typed_array(&self) -> bool {
+        self.type_id >= JSValueType::Uint8Array as u8 && self.type_id <= JSValueType::Float64Array as u8
+    }
+
     //判断是否是NativeObject
 	pub fn is_native_object(&self) -> bool {
         if self.type_id == JSValueType::NativeObject as u8 {
@@ -1724,6 +2390,153 @@ impl JSType {
         from_raw_parts_mut(buffer as *mut u8, length)
     }
 
+    //获取Int8Array的引用，类型不匹配或数据区无效返回错误
+    pub fn to_i8_slice(&self) -> Result<&[i8], Error> {
+        if !self.is_int8_array() {
+            return Err(Error::new(ErrorKind::InvalidInput, "not Int8Array"));
+        }
+        unsafe { self.to_typed_slice::<i8>() }
+    }
+
+    //获取Int16Array的引用，类型不匹配或数据区无效返回错误
+    pub fn to_i16_slice(&self) -> Result<&[i16], Error> {
+        if !self.is_int16_array() {
+            return Err(Error::new(ErrorKind::InvalidInput, "not Int16Array"));
+        }
+        unsafe { self.to_typed_slice::<i16>() }
+    }
+
+    //获取Uint16Array的引用，类型不匹配或数据区无效返回错误
+    pub fn to_u16_slice(&self) -> Result<&[u16], Error> {
+        if !self.is_uint16_array() {
+            return Err(Error::new(ErrorKind::InvalidInput, "not Uint16Array"));
+        }
+        unsafe { self.to_typed_slice::<u16>() }
+    }
+
+    //获取Int32Array的引用，类型不匹配或数据区无效返回错误
+    pub fn to_i32_slice(&self) -> Result<&[i32], Error> {
+        if !self.is_int32_array() {
+            return Err(Error::new(ErrorKind::InvalidInput, "not Int32Array"));
+        }
+        unsafe { self.to_typed_slice::<i32>() }
+    }
+
+    //获取Uint32Array的引用，类型不匹配或数据区无效返回错误
+    pub fn to_u32_slice(&self) -> Result<&[u32], Error> {
+        if !self.is_uint32_array() {
+            return Err(Error::new(ErrorKind::InvalidInput, "not Uint32Array"));
+        }
+        unsafe { self.to_typed_slice::<u32>() }
+    }
+
+    //获取Float32Array的引用，类型不匹配或数据区无效返回错误
+    pub fn to_f32_slice(&self) -> Result<&[f32], Error> {
+        if !self.is_float32_array() {
+            return Err(Error::new(ErrorKind::InvalidInput, "not Float32Array"));
+        }
+        unsafe { self.to_typed_slice::<f32>() }
+    }
+
+    //获取Float64Array的引用，类型不匹配或数据区无效返回错误
+    pub fn to_f64_slice(&self) -> Result<&[f64], Error> {
+        if !self.is_float64_array() {
+            return Err(Error::new(ErrorKind::InvalidInput, "not Float64Array"));
+        }
+        unsafe { self.to_typed_slice::<f64>() }
+    }
+
+    //获取Int8Array的可写引用，类型不匹配或数据区无效返回错误
+    pub unsafe fn to_i8_slice_mut(&mut self) -> Result<&mut [i8], Error> {
+        if !self.is_int8_array() {
+            return Err(Error::new(ErrorKind::InvalidInput, "not Int8Array"));
+        }
+        self.to_typed_slice_mut::<i8>()
+    }
+
+    //获取Int16Array的可写引用，类型不匹配或数据区无效返回错误
+    pub unsafe fn to_i16_slice_mut(&mut self) -> Result<&mut [i16], Error> {
+        if !self.is_int16_array() {
+            return Err(Error::new(ErrorKind::InvalidInput, "not Int16Array"));
+        }
+        self.to_typed_slice_mut::<i16>()
+    }
+
+    //获取Uint16Array的可写引用，类型不匹配或数据区无效返回错误
+    pub unsafe fn to_u16_slice_mut(&mut self) -> Result<&mut [u16], Error> {
+        if !self.is_uint16_array() {
+            return Err(Error::new(ErrorKind::InvalidInput, "not Uint16Array"));
+        }
+        self.to_typed_slice_mut::<u16>()
+    }
+
+    //获取Int32Array的可写引用，类型不匹配或数据区无效返回错误
+    pub unsafe fn to_i32_slice_mut(&mut self) -> Result<&mut [i32], Error> {
+        if !self.is_int32_array() {
+            return Err(Error::new(ErrorKind::InvalidInput, "not Int32Array"));
+        }
+        self.to_typed_slice_mut::<i32>()
+    }
+
+    //获取Uint32Array的可写引用，类型不匹配或数据区无效返回错误
+    pub unsafe fn to_u32_slice_mut(&mut self) -> Result<&mut [u32], Error> {
+        if !self.is_uint32_array() {
+            return Err(Error::new(ErrorKind::InvalidInput, "not Uint32Array"));
+        }
+        self.to_typed_slice_mut::<u32>()
+    }
+
+    //获取Float32Array的可写引用，类型不匹配或数据区无效返回错误
+    pub unsafe fn to_f32_slice_mut(&mut self) -> Result<&mut [f32], Error> {
+        if !self.is_float32_array() {
+            return Err(Error::new(ErrorKind::InvalidInput, "not Float32Array"));
+        }
+        self.to_typed_slice_mut::<f32>()
+    }
+
+    //获取Float64Array的可写引用，类型不匹配或数据区无效返回错误
+    pub unsafe fn to_f64_slice_mut(&mut self) -> Result<&mut [f64], Error> {
+        if !self.is_float64_array() {
+            return Err(Error::new(ErrorKind::InvalidInput, "not Float64Array"));
+        }
+        self.to_typed_slice_mut::<f64>()
+    }
+
+    //将类型数组的数据区转换为指定元素类型的引用，视图的数据区已包括视图偏移，长度为视图的字节长度
+    unsafe fn to_typed_slice<T>(&self) -> Result<&[T], Error> {
+        let (buffer, length) = self.typed_data::<T>()?;
+        if buffer.is_null() {
+            return Ok(&[]);
+        }
+        Ok(from_raw_parts(buffer as *const T, length))
+    }
+
+    //将类型数组的数据区转换为指定元素类型的可写引用
+    unsafe fn to_typed_slice_mut<T>(&mut self) -> Result<&mut [T], Error> {
+        let (buffer, length) = self.typed_data::<T>()?;
+        if buffer.is_null() {
+            return Ok(&mut []);
+        }
+        Ok(from_raw_parts_mut(buffer as *mut T, length))
+    }
+
+    //获取类型数组的数据区和元素数量，数据区未按元素类型对齐或字节长度不是元素大小的整数倍返回错误
+    unsafe fn typed_data<T>(&self) -> Result<(*const c_void_ptr, usize), Error> {
+        let length = dukc_get_buffer_length(self.vm as *const c_void_ptr, self.value as u32) as usize;
+        let buffer = dukc_get_buffer(self.vm as *const c_void_ptr, self.value as u32);
+        if buffer.is_null() {
+            return Ok((buffer, 0));
+        }
+
+        if (buffer as usize) % align_of::<T>() != 0 {
+            return Err(Error::new(ErrorKind::InvalidData, format!("typed array unaligned, ptr: {:?}, align: {}", buffer, align_of::<T>())));
+        }
+        if length % size_of::<T>() != 0 {
+            return Err(Error::new(ErrorKind::InvalidData, format!("typed array length invalid, length: {}, size: {}", length, size_of::<T>())));
+        }
+        Ok((buffer, length / size_of::<T>()))
+    }
+
     //获取指定Buffer的复制
 	pub fn into_vec(&self) -> Vec<u8> {
         self.to_bytes().to_vec()
@@ -1787,68 +2600,294 @@ impl JSBuffer {
         self.len
     }
 
+    //判断从指定位置开始的指定长度是否在buffer范围内
+    pub fn in_range(&self, offset: usize, size: usize) -> bool {
+        match offset.checked_add(size) {
+            Some(last) => last <= self.len,
+            None => false,
+        }
+    }
+
+    //检查从指定位置开始的指定长度是否在buffer范围内，越界返回错误
+    fn check_range(&self, offset: usize, size: usize) -> Result<usize, Error> {
+        if self.in_range(offset, size) {
+            Ok(offset + size)
+        } else {
+            Err(Error::new(ErrorKind::UnexpectedEof, format!("js buffer access out of range, offset: {}, size: {}, len: {}", offset, size, self.len)))
+        }
+    }
+
+    //在指定位置安全的读指定类型的原始值
+    fn try_read_raw<T: Copy>(&self, offset: usize) -> Result<T, Error> {
+        self.check_range(offset, size_of::<T>())?;
+        unsafe { Ok(read_unaligned(self.buffer.wrapping_offset(offset as isize) as *const T)) }
+    }
+
+    //在指定位置安全的写指定类型的原始值，返回写入后的位置
+    fn try_write_raw<T: Copy>(&mut self, offset: usize, v: T) -> Result<usize, Error> {
+        let last = self.check_range(offset, size_of::<T>())?;
+        unsafe { write_unaligned(self.buffer.wrapping_offset(offset as isize) as *mut T, v); }
+        Ok(last)
+    }
+
+    //在指定位置安全的读小端i8
+    pub fn try_read_i8(&self, offset: usize) -> Result<i8, Error> {
+        self.try_read_raw::<i8>(offset)
+    }
+
+    //在指定位置安全的读小端i16
+    pub fn try_read_i16(&self, offset: usize) -> Result<i16, Error> {
+        self.try_read_raw::<i16>(offset).map(i16::from_le)
+    }
+
+    //在指定位置安全的读小端i32
+    pub fn try_read_i32(&self, offset: usize) -> Result<i32, Error> {
+        self.try_read_raw::<i32>(offset).map(i32::from_le)
+    }
+
+    //在指定位置安全的读小端i64
+    pub fn try_read_i64(&self, offset: usize) -> Result<i64, Error> {
+        self.try_read_raw::<i64>(offset).map(i64::from_le)
+    }
+
+    //在指定位置安全的读小端u8
+    pub fn try_read_u8(&self, offset: usize) -> Result<u8, Error> {
+        self.try_read_raw::<u8>(offset)
+    }
+
+    //在指定位置安全的读小端u16
+    pub fn try_read_u16(&self, offset: usize) -> Result<u16, Error> {
+        self.try_read_raw::<u16>(offset).map(u16::from_le)
+    }
+
+    //在指定位置安全的读小端u32
+    pub fn try_read_u32(&self, offset: usize) -> Result<u32, Error> {
+        self.try_read_raw::<u32>(offset).map(u32::from_le)
+    }
+
+    //在指定位置安全的读小端u64
+    pub fn try_read_u64(&self, offset: usize) -> Result<u64, Error> {
+        self.try_read_raw::<u64>(offset).map(u64::from_le)
+    }
+
+    //在指定位置安全的读小端f32
+    pub fn try_read_f32(&self, offset: usize) -> Result<f32, Error> {
+        self.try_read_u32(offset).map(f32::from_bits)
+    }
+
+    //在指定位置安全的读小端f64
+    pub fn try_read_f64(&self, offset: usize) -> Result<f64, Error> {
+        self.try_read_u64(offset).map(f64::from_bits)
+    }
+
+    //在指定位置安全的读大端i16
+    pub fn try_read_i16_be(&self, offset: usize) -> Result<i16, Error> {
+        self.try_read_raw::<i16>(offset).map(i16::from_be)
+    }
+
+    //在指定位置安全的读大端i32
+    pub fn try_read_i32_be(&self, offset: usize) -> Result<i32, Error> {
+        self.try_read_raw::<i32>(offset).map(i32::from_be)
+    }
+
+    //在指定位置安全的读大端i64
+    pub fn try_read_i64_be(&self, offset: usize) -> Result<i64, Error> {
+        self.try_read_raw::<i64>(offset).map(i64::from_be)
+    }
+
+    //在指定位置安全的读大端u16
+    pub fn try_read_u16_be(&self, offset: usize) -> Result<u16, Error> {
+        self.try_read_raw::<u16>(offset).map(u16::from_be)
+    }
+
+    //在指定位置安全的读大端u32
+    pub fn try_read_u32_be(&self, offset: usize) -> Result<u32, Error> {
+        self.try_read_raw::<u32>(offset).map(u32::from_be)
+    }
+
+    //在指定位置安全的读大端u64
+    pub fn try_read_u64_be(&self, offset: usize) -> Result<u64, Error> {
+        self.try_read_raw::<u64>(offset).map(u64::from_be)
+    }
+
+    //在指定位置安全的读大端f32
+    pub fn try_read_f32_be(&self, offset: usize) -> Result<f32, Error> {
+        self.try_read_u32_be(offset).map(f32::from_bits)
+    }
+
+    //在指定位置安全的读大端f64
+    pub fn try_read_f64_be(&self, offset: usize) -> Result<f64, Error> {
+        self.try_read_u64_be(offset).map(f64::from_bits)
+    }
+
+    //在指定位置安全的读字节数组
+    pub fn try_read(&self, offset: usize, len: usize) -> Result<&[u8], Error> {
+        self.check_range(offset, len)?;
+        unsafe {
+            Ok(from_raw_parts(self.buffer.wrapping_offset(offset as isize) as *const u8, len))
+        }
+    }
+
+    //在指定位置安全的写小端i8，返回写入后的位置
+    pub fn try_write_i8(&mut self, offset: usize, v: i8) -> Result<usize, Error> {
+        self.try_write_raw(offset, v)
+    }
+
+    //在指定位置安全的写小端i16，返回写入后的位置
+    pub fn try_write_i16(&mut self, offset: usize, v: i16) -> Result<usize, Error> {
+        self.try_write_raw(offset, v.to_le())
+    }
+
+    //在指定位置安全的写小端i32，返回写入后的位置
+    pub fn try_write_i32(&mut self, offset: usize, v: i32) -> Result<usize, Error> {
+        self.try_write_raw(offset, v.to_le())
+    }
+
+    //在指定位置安全的写小端i64，返回写入后的位置
+    pub fn try_write_i64(&mut self, offset: usize, v: i64) -> Result<usize, Error> {
+        self.try_write_raw(offset, v.to_le())
+    }
+
+    //在指定位置安全的写小端u8，返回写入后的位置
+    pub fn try_write_u8(&mut self, offset: usize, v: u8) -> Result<usize, Error> {
+        self.try_write_raw(offset, v)
+    }
+
+    //在指定位置安全的写小端u16，返回写入后的位置
+    pub fn try_write_u16(&mut self, offset: usize, v: u16) -> Result<usize, Error> {
+        self.try_write_raw(offset, v.to_le())
+    }
+
+    //在指定位置安全的写小端u32，返回写入后的位置
+    pub fn try_write_u32(&mut self, offset: usize, v: u32) -> Result<usize, Error> {
+        self.try_write_raw(offset, v.to_le())
+    }
+
+    //在指定位置安全的写小端u64，返回写入后的位置
+    pub fn try_write_u64(&mut self, offset: usize, v: u64) -> Result<usize, Error> {
+        self.try_write_raw(offset, v.to_le())
+    }
+
+    //在指定位置安全的写小端f32，返回写入后的位置
+    pub fn try_write_f32(&mut self, offset: usize, v: f32) -> Result<usize, Error> {
+        self.try_write_u32(offset, v.to_bits())
+    }
+
+    //在指定位置安全的写小端f64，返回写入后的位置
+    pub fn try_write_f64(&mut self, offset: usize, v: f64) -> Result<usize, Error> {
+        self.try_write_u64(offset, v.to_bits())
+    }
+
+    //在指定位置安全的写大端i16，返回写入后的位置
+    pub fn try_write_i16_be(&mut self, offset: usize, v: i16) -> Result<usize, Error> {
+        self.try_write_raw(offset, v.to_be())
+    }
+
+    //在指定位置安全的写大端i32，返回写入后的位置
+    pub fn try_write_i32_be(&mut self, offset: usize, v: i32) -> Result<usize, Error> {
+        self.try_write_raw(offset, v.to_be())
+    }
+
+    //在指定位置安全的写大端i64，返回写入后的位置
+    pub fn try_write_i64_be(&mut self, offset: usize, v: i64) -> Result<usize, Error> {
+        self.try_write_raw(offset, v.to_be())
+    }
+
+    //在指定位置安全的写大端u16，返回写入后的位置
+    pub fn try_write_u16_be(&mut self, offset: usize, v: u16) -> Result<usize, Error> {
+        self.try_write_raw(offset, v.to_be())
+    }
+
+    //在指定位置安全的写大端u32，返回写入后的位置
+    pub fn try_write_u32_be(&mut self, offset: usize, v: u32) -> Result<usize, Error> {
+        self.try_write_raw(offset, v.to_be())
+    }
+
+    //在指定位置安全的写大端u64，返回写入后的位置
+    pub fn try_write_u64_be(&mut self, offset: usize, v: u64) -> Result<usize, Error> {
+        self.try_write_raw(offset, v.to_be())
+    }
+
+    //在指定位置安全的写大端f32，返回写入后的位置
+    pub fn try_write_f32_be(&mut self, offset: usize, v: f32) -> Result<usize, Error> {
+        self.try_write_u32_be(offset, v.to_bits())
+    }
+
+    //在指定位置安全的写大端f64，返回写入后的位置
+    pub fn try_write_f64_be(&mut self, offset: usize, v: f64) -> Result<usize, Error> {
+        self.try_write_u64_be(offset, v.to_bits())
+    }
+
+    //从指定位置安全的写字节数组，返回写入后的位置
+    pub fn try_write(&mut self, offset: usize, v: &[u8]) -> Result<usize, Error> {
+        let len = v.len();
+        let last = self.check_range(offset, len)?;
+        unsafe { memcpy(self.buffer.wrapping_offset(offset as isize), v.as_ptr() as *const c_void_ptr, len); }
+        Ok(last)
+    }
+
     //在指定位置读小端i8
     pub fn read_i8(&self, offset: usize) -> i8 {
-        if offset >= self.len {
+        if !self.in_range(offset, 1) {
             panic!("access out of range");
         }
-        unsafe { i8::from_le(*(self.buffer.wrapping_offset(offset as isize) as *mut i8)) }
+        unsafe { i8::from_le(read_unaligned(self.buffer.wrapping_offset(offset as isize) as *const i8)) }
     }
 
     //在指定位置读小端i16
     pub fn read_i16(&self, offset: usize) -> i16 {
-        if offset >= self.len {
+        if !self.in_range(offset, 2) {
             panic!("access out of range");
         }
-        unsafe { i16::from_le(*(self.buffer.wrapping_offset(offset as isize) as *mut i16)) }
+        unsafe { i16::from_le(read_unaligned(self.buffer.wrapping_offset(offset as isize) as *const i16)) }
     }
 
     //在指定位置读小端i32
     pub fn read_i32(&self, offset: usize) -> i32 {
-        if offset >= self.len {
+        if !self.in_range(offset, 4) {
             panic!("access out of range");
         }
-        unsafe { i32::from_le(*(self.buffer.wrapping_offset(offset as isize) as *mut i32)) }
+        unsafe { i32::from_le(read_unaligned(self.buffer.wrapping_offset(offset as isize) as *const i32)) }
     }
 
     //在指定位置读小端i64
     pub fn read_i64(&self, offset: usize) -> i64 {
-        if offset >= self.len {
+        if !self.in_range(offset, 8) {
             panic!("access out of range");
         }
-        unsafe { i64::from_le(*(self.buffer.wrapping_offset(offset as isize) as *mut i64)) }
+        unsafe { i64::from_le(read_unaligned(self.buffer.wrapping_offset(offset as isize) as *const i64)) }
     }
 
     //在指定位置读小端u8
     pub fn read_u8(&self, offset: usize) -> u8 {
-        if offset >= self.len {
+        if !self.in_range(offset, 1) {
             panic!("access out of range");
         }
-        unsafe { u8::from_le(*(self.buffer.wrapping_offset(offset as isize) as *mut u8)) }
+        unsafe { u8::from_le(read_unaligned(self.buffer.wrapping_offset(offset as isize) as *const u8)) }
     }
 
     //在指定位置读小端u16
     pub fn read_u16(&self, offset: usize) -> u16 {
-        if offset >= self.len {
+        if !self.in_range(offset, 2) {
             panic!("access out of range");
         }
-        unsafe { u16::from_le(*(self.buffer.wrapping_offset(offset as isize) as *mut u16)) }
+        unsafe { u16::from_le(read_unaligned(self.buffer.wrapping_offset(offset as isize) as *const u16)) }
     }
 
     //在指定位置读小端u32
     pub fn read_u32(&self, offset: usize) -> u32 {
-        if offset >= self.len {
+        if !self.in_range(offset, 4) {
             panic!("access out of range");
         }
-        unsafe { u32::from_le(*(self.buffer.wrapping_offset(offset as isize) as *mut u32)) }
+        unsafe { u32::from_le(read_unaligned(self.buffer.wrapping_offset(offset as isize) as *const u32)) }
     }
 
     //在指定位置读小端u64
     pub fn read_u64(&self, offset: usize) -> u64 {
-        if offset >= self.len {
+        if !self.in_range(offset, 8) {
             panic!("access out of range");
         }
-        unsafe { u64::from_le(*(self.buffer.wrapping_offset(offset as isize) as *mut u64)) }
+        unsafe { u64::from_le(read_unaligned(self.buffer.wrapping_offset(offset as isize) as *const u64)) }
     }
 
     //在指定位置读小端f32
@@ -1863,66 +2902,66 @@ impl JSBuffer {
 
     //在指定位置读大端i8
     pub fn read_i8_be(&self, offset: usize) -> i8 {
-        if offset >= self.len {
+        if !self.in_range(offset, 1) {
             panic!("access out of range");
         }
-        unsafe { i8::from_be(*(self.buffer.wrapping_offset(offset as isize) as *mut i8)) }
+        unsafe { i8::from_be(read_unaligned(self.buffer.wrapping_offset(offset as isize) as *const i8)) }
     }
 
     //在指定位置读大端i16
     pub fn read_i16_be(&self, offset: usize) -> i16 {
-        if offset >= self.len {
+        if !self.in_range(offset, 2) {
             panic!("access out of range");
         }
-        unsafe { i16::from_be(*(self.buffer.wrapping_offset(offset as isize) as *mut i16)) }
+        unsafe { i16::from_be(read_unaligned(self.buffer.wrapping_offset(offset as isize) as *const i16)) }
     }
 
     //在指定位置读大端i32
     pub fn read_i32_be(&self, offset: usize) -> i32 {
-        if offset >= self.len {
+        if !self.in_range(offset, 4) {
             panic!("access out of range");
         }
-        unsafe { i32::from_be(*(self.buffer.wrapping_offset(offset as isize) as *mut i32)) }
+        unsafe { i32::from_be(read_unaligned(self.buffer.wrapping_offset(offset as isize) as *const i32)) }
     }
 
     //在指定位置读大端i64
     pub fn read_i64_be(&self, offset: usize) -> i64 {
-        if offset >= self.len {
+        if !self.in_range(offset, 8) {
             panic!("access out of range");
         }
-        unsafe { i64::from_be(*(self.buffer.wrapping_offset(offset as isize) as *mut i64)) }
+        unsafe { i64::from_be(read_unaligned(self.buffer.wrapping_offset(offset as isize) as *const i64)) }
     }
 
     //在指定位置读大端u8
     pub fn read_u8_be(&self, offset: usize) -> u8 {
-        if offset >= self.len {
+        if !self.in_range(offset, 1) {
             panic!("access out of range");
         }
-        unsafe { u8::from_be(*(self.buffer.wrapping_offset(offset as isize) as *mut u8)) }
+        unsafe { u8::from_be(read_unaligned(self.buffer.wrapping_offset(offset as isize) as *const u8)) }
     }
 
     //在指定位置读大端u16
     pub fn read_u16_be(&self, offset: usize) -> u16 {
-        if offset >= self.len {
+        if !self.in_range(offset, 2) {
             panic!("access out of range");
         }
-        unsafe { u16::from_be(*(self.buffer.wrapping_offset(offset as isize) as *mut u16)) }
+        unsafe { u16::from_be(read_unaligned(self.buffer.wrapping_offset(offset as isize) as *const u16)) }
     }
 
     //在指定位置读大端u32
     pub fn read_u32_be(&self, offset: usize) -> u32 {
-        if offset >= self.len {
+        if !self.in_range(offset, 4) {
             panic!("access out of range");
         }
-        unsafe { u32::from_be(*(self.buffer.wrapping_offset(offset as isize) as *mut u32)) }
+        unsafe { u32::from_be(read_unaligned(self.buffer.wrapping_offset(offset as isize) as *const u32)) }
     }
 
     //在指定位置读大端u64
     pub fn read_u64_be(&self, offset: usize) -> u64 {
-        if offset >= self.len {
+        if !self.in_range(offset, 8) {
             panic!("access out of range");
         }
-        unsafe { u64::from_be(*(self.buffer.wrapping_offset(offset as isize) as *mut u64)) }
+        unsafe { u64::from_be(read_unaligned(self.buffer.wrapping_offset(offset as isize) as *const u64)) }
     }
 
     //在指定位置读大端f32
@@ -1937,7 +2976,7 @@ impl JSBuffer {
 
     //在指定位置读字节数组
     pub fn read(&self, offset: usize, len: usize) -> &[u8] {
-        if offset + len > self.len {
+        if !self.in_range(offset, len) {
             panic!("access out of range");
         }
         unsafe {
@@ -1947,7 +2986,7 @@ impl JSBuffer {
 
     //在指定位置读UTF8字符串
     pub fn to_string(&self, offset: usize, len: usize) -> Result<String, FromUtf8Error> {
-        if offset + len > self.len {
+        if !self.in_range(offset, len) {
             panic!("access out of range");
         }
 
@@ -2162,6 +3201,11 @@ pub fn set_vm_timeout(timeout: usize) -> usize {
 * 线程安全的注册全局虚拟机堆整理定时器，同一时间应该只有一个全局堆整理
 */
 pub fn register_global_vm_heap_collect_timer(collect_timeout: usize) {
+    if VM_COLLECT_STOPPED.load(Ordering::SeqCst) {
+        //全局虚拟机整理定时器已停止，则忽略
+        return;
+    }
+
     //初始化虚拟机整理队列
     let vm_coolect_queue_len = VM_COLLECT_QUEUE.lock().unwrap().len();
     if vm_coolect_queue_len == 0 {
@@ -2179,8 +3223,16 @@ pub fn register_global_vm_heap_collect_timer(collect_timeout: usize) {
         //当前已分配内存未达最大堆限制，则使用配置的虚拟机超时时长
         VM_TIMEOUT.load(Ordering::Relaxed)
     };
+    VM_COLLECT_INTERVAL.store(collect_timeout, Ordering::Relaxed);
+    VM_COLLECT_REGISTER_TIME.store(now_utc(), Ordering::Relaxed);
     let runner = FuncRuner::new(Box::new(move || {
         let func = Box::new(move |_lock| {
+            if VM_COLLECT_STOPPED.load(Ordering::SeqCst) {
+                //全局虚拟机整理定时器已停止，则不再整理，也不再注册下次整理
+                return;
+            }
+            VM_COLLECT_LAST_TIME.store(now_utc(), Ordering::Relaxed); //记录本次整理的运行时间
+
             let start_time = Instant::now();
             let mut factory_collect_time = Duration::from_millis(0);
             let last_heap_size = all_alloced_size();
@@ -2201,6 +3253,7 @@ pub fn register_global_vm_heap_collect_timer(collect_timeout: usize) {
 
                         let start_factory_collect_time = Instant::now();
 
+                        factory.evict_idle_sessions(); //解绑当前虚拟机工厂内，空闲超时的会话
                         factory.collect(Arc::new(move |vm: &mut Arc<JS>| {
                             //整理当前虚拟机工厂内，尾部的一个超时虚拟机
                             if (factory_copy.size() > 1)
@@ -2255,6 +3308,7 @@ pub fn register_global_vm_heap_collect_timer(collect_timeout: usize) {
             //在限流整理中不主动丢弃低负载虚拟机工厂的剩余虚拟机，但会主动生成高负载虚拟机工厂的空闲虚拟机
             let mut vm_factory_task_queue_len = 0;
             let mut timeout_total = timeout_count.load(Ordering::Relaxed);
+            let events = Arc::new(Mutex::new(Vec::new())); //持有全局虚拟机工厂注册表的锁时产生的事件，在释放锁后通知
             {
                 let mut balancing_loads = Vec::new();
                 let mut low_loads = Vec::new();
@@ -2321,6 +3375,14 @@ pub fn register_global_vm_heap_collect_timer(collect_timeout: usize) {
                             high_load_factory.add_limit_capacity(); //增加高负载虚拟机工厂的限制容量1
                             high_load_factory.collect_produce(); //并立即为高负载虚拟机工厂生成1个空闲虚拟机
                             info!("===> Factory Global Collect, low load factory: [{:?}, {:?}, {:?}], high load factory: [{:?}, {:?}, {:?}]", low_load_factory.name(), low_load_factory.limit_capacity(), low_load_factory.size(), high_load_factory.name(), high_load_factory.limit_capacity(), high_load_factory.size());
+                            if is_listening() {
+                                events.lock().unwrap().push(VmEvent::Rebalanced {
+                                    from: low_load_factory.name(),
+                                    from_limit: low_load_factory.limit_capacity(),
+                                    to: high_load_factory.name(),
+                                    to_limit: high_load_factory.limit_capacity(),
+                                });
+                            }
                         }
                     }
                 }
@@ -2332,6 +3394,7 @@ pub fn register_global_vm_heap_collect_timer(collect_timeout: usize) {
 
                     let factory_copy = factory.clone();
                     let timeout_count_copy = timeout_count.clone();
+                    let events_copy = events.clone();
                     factory.collect(Arc::new(move |vm: &mut Arc<JS>| {
                         //整理当前虚拟机工厂内，所有超时虚拟机
                         if (factory_copy.size() > 1)
@@ -2340,6 +3403,14 @@ pub fn register_global_vm_heap_collect_timer(collect_timeout: usize) {
                             //虚拟机已超时，且当前虚拟机工厂虚拟机数量大于最少虚拟机数量，则将超时虚拟机放入被整理队列
                             factory_copy.throw(1);
                             timeout_count_copy.fetch_add(1, Ordering::Relaxed);
+                            if is_listening() {
+                                events_copy.lock().unwrap().push(VmEvent::Thrown {
+                                    factory: factory_copy.name(),
+                                    vm_id: vm.get_id(),
+                                    heap_size: vm.heap_size(),
+                                    reason: ThrowReason::Timeout,
+                                });
+                            }
                             CollectResult::Continue(true) //移除当前尾部的超时虚拟机，并继续整理
                         } else {
                             CollectResult::Break(false) //当前尾部没有超时虚拟机，说明虚拟机工厂内没有超时虚拟机，则立即中止整理
@@ -2355,6 +3426,11 @@ pub fn register_global_vm_heap_collect_timer(collect_timeout: usize) {
                 }
             }
 
+            let events: Vec<VmEvent> = events.lock().unwrap().drain(..).collect();
+            for event in events {
+                emit(event);
+            }
+
             free_sys_mem(all_alloced_size(), FREE_SYSTEM_MEMORY_MAX_LIMIT);
 
             //限流整理完成，则结束本次整理，并注册下次整理
@@ -2373,6 +3449,40 @@ pub fn register_global_vm_heap_collect_timer(collect_timeout: usize) {
     TIMER.set_timeout(runner, collect_timeout as u32);
 }
 
+//停止全局虚拟机整理定时器，已注册的整理将被忽略，且不会再注册下次整理，停止后无法重新注册
+pub fn stop_global_vm_heap_collect_timer() {
+    VM_COLLECT_STOPPED.store(true, Ordering::SeqCst);
+}
+
+//判断全局虚拟机整理定时器是否已停止
+pub fn is_global_vm_heap_collect_stopped() -> bool {
+    VM_COLLECT_STOPPED.load(Ordering::SeqCst)
+}
+
+//获取全局虚拟机整理定时器的整理间隔，单位ms，未注册返回空
+pub fn global_vm_heap_collect_interval() -> Option<usize> {
+    match VM_COLLECT_INTERVAL.load(Ordering::Relaxed) {
+        0 => None,
+        interval => Some(interval),
+    }
+}
+
+//获取全局虚拟机整理定时器的最近注册时间，单位us，未注册返回空
+pub fn global_vm_heap_collect_register_time() -> Option<usize> {
+    match VM_COLLECT_REGISTER_TIME.load(Ordering::Relaxed) {
+        0 => None,
+        time => Some(time),
+    }
+}
+
+//获取全局虚拟机整理的最近运行时间，单位us，未运行返回空
+pub fn global_vm_heap_collect_last_time() -> Option<usize> {
+    match VM_COLLECT_LAST_TIME.load(Ordering::Relaxed) {
+        0 => None,
+        time => Some(time),
+    }
+}
+
 //线程安全的回收多余的空闲系统内存
 #[cfg(any(windows))]
 fn free_sys_mem(_: usize, _: u64) -> bool {
@@ -0,0 +1,57 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use adapter::JS;
+
+/*
+* 会话解绑处理器，参数分别为会话和绑定的虚拟机，在虚拟机空闲时同步调用，可以在此序列化会话状态
+*/
+pub type EvictHandler = Arc<Fn(usize, Arc<JS>) + Send + Sync>;
+
+/*
+* 虚拟机工厂的会话亲和配置
+*/
+#[derive(Clone)]
+pub struct AffinityConfig {
+    max_pinned:     usize,                  //最多绑定会话的虚拟机数量，0表示无限制
+    idle_timeout:   Duration,               //会话空闲超时时长，超时的会话将在虚拟机整理时解绑
+    evict_handler:  Option<EvictHandler>,   //会话解绑处理器
+}
+
+impl AffinityConfig {
+    //构建虚拟机工厂的会话亲和配置
+    pub fn new(max_pinned: usize) -> Self {
+        AffinityConfig {
+            max_pinned,
+            idle_timeout: Duration::from_secs(300),
+            evict_handler: None,
+        }
+    }
+
+    //设置会话空闲超时时长
+    pub fn set_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    //设置会话解绑处理器
+    pub fn set_evict_handler(mut self, handler: EvictHandler) -> Self {
+        self.evict_handler = Some(handler);
+        self
+    }
+
+    //获取最多绑定会话的虚拟机数量
+    pub fn max_pinned(&self) -> usize {
+        self.max_pinned
+    }
+
+    //获取会话空闲超时时长
+    pub fn idle_timeout(&self) -> Duration {
+        self.idle_timeout
+    }
+
+    //获取会话解绑处理器
+    pub fn evict_handler(&self) -> Option<&EvictHandler> {
+        self.evict_handler.as_ref()
+    }
+}
@@ -0,0 +1,310 @@
+use std::cmp::min;
+use std::any::Any;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::io::{Read, Write, Seek, SeekFrom, Result, Error, ErrorKind};
+
+use parking_lot::Mutex;
+#[cfg(feature = "bytes")]
+use bytes::Bytes;
+
+use hash::XHashMap;
+
+use adapter::JSBuffer;
+
+/*
+* LEB128变长整数的最大字节数
+*/
+const MAX_VARINT_LEN: usize = 10;
+
+lazy_static! {
+    //外部缓冲区分配id，从1开始分配
+    static ref EXTERNAL_BUFFER_ID: AtomicU64 = AtomicU64::new(1);
+    //外部缓冲区表，在虚拟机回收外部缓冲区前持有外部数据的所有者和数据长度
+    static ref EXTERNAL_BUFFERS: Mutex<XHashMap<u64, (Box<Any + Send>, usize)>> = Mutex::new(XHashMap::default());
+    //外部缓冲区总大小
+    static ref EXTERNAL_BUFFER_SIZE: AtomicUsize = AtomicUsize::new(0);
+}
+
+/*
+* 外部数据，可以移动到虚拟机的外部缓冲区，任何外部数据都不会复制，独占的外部数据脚本可以修改，共享的外部数据只读
+*/
+pub trait ExternalBytes: Send + 'static {
+    //转换为外部数据的所有者，返回所有者、数据指针、数据长度和是否只读，数据在所有者释放前不会移动或释放
+    fn into_external(self) -> (Box<Any + Send>, *mut u8, usize, bool);
+}
+
+impl ExternalBytes for Vec<u8> {
+    fn into_external(mut self) -> (Box<Any + Send>, *mut u8, usize, bool) {
+        //移动Vec不会移动堆上的数据
+        let ptr = self.as_mut_ptr();
+        let len = self.len();
+        (Box::new(self), ptr, len, false)
+    }
+}
+
+impl ExternalBytes for Arc<Vec<u8>> {
+    fn into_external(self) -> (Box<Any + Send>, *mut u8, usize, bool) {
+        //没有其它引用，则取出数据作为可写的外部数据，否则共享数据作为只读的外部数据
+        match Arc::try_unwrap(self) {
+            Ok(vec) => vec.into_external(),
+            Err(shared) => {
+                let ptr = shared.as_ptr() as *mut u8;
+                let len = shared.len();
+                (Box::new(shared), ptr, len, true)
+            },
+        }
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl ExternalBytes for Bytes {
+    fn into_external(self) -> (Box<Any + Send>, *mut u8, usize, bool) {
+        //Bytes的数据可能被共享，且不可写，作为只读的外部数据
+        let ptr = self.as_ptr() as *mut u8;
+        let len = self.len();
+        (Box::new(self), ptr, len, true)
+    }
+}
+
+//注册外部数据的所有者，返回外部缓冲区句柄，所有者在外部缓冲区被虚拟机回收前不会释放
+pub fn register_external_buffer(owner: Box<Any + Send>, len: usize) -> u64 {
+    let handle = EXTERNAL_BUFFER_ID.fetch_add(1, Ordering::Relaxed);
+    EXTERNAL_BUFFER_SIZE.fetch_add(len, Ordering::Relaxed);
+    EXTERNAL_BUFFERS.lock().insert(handle, (owner, len));
+    handle
+}
+
+//虚拟机回收外部缓冲区后，释放对应的外部数据的所有者
+#[no_mangle]
+pub extern "C" fn external_buffer_free(handle: u64) {
+    if let Some((_owner, len)) = EXTERNAL_BUFFERS.lock().remove(&handle) {
+        EXTERNAL_BUFFER_SIZE.fetch_sub(len, Ordering::Relaxed);
+    }
+}
+
+//获取虚拟机未回收的外部缓冲区数量
+pub fn external_buffer_count() -> usize {
+    EXTERNAL_BUFFERS.lock().len()
+}
+
+//获取虚拟机未回收的外部缓冲区总大小
+pub fn external_buffer_size() -> usize {
+    EXTERNAL_BUFFER_SIZE.load(Ordering::Relaxed)
+}
+
+/*
+* Js Buffer游标，在Js Buffer上顺序读写，所有访问都会检查边界
+*/
+pub struct JSBufferCursor {
+    buffer: JSBuffer,   //Js Buffer
+    pos:    usize,      //当前位置
+}
+
+impl Read for JSBufferCursor {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let len = min(self.remaining(), buf.len());
+        if len == 0 {
+            return Ok(0);
+        }
+
+        buf[..len].copy_from_slice(self.buffer.try_read(self.pos, len)?);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+impl Write for JSBufferCursor {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        //Js Buffer长度固定，超出部分不会写入
+        let len = min(self.remaining(), buf.len());
+        if len == 0 {
+            return Ok(0);
+        }
+
+        self.pos = self.buffer.try_write(self.pos, &buf[..len])?;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for JSBufferCursor {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(n) => (0, n as i64),
+            SeekFrom::End(n) => (self.buffer.len() as i64, n),
+            SeekFrom::Current(n) => (self.pos as i64, n),
+        };
+
+        match base.checked_add(offset) {
+            Some(n) if n >= 0 && n as usize <= self.buffer.len() => {
+                self.pos = n as usize;
+                Ok(n as u64)
+            },
+            _ => {
+                Err(Error::new(ErrorKind::InvalidInput, format!("invalid js buffer seek, base: {}, offset: {}, len: {}", base, offset, self.buffer.len())))
+            },
+        }
+    }
+}
+
+impl JSBufferCursor {
+    //构建Js Buffer游标
+    pub fn new(buffer: JSBuffer) -> Self {
+        JSBufferCursor {
+            buffer,
+            pos: 0,
+        }
+    }
+
+    //获取当前位置
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    //获取剩余可读写的字节数
+    pub fn remaining(&self) -> usize {
+        self.buffer.len() - self.pos
+    }
+
+    //获取内部Js Buffer
+    pub fn get_ref(&self) -> &JSBuffer {
+        &self.buffer
+    }
+
+    //取出内部Js Buffer
+    pub fn into_inner(self) -> JSBuffer {
+        self.buffer
+    }
+
+    //读u8
+    pub fn read_u8(&mut self) -> Result<u8> {
+        let v = self.buffer.try_read_u8(self.pos)?;
+        self.pos += 1;
+        Ok(v)
+    }
+
+    //写u8
+    pub fn write_u8(&mut self, v: u8) -> Result<()> {
+        self.pos = self.buffer.try_write_u8(self.pos, v)?;
+        Ok(())
+    }
+
+    //读LEB128编码的无符号变长整数
+    pub fn read_varint(&mut self) -> Result<u64> {
+        let start = self.pos;
+        let mut v = 0u64;
+        for index in 0..MAX_VARINT_LEN {
+            let byte = match self.read_u8() {
+                Err(e) => {
+                    //读取失败，则恢复读取前的位置
+                    self.pos = start;
+                    return Err(e);
+                },
+                Ok(byte) => byte,
+            };
+
+            if index == MAX_VARINT_LEN - 1 && byte > 1 {
+                //第10个字节只允许使用最低位，否则溢出
+                break;
+            }
+
+            v |= ((byte & 0x7f) as u64) << (index * 7);
+            if byte & 0x80 == 0 {
+                return Ok(v);
+            }
+        }
+
+        self.pos = start;
+        Err(Error::new(ErrorKind::InvalidData, format!("invalid varint, offset: {}", start)))
+    }
+
+    //写LEB128编码的无符号变长整数，返回写入的字节数
+    pub fn write_varint(&mut self, mut v: u64) -> Result<usize> {
+        let mut bytes = [0u8; MAX_VARINT_LEN];
+        let mut len = 0;
+        loop {
+            let byte = (v & 0x7f) as u8;
+            v >>= 7;
+            if v == 0 {
+                bytes[len] = byte;
+                len += 1;
+                break;
+            }
+            bytes[len] = byte | 0x80;
+            len += 1;
+        }
+
+        //先写入临时缓冲，保证越界时不会写入部分数据
+        self.pos = self.buffer.try_write(self.pos, &bytes[..len])?;
+        Ok(len)
+    }
+
+    //读ZigZag和LEB128编码的有符号变长整数
+    pub fn read_varint_signed(&mut self) -> Result<i64> {
+        let v = self.read_varint()?;
+        Ok(((v >> 1) as i64) ^ -((v & 1) as i64))
+    }
+
+    //写ZigZag和LEB128编码的有符号变长整数，返回写入的字节数
+    pub fn write_varint_signed(&mut self, v: i64) -> Result<usize> {
+        self.write_varint(((v << 1) ^ (v >> 63)) as u64)
+    }
+
+    //读以变长整数为长度前缀的字节数组
+    pub fn read_bytes(&mut self) -> Result<&[u8]> {
+        let start = self.pos;
+        let len = self.read_varint()? as usize;
+        if len > self.remaining() {
+            //长度超出剩余字节数，则恢复读取前的位置
+            self.pos = start;
+            return Err(Error::new(ErrorKind::UnexpectedEof, format!("invalid bytes length, offset: {}, len: {}, remaining: {}", start, len, self.remaining())));
+        }
+
+        let offset = self.pos;
+        self.pos += len;
+        self.buffer.try_read(offset, len)
+    }
+
+    //写以变长整数为长度前缀的字节数组，返回写入的字节数
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> Result<usize> {
+        let start = self.pos;
+        let prefix = self.write_varint(bytes.len() as u64)?;
+        match self.buffer.try_write(self.pos, bytes) {
+            Err(e) => {
+                //写入失败，则恢复写入前的位置
+                self.pos = start;
+                Err(e)
+            },
+            Ok(last) => {
+                self.pos = last;
+                Ok(prefix + bytes.len())
+            },
+        }
+    }
+
+    //读以变长整数为长度前缀的UTF8字符串
+    pub fn read_string(&mut self) -> Result<String> {
+        let start = self.pos;
+        let r = match self.read_bytes() {
+            Err(e) => return Err(e),
+            Ok(bytes) => String::from_utf8(bytes.to_vec()),
+        };
+
+        match r {
+            Err(e) => {
+                self.pos = start;
+                Err(Error::new(ErrorKind::InvalidData, e))
+            },
+            Ok(s) => Ok(s),
+        }
+    }
+
+    //写以变长整数为长度前缀的UTF8字符串，返回写入的字节数
+    pub fn write_string(&mut self, s: &str) -> Result<usize> {
+        self.write_bytes(s.as_bytes())
+    }
+}
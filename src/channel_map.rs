@@ -3,6 +3,7 @@ use std::clone::Clone;
 use std::collections::HashMap;
 use std::collections::hash_map::Entry;
 use std::cell::RefCell;
+use std::time::{Duration, Instant};
 
 use atom::Atom;
 use handler::{Env, GenType, Handler, Args};
@@ -10,6 +11,7 @@ use gray::GrayVersion;
 
 use adapter::{JS, JSType};
 use pi_vm_impl::{block_reply, push_callback};
+use trace::{TraceContext, Span, is_tracing, vm_context, enter, record};
 
 /*
 * 通道对端
@@ -27,6 +29,7 @@ pub struct VMChannel {
     dst: VMChannelPeer,                         //目标
     attrs: RefCell<HashMap<Atom, GenType>>,     //属性表
     gray: Option<usize>,                        //灰度
+    span: Option<(TraceContext, Option<u64>, Atom, Instant)>,   //异步请求的跨度，包括跟踪上下文、父跨度id、请求名和请求时间，为空表示未跟踪
 }
 
 impl GrayVersion for VMChannel {
@@ -76,9 +79,15 @@ impl VMChannel {
             dst: dst,
             gray: None,
             attrs: RefCell::new(HashMap::new()),
+            span: None,
         }
     }
 
+    //获取异步请求的跟踪上下文，异步调用处理器可以使用此上下文继续跟踪
+    pub fn trace(&self) -> Option<TraceContext> {
+        self.span.as_ref().map(|(context, _, _, _)| *context)
+    }
+
     //发送消息
     pub fn send(&self, _name: Atom, _msg: Arc<Vec<u8>>) {
         //TODO
@@ -87,6 +96,14 @@ impl VMChannel {
 
     //回应请求
     pub fn response(&self, callback: Option<u32>, result: Arc<Vec<u8>>, native_objs: Vec<usize>) -> bool {
+        if let (Some((context, parent_id, name, start)), VMChannelPeer::VM(ref js)) = (&self.span, &self.src) {
+            //记录从请求到回应的跨度
+            record(Span::new(*context, *parent_id, format!("async_request {}", name.to_string()), Duration::from_millis(0), start.elapsed(), vec![
+                ("vm", js.get_id().to_string()),
+                ("callback", format!("{:?}", callback)),
+            ]));
+        }
+
         match self.src {
             VMChannelPeer::VM(ref js) => {
                 match callback {
@@ -94,8 +111,13 @@ impl VMChannel {
                         //同步阻塞返回
                         let result = Box::new(move |vm: Arc<JS>| {
                             let array = vm.new_array();
-                            let mut buffer = vm.new_uint8_array(result.len() as u32);
-                            buffer.from_bytes(result.as_slice());
+                            let mut buffer = match vm.new_external_uint8_array(result) {
+                                Err(e) => {
+                                    warn!("!!!> Vm Response Error, e: {:?}", e);
+                                    vm.new_undefined()
+                                },
+                                Ok(buffer) => buffer, //回应数据不复制，共享的回应数据只读
+                            };
                             vm.set_index(&array, 0, &mut buffer);
                             let mut value: JSType;
                             let mut sub_array = vm.new_array();
@@ -110,8 +132,11 @@ impl VMChannel {
                     Some(index) => {
                         //异步回调
                         let args = Box::new(move |vm: Arc<JS>| -> usize {
-                            let buffer = vm.new_uint8_array(result.len() as u32);
-                            buffer.from_bytes(result.as_slice());
+                            if let Err(e) = vm.new_external_uint8_array(result) {
+                                //回应数据不复制，共享的回应数据只读
+                                warn!("!!!> Vm Response Error, e: {:?}", e);
+                                vm.new_undefined();
+                            }
                             let mut value: JSType;
                             let array = vm.new_array();
                             for i in 0..native_objs.len() {
@@ -198,8 +223,29 @@ impl VMChannelMap {
             objs.push(js.new_native_object(native_objs[index]));
         }
 
-        let channel = VMChannel::new(VMChannelPeer::VM(js), VMChannelPeer::Any);
+        let mut span = None;
+        if is_tracing() {
+            //启用跟踪时，异步请求作为虚拟机当前任务的子跨度
+            let parent = vm_context(&js);
+            let context = parent.map(|parent| parent.child()).unwrap_or_else(TraceContext::new);
+            span = Some((context, parent.map(|parent| parent.span_id()), name.clone(), Instant::now()));
+        }
+
+        //异步请求继续使用虚拟机当前调用路由到的灰度版本，没有则使用通道表的灰度值，灰度值为0表示没有灰度
+        let gray = js.call_options().and_then(|options| options.gray()).or_else(|| {
+            match self.get_gray() {
+                0 => None,
+                gray => Some(gray),
+            }
+        });
+        let mut channel = VMChannel::new(VMChannelPeer::VM(js), VMChannelPeer::Any);
+        channel.set_gray(gray);
+        let last = span.as_ref().map(|(context, _, _, _)| enter(Some(*context))); //处理器同步执行期间可以获取当前跟踪上下文
+        channel.span = span;
         handler.handle(Arc::new(channel), name, Args::ThreeArgs(msg, objs, callback));
+        if let Some(last) = last {
+            enter(last);
+        }
         true
     }
 }
\ No newline at end of file
@@ -1,5 +1,6 @@
 use std::sync::Arc;
 use std::cell::RefCell;
+use std::time::{Duration, Instant};
 use std::io::{Error, ErrorKind};
 use std::sync::atomic::{AtomicBool, AtomicU8, AtomicU32, AtomicI32, Ordering};
 
@@ -7,15 +8,17 @@ use parking_lot::RwLock;
 use crossbeam_channel::bounded;
 
 use atom::Atom;
-use worker::{impls::cast_js_task, task::TaskType};
+use worker::task::TaskType;
 use handler::{Args, GenType};
 use hash::XHashMap;
 
 use adapter::{pause, JS};
-use pi_vm_impl::push_msg;
+use pi_vm_impl::{CallOptions, push_msg};
 use bonmgr::{NativeObjsAuth, ptr_jstype};
 use proc::{ProcStatus, ProcInfo, Process, ProcessFactory};
 use proc_pool::register_process;
+use scheduler::cast_js_task;
+use trace::{Span, is_tracing, current, record};
 
 /*
 * 默认的异步虚拟机任务优先级
@@ -129,8 +132,24 @@ impl Process<(Arc<NativeObjsAuth>, Arc<Vec<Vec<u8>>>), Box<FnOnce(Arc<JS>) -> us
         match self.status.load(Ordering::SeqCst) {
             running_status => {
                 //当前进程正在运行
+                let pid = self.pid;
+                let sent = Instant::now();
                 let args = Box::new(move |vm: Arc<JS>| {
-                    gen_args_to_js_args(vm, Some(info.source()), info.payload())
+                    let src = info.source();
+                    let context = match info.trace() {
+                        Some(parent) if is_tracing() => {
+                            //接收消息时，记录发送跨度的子跨度，等待时长为消息从发送到被接收的延迟，进程处理消息期间的调用将延续此跟踪
+                            let context = parent.child();
+                            record(Span::new(context, Some(parent.span_id()), "process_receive".to_string(), sent.elapsed(), Duration::from_millis(0), vec![
+                                ("src", src.to_string()),
+                                ("dst", pid.to_string()),
+                            ]));
+                            Some(context)
+                        },
+                        _ => None,
+                    };
+                    vm.set_call_options(context.map(|context| CallOptions::new().set_trace(context))); //设置进程处理当前消息的调用选项，本地函数可以在处理期间获取跟踪上下文
+                    gen_args_to_js_args(vm, Some(src), info.into_payload())
                 });
                 push_msg(self.vm.clone(), self.receiver.load(Ordering::Relaxed), args, Atom::from(format!("DukProcess Info Task, pid: {:?}, name: {:?}", self.pid, self.name)));
                 Ok(())
@@ -253,7 +272,7 @@ impl ProcessFactory for DukProcessFactory {
                args: GenType) -> Result<(), Self::Error> {
         if let Some(process) = self.pool.read().get(&(pid as usize)).cloned() {
             let vm_args = Box::new(move |vm: Arc<JS>| {
-                gen_args_to_js_args(vm, None, &args)
+                gen_args_to_js_args(vm, None, args)
             });
             return process.borrow().call(module, function, init, vm_args);
         }
@@ -315,12 +334,17 @@ impl ProcessFactory for DukProcessFactory {
 
     fn send(&self, src: u64, dst: u64, msg: GenType) -> Result<(), Self::Error> {
         if let Some(process) = self.pool.read().get(&(dst as usize)).cloned() {
-            return process.borrow().info(ProcInfo::new(src, dst, msg));
+            //在消息中携带当前的发送跨度，以保证接收消息的跨度可以延续跟踪
+            return process.borrow().info(ProcInfo::new(src, dst, msg).set_trace(current()));
         }
 
         Err(Error::new(ErrorKind::Other, format!("send msg to duk process failed, src: {:?}, dst: {:?}, reason: process not exists", src, dst)))
     }
 
+    fn throw(&self, pid: u64, reason: String) -> Result<(), Self::Error> {
+        DukProcessFactory::throw(self, pid, reason)
+    }
+
     fn close(&self, pid: u64, reason: String) -> Result<Option<String>, Self::Error> {
         //移除当前进程的异步消息接收器，并发送关闭消息，以保证进程可以自动回收
         if let Err(e) = self.unset_receiver(pid) {
@@ -362,18 +386,24 @@ impl DukProcessFactory {
         }
     }
 
-    //在指定进程中抛出一个异常
+    //在指定进程中抛出一个异常，进程未设置异常捕获器则失败
     pub fn throw(&self, pid: u64, error: String) -> Result<(), <Self as ProcessFactory>::Error> {
         if let Some(process) = self.pool.read().get(&(pid as usize)).cloned() {
-            return process.borrow().throw(error);
+            let process = process.borrow();
+            if process.catcher.load(Ordering::Relaxed) <= 0 {
+                //未设置异常捕获器
+                return Err(Error::new(ErrorKind::Other, format!("duk process throw failed, pid: {:?}, reason: catcher not exists", pid)));
+            }
+
+            return process.throw(error);
         }
 
         Err(Error::new(ErrorKind::Other, format!("trhow error to duk process failed, pid: {:?}, reason: process not exists", pid)))
     }
 }
 
-//解析GenType，并构建指定虚拟机的JsType，返回参数数量
-fn gen_args_to_js_args(vm: Arc<JS>, src: Option<u64>, args: &GenType) -> usize {
+//解析GenType，并构建指定虚拟机的JsType，返回参数数量，二进制参数会移动到虚拟机，不复制
+fn gen_args_to_js_args(vm: Arc<JS>, src: Option<u64>, args: GenType) -> usize {
     let mut size = 0;
     if let Some(src) = src {
         //有源进程唯一id
@@ -389,22 +419,23 @@ fn gen_args_to_js_args(vm: Arc<JS>, src: Option<u64>, args: &GenType) -> usize {
                     size += 1;
                 },
                 GenType::Bool(val) => {
-                    vm.new_boolean(*val);
+                    vm.new_boolean(val);
                     size += 1;
                 },
                 GenType::F64(val) => {
-                    vm.new_f64(*val);
+                    vm.new_f64(val);
                     size += 1;
                 },
                 GenType::Str(val) => {
-                    if let Err(e) = vm.new_str(val.clone()) {
+                    if let Err(e) = vm.new_str(val) {
                         panic!("native string to js string failed, reason: {:?}", e);
                     }
                     size += 1;
                 },
                 GenType::Bin(val) => {
-                    let buf = vm.new_uint8_array(val.len() as u32);
-                    buf.from_bytes(val.as_slice());
+                    if let Err(e) = vm.new_external_uint8_array(val) {
+                        panic!("native binary to js buffer failed, reason: {:?}", e);
+                    }
                     size += 1;
                 },
                 GenType::Array(array) => {
@@ -0,0 +1,221 @@
+use std::sync::Arc;
+use std::time::Duration;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use parking_lot::RwLock;
+use crossbeam_channel::{Sender, Receiver, unbounded};
+
+use atom::Atom;
+use adapter::JS;
+use pi_vm_impl::RefuseReason;
+
+lazy_static! {
+    //虚拟机事件监听器表，包括监听器id、监听的虚拟机工厂名和监听器，虚拟机工厂名为空表示全局监听器
+    static ref VM_EVENT_LISTENERS: RwLock<Vec<(usize, Option<String>, Arc<VmEventListener>)>> = RwLock::new(Vec::new());
+    //虚拟机事件监听器分配id
+    static ref VM_EVENT_LISTENER_ID: AtomicUsize = AtomicUsize::new(1);
+    //虚拟机事件监听器数量，用于在没有监听器时快速跳过事件的构建
+    static ref VM_EVENT_LISTENER_COUNT: AtomicUsize = AtomicUsize::new(0);
+}
+
+/*
+* 虚拟机丢弃的原因
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThrowReason {
+    WaitThrow,  //虚拟机被标记为等待丢弃
+    Policy,     //虚拟机工厂的复用策略要求丢弃
+    Recreate,   //虚拟机工厂的复用策略要求丢弃，并构建新的虚拟机
+    Leak,       //虚拟机内存泄漏
+    Timeout,    //虚拟机空闲超时，在全局虚拟机整理时丢弃
+    Shrink,     //虚拟机工厂收缩空闲虚拟机
+    Shutdown,   //虚拟机工厂关闭
+}
+
+/*
+* 虚拟机事件
+*/
+#[derive(Debug, Clone)]
+pub enum VmEvent {
+    Created {                   //虚拟机构建完成
+        factory:    String,     //虚拟机工厂名
+        vm_id:      usize,      //虚拟机id
+        heap_size:  usize,      //虚拟机初始化后的堆大小
+        elapsed:    Duration,   //构建并加载虚拟机的耗时
+    },
+    LoadFailed {                //虚拟机构建或加载字节码失败
+        factory:    String,     //虚拟机工厂名
+        vm_id:      usize,      //虚拟机id
+        elapsed:    Duration,   //失败前的耗时
+    },
+    Reused {                    //虚拟机已重置全局环境，并被虚拟机工厂复用
+        factory:        String, //虚拟机工厂名
+        vm_id:          usize,  //虚拟机id
+        heap_size:      usize,  //虚拟机重置全局环境后的堆大小
+        reused_count:   usize,  //虚拟机复用次数
+    },
+    Freed {                     //虚拟机已释放可回收内存
+        factory:    String,     //虚拟机工厂名
+        vm_id:      usize,      //虚拟机id
+        heap_size:  usize,      //虚拟机释放后的堆大小
+    },
+    Thrown {                    //虚拟机被丢弃
+        factory:    String,     //虚拟机工厂名
+        vm_id:      usize,      //虚拟机id
+        heap_size:  usize,      //虚拟机丢弃时的堆大小
+        reason:     ThrowReason,    //丢弃的原因
+    },
+    WaitThrow {                 //虚拟机被标记为等待丢弃，将在当前任务执行完成后丢弃
+        factory:    String,     //虚拟机工厂名
+        vm_id:      usize,      //虚拟机id
+        heap_size:  usize,      //虚拟机被标记时的堆大小
+    },
+    InitPanic {                 //虚拟机初始化异常
+        status:     i32,        //异常状态
+        error:      String,     //异常信息
+    },
+    RunError {                  //虚拟机运行异常
+        factory:    String,     //虚拟机工厂名
+        vm_id:      usize,      //虚拟机id
+        error:      String,     //异常信息
+    },
+    Refused {                   //虚拟机工厂拒绝任务
+        factory:    String,     //虚拟机工厂名
+        reason:     RefuseReason,   //拒绝的原因
+        info:       Atom,       //任务信息
+    },
+    Rebalanced {                //全局虚拟机整理时，将低负载虚拟机工厂的限制容量调度到高负载虚拟机工厂
+        from:       String,     //低负载虚拟机工厂名
+        from_limit: usize,      //低负载虚拟机工厂调度后的限制容量
+        to:         String,     //高负载虚拟机工厂名
+        to_limit:   usize,      //高负载虚拟机工厂调度后的限制容量
+    },
+}
+
+impl VmEvent {
+    //获取事件所属的虚拟机工厂名，全局事件返回空，调度事件返回高负载虚拟机工厂名
+    pub fn factory(&self) -> Option<&str> {
+        match self {
+            VmEvent::Created { factory, .. } => Some(factory),
+            VmEvent::LoadFailed { factory, .. } => Some(factory),
+            VmEvent::Reused { factory, .. } => Some(factory),
+            VmEvent::Freed { factory, .. } => Some(factory),
+            VmEvent::Thrown { factory, .. } => Some(factory),
+            VmEvent::WaitThrow { factory, .. } => Some(factory),
+            VmEvent::InitPanic { .. } => None,
+            VmEvent::RunError { factory, .. } => Some(factory),
+            VmEvent::Refused { factory, .. } => Some(factory),
+            VmEvent::Rebalanced { to, .. } => Some(to),
+        }
+    }
+
+    //判断事件是否与指定虚拟机工厂相关
+    fn is_related(&self, name: &str) -> bool {
+        match self {
+            VmEvent::Rebalanced { from, to, .. } => from == name || to == name,
+            event => event.factory() == Some(name),
+        }
+    }
+}
+
+/*
+* 虚拟机事件监听器，在产生事件的线程上同步调用，不允许阻塞，需要异步处理时使用通道监听器
+*/
+pub trait VmEventListener: Send + Sync + 'static {
+    //处理虚拟机事件
+    fn on_event(&self, event: &VmEvent);
+}
+
+impl<F: Fn(&VmEvent) + Send + Sync + 'static> VmEventListener for F {
+    fn on_event(&self, event: &VmEvent) {
+        self(event)
+    }
+}
+
+/*
+* 通道监听器，将虚拟机事件发送到通道，由接收者异步处理
+*/
+pub struct ChannelListener {
+    sender: Sender<VmEvent>,    //虚拟机事件发送器
+}
+
+impl VmEventListener for ChannelListener {
+    fn on_event(&self, event: &VmEvent) {
+        //接收者已关闭，则忽略事件
+        let _ = self.sender.send(event.clone());
+    }
+}
+
+impl ChannelListener {
+    //构建通道监听器，并返回虚拟机事件接收器
+    pub fn new() -> (Arc<Self>, Receiver<VmEvent>) {
+        let (sender, receiver) = unbounded();
+        (Arc::new(ChannelListener { sender }), receiver)
+    }
+}
+
+//注册全局虚拟机事件监听器，监听所有虚拟机工厂的事件，返回监听器id
+pub fn register_listener(listener: Arc<VmEventListener>) -> usize {
+    add_listener(None, listener)
+}
+
+//注册指定虚拟机工厂的虚拟机事件监听器，只监听与指定虚拟机工厂相关的事件，返回监听器id
+pub fn register_factory_listener(factory: &str, listener: Arc<VmEventListener>) -> usize {
+    add_listener(Some(factory.to_string()), listener)
+}
+
+//注销指定id的虚拟机事件监听器
+pub fn unregister_listener(id: usize) -> Option<Arc<VmEventListener>> {
+    let mut listeners = VM_EVENT_LISTENERS.write();
+    match listeners.iter().position(|(listener_id, _, _)| *listener_id == id) {
+        None => None,
+        Some(index) => {
+            VM_EVENT_LISTENER_COUNT.fetch_sub(1, Ordering::SeqCst);
+            Some(listeners.remove(index).2)
+        },
+    }
+}
+
+//判断是否有虚拟机事件监听器
+#[inline]
+pub fn is_listening() -> bool {
+    VM_EVENT_LISTENER_COUNT.load(Ordering::Relaxed) > 0
+}
+
+//通知所有相关的虚拟机事件监听器，在当前线程上同步调用，调用时不持有监听器表的锁
+pub(crate) fn emit(event: VmEvent) {
+    if !is_listening() {
+        return;
+    }
+
+    let listeners: Vec<Arc<VmEventListener>> = VM_EVENT_LISTENERS.read().iter().filter(|(_, factory, _)| {
+        match factory {
+            None => true,
+            Some(name) => event.is_related(name),
+        }
+    }).map(|(_, _, listener)| listener.clone()).collect();
+
+    for listener in listeners {
+        listener.on_event(&event);
+    }
+}
+
+//通知虚拟机被丢弃
+pub(crate) fn emit_thrown(js: &JS, reason: ThrowReason) {
+    if is_listening() {
+        emit(VmEvent::Thrown {
+            factory: (&js.get_name()).to_string(),
+            vm_id: js.get_id(),
+            heap_size: js.heap_size(),
+            reason,
+        });
+    }
+}
+
+//增加虚拟机事件监听器
+fn add_listener(factory: Option<String>, listener: Arc<VmEventListener>) -> usize {
+    let id = VM_EVENT_LISTENER_ID.fetch_add(1, Ordering::Relaxed);
+    VM_EVENT_LISTENERS.write().push((id, factory, listener));
+    VM_EVENT_LISTENER_COUNT.fetch_add(1, Ordering::SeqCst);
+    id
+}
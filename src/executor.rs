@@ -0,0 +1,358 @@
+use std::rc::Rc;
+use std::sync::Arc;
+use std::cell::RefCell;
+use std::time::Duration;
+use std::collections::{HashMap, VecDeque, BTreeMap};
+
+use worker::task::TaskType;
+use atom::Atom;
+
+use adapter::{JS, JSStatus};
+
+thread_local! {
+    //当前线程安装的测试执行器
+    static TEST_EXECUTOR: RefCell<Option<TestExecutor>> = RefCell::new(None);
+}
+
+/*
+* 测试执行器中等待执行的任务
+*/
+struct TestTask {
+    seq:        usize,                          //投递序号，优先级相同时决定任务的执行顺序
+    priority:   usize,                          //任务优先级，越大越先执行
+    func:       Box<FnOnce(Option<isize>)>,     //任务函数
+    info:       Atom,                           //任务信息
+}
+
+impl TestTask {
+    //判断是否比指定任务先执行，优先级高的先执行，优先级相同时投递序号小的先执行
+    fn is_before(&self, priority: usize, seq: usize) -> bool {
+        self.priority > priority || (self.priority == priority && self.seq < seq)
+    }
+}
+
+/*
+* 测试执行器中的任务队列
+*/
+struct TestQueue {
+    is_locked:  bool,                   //是否已锁住，锁住的任务队列中的任务不会被执行
+    tasks:      VecDeque<TestTask>,     //等待执行的任务
+}
+
+/*
+* 测试执行器中的延迟任务
+*/
+struct DelayTask {
+    queue:  Option<isize>,  //任务所在的任务队列，为空表示全局任务
+    task:   TestTask,       //任务
+}
+
+/*
+* 测试执行器的状态
+*/
+struct ExecutorState {
+    seq:            usize,                                  //任务投递序号
+    queue_id:       isize,                                  //任务队列分配id
+    now:            Duration,                               //虚拟时间
+    globals:        VecDeque<TestTask>,                     //等待执行的全局任务
+    queues:         HashMap<isize, TestQueue>,              //任务队列表
+    delays:         BTreeMap<(Duration, usize), DelayTask>, //延迟任务，按到期时间和投递序号排序
+    executed:       usize,                                  //已执行的任务数量
+    infos:          Vec<Atom>,                              //已执行的任务信息，按执行顺序排列
+    watches:        Vec<(Arc<JS>, Vec<JSStatus>)>,          //观察的虚拟机和虚拟机状态变化列表
+}
+
+/*
+* 单线程的确定性测试执行器，安装后当前线程投递的所有虚拟机任务都由测试执行器按任务优先级从高到低执行，优先级相同时按投递顺序执行，
+* 任务队列中的任务按投递顺序执行，延迟任务使用虚拟时间，只在推进虚拟时间时到期，测试执行器只在安装的线程上生效
+*/
+#[derive(Clone)]
+pub struct TestExecutor {
+    inner: Rc<RefCell<ExecutorState>>,  //测试执行器的状态
+}
+
+impl TestExecutor {
+    //构建测试执行器，并安装到当前线程，替换当前线程已安装的测试执行器
+    pub fn install() -> Self {
+        let executor = TestExecutor {
+            inner: Rc::new(RefCell::new(ExecutorState {
+                seq: 0,
+                queue_id: 0,
+                now: Duration::from_millis(0),
+                globals: VecDeque::new(),
+                queues: HashMap::new(),
+                delays: BTreeMap::new(),
+                executed: 0,
+                infos: Vec::new(),
+                watches: Vec::new(),
+            })),
+        };
+
+        TEST_EXECUTOR.with(|current| *current.borrow_mut() = Some(executor.clone()));
+        executor
+    }
+
+    //从当前线程卸载测试执行器，卸载后当前线程投递的任务由工作者执行，未执行的任务将被丢弃
+    pub fn uninstall() -> Option<Self> {
+        TEST_EXECUTOR.with(|current| current.borrow_mut().take())
+    }
+
+    //获取当前线程安装的测试执行器
+    pub fn current() -> Option<Self> {
+        TEST_EXECUTOR.with(|current| current.borrow().clone())
+    }
+
+    //获取虚拟时间
+    pub fn now(&self) -> Duration {
+        self.inner.borrow().now
+    }
+
+    //获取可以立即执行的任务数量，不包括锁住的任务队列中的任务和未到期的延迟任务
+    pub fn ready_len(&self) -> usize {
+        let state = self.inner.borrow();
+        state.globals.len() + state.queues.values().filter(|queue| !queue.is_locked).map(|queue| queue.tasks.len()).sum::<usize>()
+    }
+
+    //获取未到期的延迟任务数量
+    pub fn delay_len(&self) -> usize {
+        self.inner.borrow().delays.len()
+    }
+
+    //获取指定任务队列中等待执行的任务数量，任务队列不存在返回空
+    pub fn queue_len(&self, queue: isize) -> Option<usize> {
+        self.inner.borrow().queues.get(&queue).map(|queue| queue.tasks.len())
+    }
+
+    //判断指定任务队列是否已锁住，任务队列不存在返回空
+    pub fn is_locked(&self, queue: isize) -> Option<bool> {
+        self.inner.borrow().queues.get(&queue).map(|queue| queue.is_locked)
+    }
+
+    //获取已执行的任务数量
+    pub fn executed(&self) -> usize {
+        self.inner.borrow().executed
+    }
+
+    //获取已执行的任务信息，按执行顺序排列
+    pub fn infos(&self) -> Vec<Atom> {
+        self.inner.borrow().infos.clone()
+    }
+
+    //执行一个可以立即执行的任务，没有可以立即执行的任务返回false
+    pub fn run_once(&self) -> bool {
+        let (lock, task) = {
+            let mut state = self.inner.borrow_mut();
+
+            //在全局任务和未锁住的任务队列的队首任务中，选择优先级最高的任务，优先级相同时选择投递序号最小的任务
+            //全局任务不在任务队列中，以全局任务的位置作为选择结果，任务队列则以任务队列id作为选择结果
+            let mut next: Option<(usize, usize, Result<usize, isize>)> = None;
+            for (index, task) in state.globals.iter().enumerate() {
+                if next.map(|(priority, seq, _)| task.is_before(priority, seq)).unwrap_or(true) {
+                    next = Some((task.priority, task.seq, Ok(index)));
+                }
+            }
+            for (id, queue) in state.queues.iter() {
+                if queue.is_locked {
+                    continue;
+                }
+
+                if let Some(task) = queue.tasks.front() {
+                    if next.map(|(priority, seq, _)| task.is_before(priority, seq)).unwrap_or(true) {
+                        next = Some((task.priority, task.seq, Err(*id)));
+                    }
+                }
+            }
+
+            match next {
+                None => return false,
+                Some((_, _, Ok(index))) => (None, state.globals.remove(index).unwrap()),
+                Some((_, _, Err(id))) => {
+                    //从任务队列中取出任务时锁住任务队列，由任务在完成后解锁
+                    let queue = state.queues.get_mut(&id).unwrap();
+                    queue.is_locked = true;
+                    (Some(id), queue.tasks.pop_front().unwrap())
+                },
+            }
+        };
+
+        //执行任务时不持有状态的借用，以允许任务继续投递任务
+        let TestTask { func, info, .. } = task;
+        func(lock);
+
+        let mut state = self.inner.borrow_mut();
+        state.executed += 1;
+        state.infos.push(info);
+        for (vm, statuses) in state.watches.iter_mut() {
+            let status = vm.status();
+            if statuses.last() != Some(&status) {
+                statuses.push(status);
+            }
+        }
+        true
+    }
+
+    //执行所有可以立即执行的任务，包括执行期间投递的任务，直到没有可以立即执行的任务，返回执行的任务数量
+    pub fn run_until_idle(&self) -> usize {
+        let mut count = 0;
+        while self.run_once() {
+            count += 1;
+        }
+        count
+    }
+
+    //推进虚拟时间，按到期时间顺序执行到期的延迟任务和所有可以立即执行的任务，返回执行的任务数量
+    pub fn advance(&self, duration: Duration) -> usize {
+        let target = self.now() + duration;
+        let mut count = self.run_until_idle();
+        loop {
+            {
+                let mut state = self.inner.borrow_mut();
+                let due = match state.delays.keys().next() {
+                    Some(key) if key.0 <= target => *key,
+                    _ => break,
+                };
+
+                //延迟任务到期，则推进虚拟时间到到期时间，并以新的投递序号投递任务
+                let DelayTask { queue, mut task } = state.delays.remove(&due).unwrap();
+                state.now = due.0;
+                state.seq += 1;
+                task.seq = state.seq;
+                state.push(queue, task);
+            }
+            count += self.run_until_idle();
+        }
+
+        self.inner.borrow_mut().now = target;
+        count
+    }
+
+    //观察指定虚拟机的状态变化，每个任务执行完成后记录与上次不同的虚拟机状态
+    pub fn watch(&self, vm: Arc<JS>) {
+        let status = vm.status();
+        self.inner.borrow_mut().watches.push((vm, vec![status]));
+    }
+
+    //获取观察的虚拟机的状态变化列表，从旧到新排列，未观察返回空
+    pub fn transitions(&self, vm: &JS) -> Option<Vec<JSStatus>> {
+        self.inner.borrow().watches.iter().find(|(watched, _)| &**watched as *const JS == vm as *const JS).map(|(_, statuses)| statuses.clone())
+    }
+
+    //断言观察的虚拟机的状态变化与指定的状态变化相同
+    pub fn assert_transitions(&self, vm: &JS, expected: &[JSStatus]) {
+        match self.transitions(vm) {
+            None => panic!("vm not watched, vm: {}", vm.get_id()),
+            Some(statuses) => {
+                assert_eq!(statuses.as_slice(), expected, "vm status transitions mismatch, vm: {}", vm.get_id());
+            },
+        }
+    }
+
+    //断言观察的虚拟机的当前状态
+    pub fn assert_status(&self, vm: &JS, expected: JSStatus) {
+        assert_eq!(vm.status(), expected, "vm status mismatch, vm: {}", vm.get_id());
+    }
+
+    //创建任务队列
+    pub(crate) fn create_queue(&self) -> isize {
+        let mut state = self.inner.borrow_mut();
+        state.queue_id += 1;
+        let id = state.queue_id;
+        state.queues.insert(id, TestQueue {
+            is_locked: false,
+            tasks: VecDeque::new(),
+        });
+        id
+    }
+
+    //移除任务队列，未执行的任务将被丢弃
+    pub(crate) fn remove_queue(&self, queue: isize) -> bool {
+        self.inner.borrow_mut().queues.remove(&queue).is_some()
+    }
+
+    //锁住任务队列
+    pub(crate) fn lock_queue(&self, queue: isize) -> bool {
+        match self.inner.borrow_mut().queues.get_mut(&queue) {
+            None => false,
+            Some(queue) => {
+                queue.is_locked = true;
+                true
+            },
+        }
+    }
+
+    //解锁任务队列
+    pub(crate) fn unlock_queue(&self, queue: isize) -> bool {
+        match self.inner.borrow_mut().queues.get_mut(&queue) {
+            None => false,
+            Some(queue) => {
+                queue.is_locked = false;
+                true
+            },
+        }
+    }
+
+    //取消未到期的延迟任务，成功返回true
+    pub(crate) fn cancel_delay(&self, handle: isize) -> bool {
+        let mut state = self.inner.borrow_mut();
+        let key = state.delays.keys().find(|(_, seq)| *seq as isize == handle).cloned();
+        match key {
+            None => false,
+            Some(key) => state.delays.remove(&key).is_some(),
+        }
+    }
+
+    //投递任务，指定了延迟时长则在虚拟时间推进到到期时间后投递，返回等待执行的任务数量，延迟任务返回唯一的任务句柄，任务队列不存在返回空
+    pub(crate) fn cast(&self,
+                       _task_type: TaskType,
+                       priority: usize,
+                       queue: Option<isize>,
+                       func: Box<FnOnce(Option<isize>)>,
+                       timeout: Option<u32>,
+                       info: Atom) -> Option<isize> {
+        let mut state = self.inner.borrow_mut();
+        if let Some(id) = queue {
+            if !state.queues.contains_key(&id) {
+                return None;
+            }
+        }
+
+        state.seq += 1;
+        let task = TestTask {
+            seq: state.seq,
+            priority,
+            func,
+            info,
+        };
+        match timeout {
+            None => Some(state.push(queue, task) as isize),
+            Some(timeout) => {
+                let due = state.now + Duration::from_millis(timeout as u64);
+                //使用单调递增的投递序号作为延迟任务的句柄
+                let seq = task.seq;
+                state.delays.insert((due, seq), DelayTask { queue, task });
+                Some(seq as isize)
+            },
+        }
+    }
+}
+
+impl ExecutorState {
+    //将任务加入全局任务或指定的任务队列，任务队列已移除则丢弃任务，返回等待执行的任务数量
+    fn push(&mut self, queue: Option<isize>, task: TestTask) -> usize {
+        match queue {
+            None => {
+                self.globals.push_back(task);
+                self.globals.len()
+            },
+            Some(id) => {
+                match self.queues.get_mut(&id) {
+                    None => 0,
+                    Some(queue) => {
+                        queue.tasks.push_back(task);
+                        queue.tasks.len()
+                    },
+                }
+            },
+        }
+    }
+}
@@ -0,0 +1,350 @@
+use std::thread;
+use std::sync::Arc;
+use std::time::Duration;
+use std::io::{Read, Write, Result};
+use std::net::{TcpListener, TcpStream, SocketAddr};
+
+use worker::impls::{js_static_sync_task_size, js_dyn_sync_task_size, js_static_async_task_size, js_dyn_async_task_size};
+use apm::allocator::{get_max_alloced_limit, vm_alloced_size, all_alloced_size};
+
+use adapter::{VM_FACTORY_REGISTERS, VM_INIT_PANIC_COUNT, VM_RUN_PANIC_COUNT, VM_WAIT_BLOCK_COUNT, VM_FINISH_TASK_COUNT, VM_POP_CALLBACK_COUNT, VM_HEAP_LIMIT_COUNT, now_utc};
+use native_object_impl::{VM_SYNC_CALL_COUNT, VM_BLOCK_CALL_COUNT};
+use pi_vm_impl::{VMFactory, VM_COUNT, VM_NEW_TIME, VM_LOAD_TIME, VM_CLONE_TIME, VM_LEAK_COUNT, VM_CLONE_FAILED_COUNT, VM_QUEUE_SWEEP_COUNT,
+                 VM_CALL_COUNT, VM_OTHER_TENANT_CALL_COUNT, VM_PUSH_CALLBACK_COUNT, VM_ASYNC_REQUEST_COUNT, queue_size, async_request_size};
+use metrics::{self, HistogramSnapshot};
+use shell::SHELL_MANAGER;
+use proc_pool::status_counts;
+use heap::escape_json;
+
+/*
+* 指标服务读取请求的超时时长，单位ms
+*/
+const METRICS_READ_TIMEOUT: u64 = 1000;
+
+/*
+* 指标的样本
+*/
+struct Sample {
+    suffix: &'static str,                   //样本名后缀，只用于直方图
+    labels: Vec<(&'static str, String)>,    //样本标签
+    value:  String,                         //样本值
+}
+
+/*
+* 同名指标的样本集合
+*/
+struct Family {
+    name:       &'static str,   //指标名
+    help:       &'static str,   //指标说明
+    kind:       &'static str,   //指标类型
+    samples:    Vec<Sample>,    //样本列表
+}
+
+impl Family {
+    //构建同名指标的样本集合
+    fn new(name: &'static str, kind: &'static str, help: &'static str) -> Self {
+        Family {
+            name,
+            help,
+            kind,
+            samples: Vec::new(),
+        }
+    }
+
+    //增加一个样本
+    fn push<V: ToString>(&mut self, labels: Vec<(&'static str, String)>, value: V) {
+        self.samples.push(Sample {
+            suffix: "",
+            labels,
+            value: value.to_string(),
+        });
+    }
+
+    //增加一个延迟直方图的所有样本，延迟单位为秒
+    fn push_histogram(&mut self, labels: Vec<(&'static str, String)>, histogram: &HistogramSnapshot) {
+        let mut total = 0;
+        for (index, count) in histogram.counts().iter().enumerate() {
+            total += count;
+            let mut bucket_labels = labels.clone();
+            match histogram.bounds().get(index) {
+                None => bucket_labels.push(("le", "+Inf".to_string())),
+                Some(bound) => bucket_labels.push(("le", (*bound as f64 / 1000000.0).to_string())),
+            }
+            self.samples.push(Sample {
+                suffix: "_bucket",
+                labels: bucket_labels,
+                value: total.to_string(),
+            });
+        }
+        self.samples.push(Sample {
+            suffix: "_sum",
+            labels: labels.clone(),
+            value: (histogram.sum() as f64 / 1000000.0).to_string(),
+        });
+        self.samples.push(Sample {
+            suffix: "_count",
+            labels,
+            value: histogram.count().to_string(),
+        });
+    }
+}
+
+//获取pi_vm所有指标的Prometheus文本格式
+pub fn render_prometheus() -> String {
+    let mut text = String::new();
+    for family in collect() {
+        text.push_str(&format!("# HELP {} {}\n", family.name, family.help));
+        text.push_str(&format!("# TYPE {} {}\n", family.name, family.kind));
+        for sample in family.samples {
+            text.push_str(family.name);
+            text.push_str(sample.suffix);
+            if !sample.labels.is_empty() {
+                let labels: Vec<String> = sample.labels.iter().map(|(key, value)| {
+                    format!("{}=\"{}\"", key, escape_label(value))
+                }).collect();
+                text.push_str(&format!("{{{}}}", labels.join(",")));
+            }
+            text.push_str(&format!(" {}\n", sample.value));
+        }
+    }
+    text
+}
+
+//获取pi_vm所有指标的json格式
+pub fn render_json() -> String {
+    let families: Vec<String> = collect().into_iter().map(|family| {
+        let samples: Vec<String> = family.samples.iter().map(|sample| {
+            let labels: Vec<String> = sample.labels.iter().map(|(key, value)| {
+                format!("\"{}\":\"{}\"", key, escape_json(value))
+            }).collect();
+            format!("{{\"name\":\"{}{}\",\"labels\":{{{}}},\"value\":{}}}", family.name, sample.suffix, labels.join(","), sample.value)
+        }).collect();
+        format!("{{\"name\":\"{}\",\"type\":\"{}\",\"help\":\"{}\",\"samples\":[{}]}}", family.name, family.kind, escape_json(family.help), samples.join(","))
+    }).collect();
+
+    format!("{{\"time\":{},\"metrics\":[{}]}}", now_utc(), families.join(","))
+}
+
+//在本地指定端口上启动指标服务，端口为0则由系统分配，GET /metrics返回Prometheus文本格式，GET /metrics/json返回json格式，返回监听的地址
+//每个连接在独立的线程中处理，以保证慢连接不会阻塞其它连接
+pub fn listen_metrics(port: u16) -> Result<SocketAddr> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    let addr = listener.local_addr()?;
+
+    thread::Builder::new().name("pi_vm metrics".to_string()).spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Err(e) => {
+                    warn!("!!!> Vm Metrics Accept Error, addr: {:?}, e: {:?}", addr, e);
+                },
+                Ok(stream) => {
+                    let spawned = thread::Builder::new().name("pi_vm metrics conn".to_string()).spawn(move || {
+                        if let Err(e) = handle_request(stream) {
+                            warn!("!!!> Vm Metrics Response Error, addr: {:?}, e: {:?}", addr, e);
+                        }
+                    });
+                    if let Err(e) = spawned {
+                        warn!("!!!> Vm Metrics Spawn Error, addr: {:?}, e: {:?}", addr, e);
+                    }
+                },
+            }
+        }
+    })?;
+
+    info!("===> Vm Metrics Listen Ok, addr: {:?}", addr);
+    Ok(addr)
+}
+
+//处理指标服务的请求，每个连接只处理一个请求
+fn handle_request(mut stream: TcpStream) -> Result<()> {
+    stream.set_read_timeout(Some(Duration::from_millis(METRICS_READ_TIMEOUT)))?;
+    let mut buf = [0u8; 1024];
+    let len = stream.read(&mut buf)?;
+    let request = String::from_utf8_lossy(&buf[..len]);
+    let mut line = request.lines().next().unwrap_or("").split_whitespace();
+    let (status, content_type, body) = match (line.next(), line.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", "text/plain; version=0.0.4", render_prometheus()),
+        (Some("GET"), Some("/metrics/json")) => ("200 OK", "application/json", render_json()),
+        _ => ("404 Not Found", "text/plain", "not found\n".to_string()),
+    };
+
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+           status, content_type, body.len(), body)?;
+    stream.flush()
+}
+
+//转义Prometheus标签值
+fn escape_label(s: &str) -> String {
+    s.replace("\\", "\\\\").replace("\"", "\\\"").replace("\n", "\\n")
+}
+
+//收集pi_vm的所有指标
+fn collect() -> Vec<Family> {
+    let mut families = Vec::new();
+
+    //全局指标
+    let mut family = Family::new("pi_vm_alloced_bytes", "gauge", "Allocated memory of the current process.");
+    family.push(vec![("type", "all".to_string())], all_alloced_size());
+    family.push(vec![("type", "vm".to_string())], vm_alloced_size());
+    family.push(vec![("type", "limit".to_string())], get_max_alloced_limit());
+    families.push(family);
+
+    let mut family = Family::new("pi_vm_js_task_queue_len", "gauge", "Length of the js worker task queues.");
+    family.push(vec![("type", "static_sync".to_string())], js_static_sync_task_size());
+    family.push(vec![("type", "dyn_sync".to_string())], js_dyn_sync_task_size());
+    family.push(vec![("type", "static_async".to_string())], js_static_async_task_size());
+    family.push(vec![("type", "dyn_async".to_string())], js_dyn_async_task_size());
+    families.push(family);
+
+    let mut family = Family::new("pi_vm_source_queue_count", "gauge", "Number of source sync task queues.");
+    family.push(vec![], queue_size());
+    families.push(family);
+
+    let mut family = Family::new("pi_vm_shell_count", "gauge", "Number of open shells.");
+    family.push(vec![], SHELL_MANAGER.read().unwrap().size());
+    families.push(family);
+
+    let mut family = Family::new("pi_vm_async_request_handler_count", "gauge", "Number of registered async request handlers.");
+    family.push(vec![], async_request_size());
+    families.push(family);
+
+    let mut family = Family::new("pi_vm_process_count", "gauge", "Number of registered processes by status.");
+    for (status, count) in status_counts() {
+        family.push(vec![("status", format!("{:?}", status))], count);
+    }
+    families.push(family);
+
+    //apm的全局计数器和计时器，计时器为累计时长，单位us
+    let globals = [
+        ("pi_vm_vm_init_panic_count_total", "Total vm init panics.", VM_INIT_PANIC_COUNT.get()),
+        ("pi_vm_vm_run_panic_count_total", "Total vm run panics.", VM_RUN_PANIC_COUNT.get()),
+        ("pi_vm_vm_wait_block_count_total", "Total finished tasks in the wait block status.", VM_WAIT_BLOCK_COUNT.get()),
+        ("pi_vm_vm_finish_task_count_total", "Total finished vm tasks.", VM_FINISH_TASK_COUNT.get()),
+        ("pi_vm_vm_pop_callback_count_total", "Total popped async callbacks.", VM_POP_CALLBACK_COUNT.get()),
+        ("pi_vm_vm_heap_limit_count_total", "Total vm heap limit exceeds.", VM_HEAP_LIMIT_COUNT.get()),
+        ("pi_vm_vm_sync_call_count_total", "Total native sync calls.", VM_SYNC_CALL_COUNT.get()),
+        ("pi_vm_vm_block_call_count_total", "Total native block calls.", VM_BLOCK_CALL_COUNT.get()),
+        ("pi_vm_vm_count_total", "Total created vms.", VM_COUNT.get()),
+        ("pi_vm_vm_new_time_total", "Total time of creating vms in microseconds.", VM_NEW_TIME.get()),
+        ("pi_vm_vm_load_time_total", "Total time of loading vms in microseconds.", VM_LOAD_TIME.get()),
+        ("pi_vm_vm_clone_time_total", "Total time of cloning vms in microseconds.", VM_CLONE_TIME.get()),
+        ("pi_vm_vm_leak_count_total", "Total leaked vms.", VM_LEAK_COUNT.get()),
+        ("pi_vm_vm_clone_failed_count_total", "Total failed vm clones.", VM_CLONE_FAILED_COUNT.get()),
+        ("pi_vm_vm_queue_sweep_count_total", "Total swept idle source queues.", VM_QUEUE_SWEEP_COUNT.get()),
+        ("pi_vm_vm_call_count_total", "Total vm calls.", VM_CALL_COUNT.get()),
+        ("pi_vm_vm_call_count_other_tenant_total", "Total vm calls of the tenants over the limit.", VM_OTHER_TENANT_CALL_COUNT.get()),
+        ("pi_vm_vm_push_callback_count_total", "Total pushed async callbacks.", VM_PUSH_CALLBACK_COUNT.get()),
+        ("pi_vm_vm_async_request_count_total", "Total async requests.", VM_ASYNC_REQUEST_COUNT.get()),
+    ];
+    for &(name, help, value) in globals.iter() {
+        let mut family = Family::new(name, "counter", help);
+        family.push(vec![], value);
+        families.push(family);
+    }
+
+    //虚拟机工厂的当前状态
+    let mut factories: Vec<(String, Arc<VMFactory>)> = VM_FACTORY_REGISTERS.read().unwrap().iter().map(|(name, factory)| {
+        (name.clone(), factory.clone())
+    }).collect();
+    factories.sort_by(|x, y| x.0.cmp(&y.0));
+
+    let mut family = Family::new("pi_vm_factory_count", "gauge", "Number of registered vm factories.");
+    family.push(vec![], factories.len());
+    families.push(family);
+
+    let mut gauges = vec![
+        Family::new("pi_vm_factory_size", "gauge", "Number of vms in the factory."),
+        Family::new("pi_vm_factory_free_pool", "gauge", "Number of free vms in the factory pool."),
+        Family::new("pi_vm_factory_free_buf", "gauge", "Number of free vms in the factory buffer."),
+        Family::new("pi_vm_factory_queue_len", "gauge", "Length of the factory task queue."),
+        Family::new("pi_vm_factory_queue_capacity", "gauge", "Capacity of the factory task queue, 0 is unlimited."),
+        Family::new("pi_vm_factory_limit_capacity", "gauge", "Limit capacity of the factory."),
+        Family::new("pi_vm_factory_scheduling_count", "gauge", "Scheduling count of the factory since the last reset."),
+        Family::new("pi_vm_factory_session_count", "gauge", "Number of pinned sessions of the factory."),
+        Family::new("pi_vm_factory_heap_size_bytes", "gauge", "Heap size of the factory vms."),
+        Family::new("pi_vm_factory_max_heap_size_bytes", "gauge", "Max heap size of the factory vms."),
+        Family::new("pi_vm_factory_heap_limit_bytes", "gauge", "Heap limit of the factory vms, 0 is unlimited."),
+    ];
+    for (name, factory) in &factories {
+        let values = [
+            factory.size(),
+            factory.free_pool_size(),
+            factory.free_buf_size(),
+            factory.queue_len(),
+            factory.queue_capacity(),
+            factory.limit_capacity(),
+            factory.scheduling_count(),
+            factory.session_size(),
+            factory.heap_size(),
+            factory.max_heap_size(),
+            factory.heap_limit(),
+        ];
+        for (family, value) in gauges.iter_mut().zip(values.iter()) {
+            family.push(vec![("factory", name.clone())], value);
+        }
+    }
+    families.append(&mut gauges);
+
+    //虚拟机工厂和进程工厂的累计指标
+    let snapshot = metrics::snapshot();
+    let mut counters = vec![
+        Family::new("pi_vm_factory_calls_total", "counter", "Total calls of the factory."),
+        Family::new("pi_vm_factory_finish_tasks_total", "counter", "Total finished tasks of the factory."),
+        Family::new("pi_vm_factory_run_panics_total", "counter", "Total run panics of the factory."),
+        Family::new("pi_vm_factory_refuses_total", "counter", "Total refused tasks of the factory."),
+        Family::new("pi_vm_factory_vms_created_total", "counter", "Total created vms of the factory."),
+        Family::new("pi_vm_factory_vm_load_failures_total", "counter", "Total failed vm creations or loads of the factory."),
+        Family::new("pi_vm_factory_vm_init_panics_total", "counter", "Total vm init panics of the factory."),
+        Family::new("pi_vm_factory_vms_thrown_total", "counter", "Total thrown vms of the factory."),
+    ];
+    let mut histograms = vec![
+        Family::new("pi_vm_factory_load_seconds", "histogram", "Latency of creating and loading a vm."),
+        Family::new("pi_vm_factory_queue_wait_seconds", "histogram", "Latency from call to execution start."),
+        Family::new("pi_vm_factory_execution_seconds", "histogram", "Latency of the synchronous execution."),
+    ];
+    for factory in snapshot.factories() {
+        let values = [
+            factory.call_count(),
+            factory.finish_task_count(),
+            factory.run_panic_count(),
+            factory.refuse_count(),
+            factory.new_count(),
+            factory.load_failed_count(),
+            factory.init_panic_count(),
+            factory.throw_count(),
+        ];
+        for (family, value) in counters.iter_mut().zip(values.iter()) {
+            family.push(vec![("factory", factory.name().to_string())], value);
+        }
+
+        let values = [factory.load_time(), factory.queue_wait(), factory.execution()];
+        for (family, histogram) in histograms.iter_mut().zip(values.iter()) {
+            family.push_histogram(vec![("factory", factory.name().to_string())], histogram);
+        }
+    }
+    families.append(&mut counters);
+    families.append(&mut histograms);
+
+    let mut counters = vec![
+        Family::new("pi_vm_process_spawns_total", "counter", "Total spawned processes of the process factory."),
+        Family::new("pi_vm_process_spawn_failures_total", "counter", "Total failed spawns of the process factory."),
+        Family::new("pi_vm_process_closes_total", "counter", "Total closed processes of the process factory."),
+        Family::new("pi_vm_process_sends_total", "counter", "Total sent messages of the process factory."),
+        Family::new("pi_vm_process_throws_total", "counter", "Total thrown exceptions of the process factory."),
+    ];
+    for process in snapshot.processes() {
+        let values = [
+            process.spawn_count(),
+            process.spawn_failed_count(),
+            process.close_count(),
+            process.send_count(),
+            process.throw_count(),
+        ];
+        for (family, value) in counters.iter_mut().zip(values.iter()) {
+            family.push(vec![("factory", process.name().to_string())], value);
+        }
+    }
+    families.append(&mut counters);
+
+    families
+}
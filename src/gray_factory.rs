@@ -0,0 +1,251 @@
+use std::sync::Arc;
+use std::time::Duration;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use rand::{thread_rng, Rng};
+use parking_lot::RwLock;
+
+use atom::Atom;
+use gray::GrayVersion;
+use adapter::JS;
+use pi_vm_impl::{VMFactory, CallOptions, RefuseReason, ShutdownReport};
+
+lazy_static! {
+    //灰度虚拟机工厂注册表
+    static ref GRAY_FACTORY_REGISTERS: RwLock<HashMap<String, Arc<GrayFactory>>> = RwLock::new(HashMap::new());
+}
+
+/*
+* 源哈希使用的FNV-1a偏移量和质数，保证不同进程和版本中相同的源总是得到相同的哈希值
+*/
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/*
+* 灰度路由规则
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrayRule {
+    Percent(usize),     //按百分比随机路由到灰度版本
+    SourceHash(usize),  //按源的哈希值的百分比路由到灰度版本，相同的源总是路由到相同的版本，源为0则路由到稳定版本
+    Explicit,           //只路由调用选项中明确指定了灰度版本的调用
+}
+
+/*
+* 灰度虚拟机工厂的版本
+*/
+struct FactoryVersion {
+    factory:    Arc<VMFactory>,     //版本的虚拟机工厂
+    count:      Arc<AtomicUsize>,   //版本的调用次数
+}
+
+/*
+* 灰度虚拟机工厂，在同一个逻辑名下注册多个版本的虚拟机工厂，并根据灰度路由规则将调用路由到稳定版本或灰度版本，
+* 不同版本的虚拟机工厂名必须不同
+*/
+pub struct GrayFactory {
+    name:       Atom,                                   //灰度虚拟机工厂的逻辑名
+    versions:   RwLock<HashMap<usize, FactoryVersion>>, //版本表
+    stable:     AtomicUsize,                            //稳定版本
+    gray:       RwLock<Option<(usize, GrayRule)>>,      //灰度版本和灰度路由规则，为空表示没有灰度版本
+}
+
+impl GrayFactory {
+    //构建灰度虚拟机工厂，并注册指定的稳定版本
+    pub fn new(name: &str, stable: usize, factory: Arc<VMFactory>) -> Self {
+        let mut versions = HashMap::new();
+        versions.insert(stable, FactoryVersion {
+            factory,
+            count: Arc::new(AtomicUsize::new(0)),
+        });
+
+        GrayFactory {
+            name: Atom::from(name),
+            versions: RwLock::new(versions),
+            stable: AtomicUsize::new(stable),
+            gray: RwLock::new(None),
+        }
+    }
+
+    //获取灰度虚拟机工厂的逻辑名
+    pub fn name(&self) -> String {
+        (&self.name).to_string()
+    }
+
+    //增加指定版本的虚拟机工厂，版本已存在则返回false
+    pub fn add_version(&self, version: usize, factory: Arc<VMFactory>) -> bool {
+        let mut versions = self.versions.write();
+        if versions.contains_key(&version) {
+            return false;
+        }
+
+        versions.insert(version, FactoryVersion {
+            factory,
+            count: Arc::new(AtomicUsize::new(0)),
+        });
+        true
+    }
+
+    //获取所有版本，从小到大排列
+    pub fn versions(&self) -> Vec<usize> {
+        let mut versions: Vec<usize> = self.versions.read().keys().cloned().collect();
+        versions.sort();
+        versions
+    }
+
+    //获取指定版本的虚拟机工厂
+    pub fn factory(&self, version: usize) -> Option<Arc<VMFactory>> {
+        self.versions.read().get(&version).map(|v| v.factory.clone())
+    }
+
+    //获取指定版本的调用次数
+    pub fn call_count(&self, version: usize) -> usize {
+        self.versions.read().get(&version).map(|v| v.count.load(Ordering::Relaxed)).unwrap_or(0)
+    }
+
+    //获取稳定版本
+    pub fn stable_version(&self) -> usize {
+        self.stable.load(Ordering::SeqCst)
+    }
+
+    //获取灰度版本
+    pub fn gray_version(&self) -> Option<usize> {
+        self.gray.read().map(|(version, _)| version)
+    }
+
+    //获取灰度路由规则
+    pub fn gray_rule(&self) -> Option<GrayRule> {
+        self.gray.read().map(|(_, rule)| rule)
+    }
+
+    //设置灰度版本和灰度路由规则，版本不存在或为稳定版本则返回false，与移除版本互斥
+    pub fn set_gray(&self, version: usize, rule: GrayRule) -> bool {
+        let mut gray = self.gray.write();
+        if version == self.stable_version() || !self.versions.read().contains_key(&version) {
+            return false;
+        }
+
+        *gray = Some((version, rule));
+        info!("===> Gray Factory Set Gray Ok, name: {:?}, stable: {}, gray: {}, rule: {:?}", self.name(), self.stable_version(), version, rule);
+        true
+    }
+
+    //将灰度版本提升为稳定版本，所有调用都将路由到新的稳定版本，返回原稳定版本，没有灰度版本则返回空
+    pub fn promote(&self) -> Option<usize> {
+        let mut gray = self.gray.write();
+        match gray.take() {
+            None => None,
+            Some((version, _)) => {
+                let old = self.stable.swap(version, Ordering::SeqCst);
+                info!("===> Gray Factory Promote Ok, name: {:?}, old: {}, stable: {}", self.name(), old, version);
+                Some(old)
+            },
+        }
+    }
+
+    //回滚灰度版本，所有调用都将路由到稳定版本，返回被回滚的灰度版本，没有灰度版本则返回空
+    pub fn rollback(&self) -> Option<usize> {
+        let version = self.gray.write().take().map(|(version, _)| version);
+        if let Some(version) = version {
+            info!("===> Gray Factory Rollback Ok, name: {:?}, stable: {}, gray: {}", self.name(), self.stable_version(), version);
+        }
+        version
+    }
+
+    //移除并关闭指定版本的虚拟机工厂，不允许移除稳定版本或灰度版本，与设置灰度版本互斥，会阻塞当前线程
+    pub fn retire(&self, version: usize, grace: Duration) -> Option<ShutdownReport> {
+        let removed = {
+            let gray = self.gray.write();
+            if version == self.stable_version() || gray.map(|(gray, _)| gray) == Some(version) {
+                return None;
+            }
+
+            self.versions.write().remove(&version)
+        };
+        removed.map(|v| v.factory.shutdown(grace))
+    }
+
+    //根据灰度和灰度路由规则，获取本次调用的版本和虚拟机工厂，灰度可以是调用选项或虚拟机通道
+    pub fn route<G: GrayVersion>(&self, target: &G) -> (usize, Arc<VMFactory>) {
+        //与设置灰度版本和移除版本保持相同的加锁顺序
+        let gray = *self.gray.read();
+        let versions = self.versions.read();
+        if let Some(version) = *target.get_gray() {
+            //调用明确指定了版本
+            if let Some(v) = versions.get(&version) {
+                return (version, v.factory.clone());
+            }
+        }
+
+        let stable = self.stable_version();
+        if let Some((version, rule)) = gray {
+            let is_gray = match rule {
+                GrayRule::Percent(percent) => thread_rng().gen_range(0, 100) < percent,
+                GrayRule::SourceHash(percent) => {
+                    match target.get_id() {
+                        0 => false,
+                        src => (source_hash(src) % 100) < percent as u64,
+                    }
+                },
+                GrayRule::Explicit => false,
+            };
+
+            if is_gray {
+                if let Some(v) = versions.get(&version) {
+                    return (version, v.factory.clone());
+                }
+            }
+        }
+
+        (stable, versions[&stable].factory.clone())
+    }
+
+    //根据源路由到指定版本的虚拟机工厂，并调用指定的js全局函数，任务被拒绝则返回拒绝原因
+    pub fn call(&self, src: Option<usize>, port: Atom, args: Box<FnOnce(Arc<JS>) -> usize>, info: Atom) -> Result<(), RefuseReason> {
+        let options = match src {
+            None => CallOptions::new(),
+            Some(src) => CallOptions::new().set_order_key(src),
+        };
+        self.call_with_options(options, port, args, info)
+    }
+
+    //根据调用选项路由到指定版本的虚拟机工厂，并使用调用选项调用指定的js全局函数，任务被拒绝则返回拒绝原因
+    //调用选项会记录路由到的版本，虚拟机在本次调用中发起的异步请求会继续路由到相同的版本
+    pub fn call_with_options(&self, mut options: CallOptions, port: Atom, args: Box<FnOnce(Arc<JS>) -> usize>, info: Atom) -> Result<(), RefuseReason> {
+        let (version, factory) = self.route(&options);
+        if let Some(v) = self.versions.read().get(&version) {
+            v.count.fetch_add(1, Ordering::Relaxed);
+        }
+
+        GrayVersion::set_gray(&mut options, Some(version));
+        factory.call_with_options(options, port, args, info)
+    }
+}
+
+//计算源的FNV-1a哈希值，与平台和进程无关
+fn source_hash(src: usize) -> u64 {
+    let mut hash = FNV_OFFSET_BASIS;
+    let mut src = src as u64;
+    for _ in 0..8 {
+        hash ^= src & 0xff;
+        hash = hash.wrapping_mul(FNV_PRIME);
+        src >>= 8;
+    }
+    hash
+}
+
+//注册灰度虚拟机工厂，已存在同名的灰度虚拟机工厂则替换，并返回被替换的灰度虚拟机工厂
+pub fn register_gray_factory(factory: Arc<GrayFactory>) -> Option<Arc<GrayFactory>> {
+    GRAY_FACTORY_REGISTERS.write().insert(factory.name(), factory)
+}
+
+//注销指定名称的灰度虚拟机工厂
+pub fn unregister_gray_factory(name: &str) -> Option<Arc<GrayFactory>> {
+    GRAY_FACTORY_REGISTERS.write().remove(name)
+}
+
+//获取指定名称的灰度虚拟机工厂
+pub fn get_gray_factory(name: &str) -> Option<Arc<GrayFactory>> {
+    GRAY_FACTORY_REGISTERS.read().get(name).cloned()
+}
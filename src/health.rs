@@ -0,0 +1,493 @@
+use std::fmt;
+use std::time::Duration;
+
+use apm::allocator::{get_max_alloced_limit, is_alloced_limit, all_alloced_size};
+
+use adapter::{VM_FACTORY_REGISTERS, now_utc, is_global_vm_heap_collect_stopped, global_vm_heap_collect_interval,
+              global_vm_heap_collect_register_time, global_vm_heap_collect_last_time};
+use proc::ProcStatus;
+use proc_pool::status_counts;
+use metrics;
+
+/*
+* 健康检查的状态，按严重程度从低到高排列
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum HealthStatus {
+    Pass,   //通过
+    Warn,   //警告，可以继续服务，但需要关注
+    Fail,   //失败，不应继续接收请求
+}
+
+impl fmt::Display for HealthStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HealthStatus::Pass => write!(f, "pass"),
+            HealthStatus::Warn => write!(f, "warn"),
+            HealthStatus::Fail => write!(f, "fail"),
+        }
+    }
+}
+
+/*
+* 健康检查的阈值，比率都是启动以来的累计比率，达到警告阈值则警告，达到失败阈值则失败
+*/
+#[derive(Debug, Clone)]
+pub struct HealthThresholds {
+    error_rate:         (f64, f64), //虚拟机工厂运行异常数量与调用数量的比率
+    init_panic_rate:    (f64, f64), //虚拟机工厂初始化异常数量与尝试构建数量的比率
+    refuse_rate:        (f64, f64), //虚拟机工厂拒绝任务数量与调用和拒绝数量之和的比率
+    queue_usage:        (f64, f64), //虚拟机工厂任务调度队列长度与容量的比率，容量无限制则忽略
+    collect_stale:      (u32, u32), //全局虚拟机整理未运行的时长与整理间隔的倍数
+    memory_usage:       f64,        //已分配内存与最大堆限制的比率，达到则警告，达到最大堆限制则失败
+    spawn_failed_rate:  (f64, f64), //进程生成失败数量与尝试生成数量的比率
+}
+
+impl Default for HealthThresholds {
+    fn default() -> Self {
+        HealthThresholds {
+            error_rate: (0.01, 0.1),
+            init_panic_rate: (0.01, 0.5),
+            refuse_rate: (0.01, 0.1),
+            queue_usage: (0.8, 1.0),
+            collect_stale: (3, 10),
+            memory_usage: 0.9,
+            spawn_failed_rate: (0.01, 0.1),
+        }
+    }
+}
+
+impl HealthThresholds {
+    //构建默认的健康检查阈值
+    pub fn new() -> Self {
+        HealthThresholds::default()
+    }
+
+    //设置虚拟机工厂运行异常比率的警告和失败阈值
+    pub fn set_error_rate(mut self, warn: f64, fail: f64) -> Self {
+        self.error_rate = (warn, fail);
+        self
+    }
+
+    //设置虚拟机工厂初始化异常比率的警告和失败阈值
+    pub fn set_init_panic_rate(mut self, warn: f64, fail: f64) -> Self {
+        self.init_panic_rate = (warn, fail);
+        self
+    }
+
+    //设置虚拟机工厂拒绝任务比率的警告和失败阈值
+    pub fn set_refuse_rate(mut self, warn: f64, fail: f64) -> Self {
+        self.refuse_rate = (warn, fail);
+        self
+    }
+
+    //设置虚拟机工厂任务调度队列使用率的警告和失败阈值
+    pub fn set_queue_usage(mut self, warn: f64, fail: f64) -> Self {
+        self.queue_usage = (warn, fail);
+        self
+    }
+
+    //设置全局虚拟机整理未运行时长的警告和失败倍数，至少为1
+    pub fn set_collect_stale(mut self, warn: u32, fail: u32) -> Self {
+        self.collect_stale = (warn.max(1), fail.max(1));
+        self
+    }
+
+    //设置已分配内存使用率的警告阈值
+    pub fn set_memory_usage(mut self, warn: f64) -> Self {
+        self.memory_usage = warn;
+        self
+    }
+
+    //设置进程生成失败比率的警告和失败阈值
+    pub fn set_spawn_failed_rate(mut self, warn: f64, fail: f64) -> Self {
+        self.spawn_failed_rate = (warn, fail);
+        self
+    }
+}
+
+/*
+* 单项健康检查
+*/
+#[derive(Debug, Clone)]
+pub struct HealthCheck {
+    name:       &'static str,   //检查项名
+    status:     HealthStatus,   //检查状态
+    message:    String,         //检查说明
+}
+
+impl fmt::Display for HealthCheck {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}] {}: {}", self.status, self.name, self.message)
+    }
+}
+
+impl HealthCheck {
+    //构建单项健康检查
+    fn new(name: &'static str, status: HealthStatus, message: String) -> Self {
+        HealthCheck {
+            name,
+            status,
+            message,
+        }
+    }
+
+    //根据比率和警告、失败阈值构建单项健康检查
+    fn with_rate(name: &'static str, rate: f64, (warn, fail): (f64, f64), message: String) -> Self {
+        let status = if rate >= fail {
+            HealthStatus::Fail
+        } else if rate >= warn {
+            HealthStatus::Warn
+        } else {
+            HealthStatus::Pass
+        };
+
+        HealthCheck::new(name, status, message)
+    }
+
+    //获取检查项名
+    pub fn name(&self) -> &str {
+        self.name
+    }
+
+    //获取检查状态
+    pub fn status(&self) -> HealthStatus {
+        self.status
+    }
+
+    //获取检查说明
+    pub fn message(&self) -> &str {
+        self.message.as_str()
+    }
+}
+
+/*
+* 虚拟机工厂的健康状况
+*/
+#[derive(Debug, Clone)]
+pub struct FactoryHealth {
+    name:               String,             //虚拟机工厂名
+    can_produce:        bool,               //是否可以获取或构建虚拟机
+    error_rate:         f64,                //运行异常比率
+    init_panic_rate:    f64,                //初始化异常比率
+    queue_len:          usize,              //任务调度队列长度
+    queue_capacity:     usize,              //任务调度队列容量，0表示无限制
+    refuse_count:       usize,              //拒绝任务数量
+    checks:             Vec<HealthCheck>,   //检查项列表
+}
+
+impl FactoryHealth {
+    //获取虚拟机工厂名
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    //判断是否可以获取或构建虚拟机
+    pub fn can_produce(&self) -> bool {
+        self.can_produce
+    }
+
+    //获取运行异常比率
+    pub fn error_rate(&self) -> f64 {
+        self.error_rate
+    }
+
+    //获取初始化异常比率
+    pub fn init_panic_rate(&self) -> f64 {
+        self.init_panic_rate
+    }
+
+    //获取任务调度队列长度
+    pub fn queue_len(&self) -> usize {
+        self.queue_len
+    }
+
+    //获取任务调度队列容量
+    pub fn queue_capacity(&self) -> usize {
+        self.queue_capacity
+    }
+
+    //获取拒绝任务数量
+    pub fn refuse_count(&self) -> usize {
+        self.refuse_count
+    }
+
+    //获取检查项列表
+    pub fn checks(&self) -> &[HealthCheck] {
+        self.checks.as_slice()
+    }
+
+    //获取虚拟机工厂的健康状态，为所有检查项中最严重的状态
+    pub fn status(&self) -> HealthStatus {
+        worst(&self.checks)
+    }
+}
+
+/*
+* 进程池的健康状况
+*/
+#[derive(Debug, Clone)]
+pub struct ProcessPoolHealth {
+    total:              usize,                      //进程总数
+    status_counts:      Vec<(ProcStatus, usize)>,   //每种运行状态的进程数量
+    spawn_count:        usize,                      //所有进程工厂的生成成功数量
+    spawn_failed_count: usize,                      //所有进程工厂的生成失败数量
+}
+
+impl ProcessPoolHealth {
+    //获取进程总数
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    //获取每种运行状态的进程数量
+    pub fn status_counts(&self) -> &[(ProcStatus, usize)] {
+        self.status_counts.as_slice()
+    }
+
+    //获取所有进程工厂的生成成功数量
+    pub fn spawn_count(&self) -> usize {
+        self.spawn_count
+    }
+
+    //获取所有进程工厂的生成失败数量
+    pub fn spawn_failed_count(&self) -> usize {
+        self.spawn_failed_count
+    }
+}
+
+/*
+* pi_vm的健康报告
+*/
+#[derive(Debug, Clone)]
+pub struct HealthReport {
+    time:                   usize,                  //生成报告的时间，单位us
+    factories:              Vec<FactoryHealth>,     //所有已注册的虚拟机工厂的健康状况，按名称排序
+    collect_alive:          bool,                   //全局虚拟机整理定时器是否存活
+    collect_last_time:      Option<usize>,          //全局虚拟机整理的最近运行时间，单位us
+    alloced_size:           usize,                  //已分配内存
+    alloced_limit:          usize,                  //最大堆限制
+    is_alloced_limit:       bool,                   //已分配内存是否已达最大堆限制
+    process_pool:           ProcessPoolHealth,      //进程池的健康状况
+    checks:                 Vec<HealthCheck>,       //全局检查项列表
+}
+
+impl fmt::Display for HealthReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "status: {}, time: {}", self.status(), self.time)?;
+        for check in &self.checks {
+            write!(f, "\n  {}", check)?;
+        }
+        for factory in &self.factories {
+            write!(f, "\n  factory {:?}: {}", factory.name, factory.status())?;
+            for check in &factory.checks {
+                write!(f, "\n    {}", check)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl HealthReport {
+    //获取生成报告的时间
+    pub fn time(&self) -> usize {
+        self.time
+    }
+
+    //获取所有已注册的虚拟机工厂的健康状况
+    pub fn factories(&self) -> &[FactoryHealth] {
+        self.factories.as_slice()
+    }
+
+    //获取指定虚拟机工厂的健康状况
+    pub fn factory(&self, name: &str) -> Option<&FactoryHealth> {
+        self.factories.iter().find(|factory| factory.name == name)
+    }
+
+    //判断全局虚拟机整理定时器是否存活
+    pub fn is_collect_alive(&self) -> bool {
+        self.collect_alive
+    }
+
+    //获取全局虚拟机整理的最近运行时间
+    pub fn collect_last_time(&self) -> Option<usize> {
+        self.collect_last_time
+    }
+
+    //获取已分配内存
+    pub fn alloced_size(&self) -> usize {
+        self.alloced_size
+    }
+
+    //获取最大堆限制
+    pub fn alloced_limit(&self) -> usize {
+        self.alloced_limit
+    }
+
+    //判断已分配内存是否已达最大堆限制
+    pub fn is_alloced_limit(&self) -> bool {
+        self.is_alloced_limit
+    }
+
+    //获取进程池的健康状况
+    pub fn process_pool(&self) -> &ProcessPoolHealth {
+        &self.process_pool
+    }
+
+    //获取全局检查项列表
+    pub fn checks(&self) -> &[HealthCheck] {
+        self.checks.as_slice()
+    }
+
+    //获取整体的健康状态，为全局检查项和所有虚拟机工厂中最严重的状态
+    pub fn status(&self) -> HealthStatus {
+        self.factories.iter().map(|factory| factory.status()).fold(worst(&self.checks), |x, y| x.max(y))
+    }
+
+    //判断是否就绪，整体的健康状态不是失败即为就绪
+    pub fn is_ready(&self) -> bool {
+        self.status() != HealthStatus::Fail
+    }
+}
+
+//使用默认阈值生成pi_vm的健康报告
+pub fn health() -> HealthReport {
+    health_with(&HealthThresholds::default())
+}
+
+//使用指定阈值生成pi_vm的健康报告
+pub fn health_with(thresholds: &HealthThresholds) -> HealthReport {
+    let now = now_utc();
+    let is_limit = is_alloced_limit();
+    let snapshot = metrics::snapshot();
+
+    //检查所有已注册的虚拟机工厂
+    let mut factories: Vec<FactoryHealth> = VM_FACTORY_REGISTERS.read().unwrap().values().map(|factory| {
+        let name = factory.name();
+        let (call_count, run_panic_count, new_count, load_failed_count, init_panic_count, refuse_count) = match snapshot.factory(&name) {
+            None => (0, 0, 0, 0, 0, 0),
+            Some(metrics) => (metrics.call_count(), metrics.run_panic_count(), metrics.new_count(), metrics.load_failed_count(),
+                              metrics.init_panic_count(), metrics.refuse_count()),
+        };
+        let free = factory.free_pool_size() + factory.free_buf_size();
+        let queue_len = factory.queue_len();
+        let queue_capacity = factory.queue_capacity();
+        let error_rate = rate(run_panic_count, call_count);
+        let init_panic_rate = rate(init_panic_count, new_count + load_failed_count);
+        let refuse_rate = rate(refuse_count, call_count + refuse_count);
+
+        //已关闭、从未成功构建过虚拟机、或没有空闲虚拟机且已达最大堆限制时，无法获取或构建虚拟机
+        let can_produce = !factory.is_closed()
+            && !(new_count == 0 && load_failed_count > 0)
+            && (free > 0 || !is_limit);
+
+        let mut checks = Vec::with_capacity(5);
+        checks.push(HealthCheck::new("produce",
+                                     if can_produce { HealthStatus::Pass } else { HealthStatus::Fail },
+                                     format!("closed: {}, size: {}, free: {}, created: {}, load failed: {}",
+                                             factory.is_closed(), factory.size(), free, new_count, load_failed_count)));
+        checks.push(HealthCheck::with_rate("error_rate", error_rate, thresholds.error_rate,
+                                           format!("run panics: {}, calls: {}", run_panic_count, call_count)));
+        checks.push(HealthCheck::with_rate("init_panic_rate", init_panic_rate, thresholds.init_panic_rate,
+                                           format!("init panics: {}, created: {}, load failed: {}", init_panic_count, new_count, load_failed_count)));
+        let queue_usage = if queue_capacity == 0 { 0.0 } else { queue_len as f64 / queue_capacity as f64 };
+        checks.push(HealthCheck::with_rate("queue", queue_usage, thresholds.queue_usage,
+                                           format!("len: {}, capacity: {}", queue_len, queue_capacity)));
+        checks.push(HealthCheck::with_rate("refuse_rate", refuse_rate, thresholds.refuse_rate,
+                                           format!("refused: {}, calls: {}", refuse_count, call_count)));
+
+        FactoryHealth {
+            name,
+            can_produce,
+            error_rate,
+            init_panic_rate,
+            queue_len,
+            queue_capacity,
+            refuse_count,
+            checks,
+        }
+    }).collect();
+    factories.sort_by(|x, y| x.name.cmp(&y.name));
+
+    let mut checks = Vec::with_capacity(3);
+
+    //检查全局虚拟机整理定时器，以最近运行时间或最近注册时间判断定时器是否存活
+    let collect_last_time = global_vm_heap_collect_last_time();
+    let collect_alive = match global_vm_heap_collect_interval() {
+        _ if is_global_vm_heap_collect_stopped() => {
+            checks.push(HealthCheck::new("collect_timer", HealthStatus::Fail, "stopped".to_string()));
+            false
+        },
+        None => {
+            checks.push(HealthCheck::new("collect_timer", HealthStatus::Warn, "not registered".to_string()));
+            false
+        },
+        Some(interval) => {
+            let last = collect_last_time.or_else(global_vm_heap_collect_register_time).unwrap_or(now);
+            let idle = Duration::from_micros(now.saturating_sub(last) as u64);
+            let interval = Duration::from_millis(interval as u64);
+            let (warn, fail) = thresholds.collect_stale;
+            let status = if idle >= interval * fail {
+                HealthStatus::Fail
+            } else if idle >= interval * warn {
+                HealthStatus::Warn
+            } else {
+                HealthStatus::Pass
+            };
+            checks.push(HealthCheck::new("collect_timer", status,
+                                         format!("interval: {:?}, idle: {:?}, last time: {:?}", interval, idle, collect_last_time)));
+            status != HealthStatus::Fail
+        },
+    };
+
+    //检查已分配内存
+    let alloced_size = all_alloced_size();
+    let alloced_limit = get_max_alloced_limit();
+    let status = if is_limit {
+        HealthStatus::Fail
+    } else if alloced_limit > 0 && alloced_size as f64 / alloced_limit as f64 >= thresholds.memory_usage {
+        HealthStatus::Warn
+    } else {
+        HealthStatus::Pass
+    };
+    checks.push(HealthCheck::new("memory", status, format!("alloced: {}, limit: {}", alloced_size, alloced_limit)));
+
+    //检查进程池
+    let counts = status_counts();
+    let total: usize = counts.iter().map(|(_, count)| count).sum();
+    let spawn_count: usize = snapshot.processes().iter().map(|process| process.spawn_count()).sum();
+    let spawn_failed_count: usize = snapshot.processes().iter().map(|process| process.spawn_failed_count()).sum();
+    checks.push(HealthCheck::with_rate("process_spawn", rate(spawn_failed_count, spawn_count + spawn_failed_count), thresholds.spawn_failed_rate,
+                                       format!("processes: {}, spawned: {}, spawn failed: {}", total, spawn_count, spawn_failed_count)));
+
+    HealthReport {
+        time: now,
+        factories,
+        collect_alive,
+        collect_last_time,
+        alloced_size,
+        alloced_limit,
+        is_alloced_limit: is_limit,
+        process_pool: ProcessPoolHealth {
+            total,
+            status_counts: counts,
+            spawn_count,
+            spawn_failed_count,
+        },
+        checks,
+    }
+}
+
+//计算比率，分母为0时比率为0
+fn rate(count: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 / total as f64
+    }
+}
+
+//获取检查项列表中最严重的状态，没有检查项则为通过
+fn worst(checks: &[HealthCheck]) -> HealthStatus {
+    checks.iter().map(|check| check.status).max().unwrap_or(HealthStatus::Pass)
+}
@@ -0,0 +1,344 @@
+use std::fs::{File, create_dir_all};
+use std::path::{Path, PathBuf};
+use std::io::{Write, BufWriter, Result, Error, ErrorKind};
+use std::sync::RwLock;
+
+use parking_lot::Mutex;
+
+use hash::XHashMap;
+
+use adapter::{JS, now_utc};
+
+/*
+* 堆统计时默认记录的最大对象数量
+*/
+const DEFAULT_LARGEST_OBJECT_COUNT: usize = 10;
+
+lazy_static! {
+    //等待导出的堆快照请求表，键为虚拟机名和虚拟机id，值为堆快照文件路径
+    static ref HEAP_SNAPSHOT_REQUESTS: Mutex<XHashMap<(String, usize), PathBuf>> = Mutex::new(XHashMap::default());
+    //自动导出堆快照的目录，为空表示不自动导出
+    static ref AUTO_HEAP_SNAPSHOT_DIR: RwLock<Option<PathBuf>> = RwLock::new(None);
+}
+
+/*
+* 堆对象类型
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeapObjectType {
+    Object = 0,
+    String,
+    Buffer,
+    Function,
+    Other,
+}
+
+impl From<u8> for HeapObjectType {
+    fn from(type_id: u8) -> Self {
+        match type_id {
+            0 => HeapObjectType::Object,
+            1 => HeapObjectType::String,
+            2 => HeapObjectType::Buffer,
+            3 => HeapObjectType::Function,
+            _ => HeapObjectType::Other,
+        }
+    }
+}
+
+impl HeapObjectType {
+    //获取类型名
+    pub fn name(&self) -> &'static str {
+        match self {
+            HeapObjectType::Object => "object",
+            HeapObjectType::String => "string",
+            HeapObjectType::Buffer => "buffer",
+            HeapObjectType::Function => "function",
+            HeapObjectType::Other => "other",
+        }
+    }
+}
+
+/*
+* 堆对象
+*/
+#[derive(Debug, Clone)]
+pub struct HeapObject {
+    id:     u64,            //对象id
+    ty:     HeapObjectType, //对象类型
+    size:   usize,          //对象大小，单位B
+    refs:   Vec<u64>,       //对象引用的对象id列表
+}
+
+impl HeapObject {
+    //获取对象id
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    //获取对象类型
+    pub fn object_type(&self) -> HeapObjectType {
+        self.ty
+    }
+
+    //获取对象大小
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    //获取对象引用的对象id列表
+    pub fn refs(&self) -> &[u64] {
+        self.refs.as_slice()
+    }
+}
+
+/*
+* 堆对象类型统计
+*/
+#[derive(Debug, Clone, Default)]
+pub struct HeapTypeStats {
+    count:  usize,  //对象数量
+    size:   usize,  //对象总大小，单位B
+}
+
+impl HeapTypeStats {
+    //获取对象数量
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    //获取对象总大小
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+/*
+* 虚拟机堆统计
+*/
+#[derive(Debug, Clone)]
+pub struct HeapStats {
+    vm_id:      usize,          //虚拟机id
+    name:       String,         //虚拟机名
+    heap_size:  usize,          //虚拟机堆大小
+    objects:    HeapTypeStats,  //对象统计
+    strings:    HeapTypeStats,  //字符串统计
+    buffers:    HeapTypeStats,  //缓冲区统计
+    functions:  HeapTypeStats,  //函数统计
+    others:     HeapTypeStats,  //其它类型统计
+    largest:    Vec<HeapObject>,//最大的对象列表，从大到小排列
+}
+
+impl HeapStats {
+    //统计指定虚拟机的堆，虚拟机必须空闲，返回空表示无法统计
+    pub fn from_vm(js: &JS) -> Option<Self> {
+        HeapStats::with_largest(js, DEFAULT_LARGEST_OBJECT_COUNT)
+    }
+
+    //统计指定虚拟机的堆，并记录指定数量的最大对象，虚拟机必须空闲，返回空表示无法统计
+    pub fn with_largest(js: &JS, count: usize) -> Option<Self> {
+        let mut stats = HeapStats {
+            vm_id: js.get_id(),
+            name: (&js.get_name()).to_string(),
+            heap_size: js.heap_size(),
+            objects: HeapTypeStats::default(),
+            strings: HeapTypeStats::default(),
+            buffers: HeapTypeStats::default(),
+            functions: HeapTypeStats::default(),
+            others: HeapTypeStats::default(),
+            largest: Vec::with_capacity(count + 1),
+        };
+
+        let is_ok = js.walk_heap(&mut |id, type_id, size, refs| {
+            let ty = HeapObjectType::from(type_id);
+            let type_stats = match ty {
+                HeapObjectType::Object => &mut stats.objects,
+                HeapObjectType::String => &mut stats.strings,
+                HeapObjectType::Buffer => &mut stats.buffers,
+                HeapObjectType::Function => &mut stats.functions,
+                HeapObjectType::Other => &mut stats.others,
+            };
+            type_stats.count += 1;
+            type_stats.size += size;
+
+            if count == 0 || (stats.largest.len() == count && stats.largest[count - 1].size >= size) {
+                //不需要记录最大对象，或比已记录的最大对象都小，则忽略
+                return;
+            }
+
+            //按大小插入，并移除超出数量的最小对象
+            let index = stats.largest.iter().position(|obj| obj.size < size).unwrap_or(stats.largest.len());
+            stats.largest.insert(index, HeapObject {
+                id,
+                ty,
+                size,
+                refs: refs.to_vec(),
+            });
+            stats.largest.truncate(count);
+        });
+
+        if is_ok {
+            Some(stats)
+        } else {
+            None
+        }
+    }
+
+    //获取虚拟机id
+    pub fn vm_id(&self) -> usize {
+        self.vm_id
+    }
+
+    //获取虚拟机名
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    //获取虚拟机堆大小
+    pub fn heap_size(&self) -> usize {
+        self.heap_size
+    }
+
+    //获取对象统计
+    pub fn objects(&self) -> &HeapTypeStats {
+        &self.objects
+    }
+
+    //获取字符串统计
+    pub fn strings(&self) -> &HeapTypeStats {
+        &self.strings
+    }
+
+    //获取缓冲区统计
+    pub fn buffers(&self) -> &HeapTypeStats {
+        &self.buffers
+    }
+
+    //获取函数统计
+    pub fn functions(&self) -> &HeapTypeStats {
+        &self.functions
+    }
+
+    //获取其它类型统计
+    pub fn others(&self) -> &HeapTypeStats {
+        &self.others
+    }
+
+    //获取最大的对象列表
+    pub fn largest(&self) -> &[HeapObject] {
+        self.largest.as_slice()
+    }
+}
+
+//导出指定虚拟机的堆快照到指定的json文件，虚拟机必须空闲，返回导出的对象数量
+pub fn write_heap_snapshot<P: AsRef<Path>>(js: &JS, path: P) -> Result<usize> {
+    let path = path.as_ref();
+    if let Some(dir) = path.parent() {
+        create_dir_all(dir)?;
+    }
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    write!(writer, "{{\"vm_id\":{},\"name\":\"{}\",\"heap_size\":{},\"time\":{},\"objects\":[",
+           js.get_id(), escape_json(&js.get_name()), js.heap_size(), now_utc())?;
+
+    let mut count = 0;
+    let mut result = Ok(());
+    let is_ok = js.walk_heap(&mut |id, type_id, size, refs| {
+        if result.is_err() {
+            //已写入失败，则忽略后续对象
+            return;
+        }
+
+        if count > 0 {
+            result = writer.write_all(b",");
+        }
+        result = result.and_then(|_| {
+            write!(writer, "{{\"id\":{},\"type\":\"{}\",\"size\":{},\"refs\":[", id, HeapObjectType::from(type_id).name(), size)
+        });
+        for (index, r) in refs.iter().enumerate() {
+            result = result.and_then(|_| {
+                if index == 0 {
+                    write!(writer, "{}", r)
+                } else {
+                    write!(writer, ",{}", r)
+                }
+            });
+        }
+        result = result.and_then(|_| writer.write_all(b"]}"));
+        count += 1;
+    });
+    result?;
+
+    if !is_ok {
+        return Err(Error::new(ErrorKind::Other, format!("write heap snapshot failed, vm: {:?}, reason: walk heap failed", js)));
+    }
+
+    writer.write_all(b"]}")?;
+    writer.flush()?;
+    Ok(count)
+}
+
+//请求导出指定虚拟机的堆快照，将在虚拟机下次空闲整理时导出到指定文件
+pub fn request_heap_snapshot<P: Into<PathBuf>>(name: &str, vm_id: usize, path: P) {
+    HEAP_SNAPSHOT_REQUESTS.lock().insert((name.to_string(), vm_id), path.into());
+}
+
+//取消导出指定虚拟机的堆快照，返回是否有等待导出的请求
+pub fn cancel_heap_snapshot(name: &str, vm_id: usize) -> bool {
+    HEAP_SNAPSHOT_REQUESTS.lock().remove(&(name.to_string(), vm_id)).is_some()
+}
+
+//设置自动导出堆快照的目录，虚拟机因堆过大被标记为等待丢弃时，会自动导出堆快照到此目录，为空则不自动导出
+pub fn set_auto_heap_snapshot_dir(dir: Option<PathBuf>) {
+    *AUTO_HEAP_SNAPSHOT_DIR.write().unwrap() = dir;
+}
+
+//导出指定虚拟机被请求的堆快照，只在虚拟机空闲时调用
+pub(crate) fn take_requested_snapshot(js: &JS) {
+    let path = {
+        let mut requests = HEAP_SNAPSHOT_REQUESTS.lock();
+        if requests.is_empty() {
+            return;
+        }
+
+        match requests.remove(&((&js.get_name()).to_string(), js.get_id())) {
+            None => return,
+            Some(path) => path,
+        }
+    };
+
+    match write_heap_snapshot(js, &path) {
+        Err(e) => warn!("!!!> Heap Snapshot Error, vm: {:?}, path: {:?}, e: {:?}", js, path, e),
+        Ok(count) => info!("===> Heap Snapshot Ok, vm: {:?}, path: {:?}, objects: {}", js, path, count),
+    }
+}
+
+//自动导出指定虚拟机的堆快照，只在虚拟机空闲时调用
+pub(crate) fn take_auto_snapshot(js: &JS) {
+    let dir = match &*AUTO_HEAP_SNAPSHOT_DIR.read().unwrap() {
+        None => return,
+        Some(dir) => dir.clone(),
+    };
+
+    let path = dir.join(format!("{}_{}_{}.heapsnapshot.json", (&js.get_name()).to_string(), js.get_id(), now_utc()));
+    match write_heap_snapshot(js, &path) {
+        Err(e) => warn!("!!!> Auto Heap Snapshot Error, vm: {:?}, path: {:?}, e: {:?}", js, path, e),
+        Ok(count) => info!("===> Auto Heap Snapshot Ok, vm: {:?}, path: {:?}, objects: {}", js, path, count),
+    }
+}
+
+//转义json字符串
+pub(crate) fn escape_json(s: &str) -> String {
+    let mut r = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => r.push_str("\\\""),
+            '\\' => r.push_str("\\\\"),
+            '\n' => r.push_str("\\n"),
+            '\r' => r.push_str("\\r"),
+            '\t' => r.push_str("\\t"),
+            c if (c as u32) < 0x20 => r.push_str(&format!("\\u{:04x}", c as u32)),
+            c => r.push(c),
+        }
+    }
+    r
+}
@@ -35,8 +35,29 @@ extern crate gray;
 extern crate hash;
 extern crate lfstack;
 extern crate parking_lot;
+extern crate once_cell;
+#[cfg(feature = "bytes")]
+extern crate bytes;
+#[cfg(feature = "tokio")]
+extern crate tokio;
 
 pub mod adapter;
+pub mod buffer;
+pub mod heap;
+pub mod reuse;
+pub mod scaling;
+pub mod affinity;
+pub mod gray_factory;
+pub mod metrics;
+pub mod exposition;
+pub mod event;
+pub mod trace;
+pub mod slow;
+pub mod health;
+pub mod scheduler;
+#[cfg(any(test, feature = "test-executor"))]
+pub mod executor;
+pub mod shutdown;
 pub mod native_object_impl;
 pub mod pi_vm_impl;
 pub mod bonmgr;
@@ -44,4 +65,7 @@ pub mod channel_map;
 pub mod shell;
 pub mod proc;
 pub mod proc_pool;
-pub mod duk_proc;
\ No newline at end of file
+pub mod duk_proc;
+
+pub use shutdown::shutdown;
+pub use health::health;
\ No newline at end of file
@@ -0,0 +1,519 @@
+use std::sync::Arc;
+use std::time::Duration;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use parking_lot::RwLock;
+
+use adapter::{VM_FACTORY_REGISTERS, now_utc};
+
+/*
+* 延迟直方图的桶上限，单位us，超过最大上限的记录在溢出桶中
+*/
+const LATENCY_BUCKETS: &'static [usize] = &[100, 500, 1000, 5000, 10000, 50000, 100000, 500000, 1000000, 5000000];
+
+lazy_static! {
+    //虚拟机工厂指标表，键为虚拟机工厂名
+    static ref VM_FACTORY_METRICS: RwLock<HashMap<String, Arc<FactoryMetrics>>> = RwLock::new(HashMap::new());
+    //进程工厂指标表，键为进程工厂名
+    static ref PROCESS_FACTORY_METRICS: RwLock<HashMap<String, Arc<ProcessMetrics>>> = RwLock::new(HashMap::new());
+}
+
+/*
+* 延迟直方图
+*/
+pub struct Histogram {
+    buckets:    Vec<AtomicUsize>,   //每个桶的记录数量，最后一个为溢出桶
+    count:      AtomicUsize,        //记录数量
+    sum:        AtomicUsize,        //记录的延迟总和，单位us
+}
+
+impl Histogram {
+    //构建延迟直方图
+    fn new() -> Self {
+        Histogram {
+            buckets: (0..LATENCY_BUCKETS.len() + 1).map(|_| AtomicUsize::new(0)).collect(),
+            count: AtomicUsize::new(0),
+            sum: AtomicUsize::new(0),
+        }
+    }
+
+    //记录一次延迟
+    pub fn record(&self, latency: Duration) {
+        let us = latency.as_micros() as usize;
+        let index = LATENCY_BUCKETS.iter().position(|bound| us <= *bound).unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(us, Ordering::Relaxed);
+    }
+
+    //获取延迟直方图的快照
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        HistogramSnapshot {
+            counts: self.buckets.iter().map(|bucket| bucket.load(Ordering::Relaxed)).collect(),
+            count: self.count.load(Ordering::Relaxed),
+            sum: self.sum.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/*
+* 延迟直方图的快照
+*/
+#[derive(Debug, Clone)]
+pub struct HistogramSnapshot {
+    counts: Vec<usize>, //每个桶的记录数量，最后一个为溢出桶
+    count:  usize,      //记录数量
+    sum:    usize,      //记录的延迟总和，单位us
+}
+
+impl HistogramSnapshot {
+    //获取桶上限列表，单位us，不包括溢出桶
+    pub fn bounds(&self) -> &'static [usize] {
+        LATENCY_BUCKETS
+    }
+
+    //获取每个桶的记录数量，最后一个为溢出桶
+    pub fn counts(&self) -> &[usize] {
+        self.counts.as_slice()
+    }
+
+    //获取记录数量
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    //获取记录的延迟总和，单位us
+    pub fn sum(&self) -> usize {
+        self.sum
+    }
+
+    //获取平均延迟
+    pub fn mean(&self) -> Duration {
+        if self.count == 0 {
+            return Duration::from_micros(0);
+        }
+        Duration::from_micros((self.sum / self.count) as u64)
+    }
+
+    //获取指定百分位的延迟上限，百分位范围为0.0到1.0，落在溢出桶则返回空
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        let target = (self.count as f64 * p).ceil() as usize;
+        let mut total = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            total += count;
+            if total >= target {
+                return LATENCY_BUCKETS.get(index).map(|bound| Duration::from_micros(*bound as u64));
+            }
+        }
+        None
+    }
+}
+
+/*
+* 虚拟机工厂指标
+*/
+pub struct FactoryMetrics {
+    name:               String,         //虚拟机工厂名
+    call_count:         AtomicUsize,    //调用数量
+    finish_task_count:  AtomicUsize,    //完成任务数量
+    run_panic_count:    AtomicUsize,    //运行异常数量
+    refuse_count:       AtomicUsize,    //拒绝任务数量
+    new_count:          AtomicUsize,    //构建虚拟机数量
+    load_failed_count:  AtomicUsize,    //构建或加载虚拟机失败数量
+    init_panic_count:   AtomicUsize,    //虚拟机初始化异常数量
+    throw_count:        AtomicUsize,    //丢弃虚拟机数量
+    load_time:          Histogram,      //构建并加载虚拟机的延迟
+    queue_wait:         Histogram,      //任务从调用到开始执行的等待延迟
+    execution:          Histogram,      //任务同步执行的延迟
+}
+
+impl FactoryMetrics {
+    //构建虚拟机工厂指标
+    fn new(name: &str) -> Self {
+        FactoryMetrics {
+            name: name.to_string(),
+            call_count: AtomicUsize::new(0),
+            finish_task_count: AtomicUsize::new(0),
+            run_panic_count: AtomicUsize::new(0),
+            refuse_count: AtomicUsize::new(0),
+            new_count: AtomicUsize::new(0),
+            load_failed_count: AtomicUsize::new(0),
+            init_panic_count: AtomicUsize::new(0),
+            throw_count: AtomicUsize::new(0),
+            load_time: Histogram::new(),
+            queue_wait: Histogram::new(),
+            execution: Histogram::new(),
+        }
+    }
+
+    //获取虚拟机工厂名
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    //增加调用数量
+    pub(crate) fn incr_call(&self) {
+        self.call_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    //增加完成任务数量
+    pub(crate) fn incr_finish_task(&self) {
+        self.finish_task_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    //增加运行异常数量
+    pub(crate) fn incr_run_panic(&self) {
+        self.run_panic_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    //增加拒绝任务数量
+    pub(crate) fn incr_refuse(&self) {
+        self.refuse_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    //增加丢弃虚拟机数量
+    pub(crate) fn incr_throw(&self, count: usize) {
+        self.throw_count.fetch_add(count, Ordering::Relaxed);
+    }
+
+    //记录构建并加载虚拟机的延迟
+    pub(crate) fn record_load(&self, latency: Duration) {
+        self.new_count.fetch_add(1, Ordering::Relaxed);
+        self.load_time.record(latency);
+    }
+
+    //增加构建或加载虚拟机失败数量
+    pub(crate) fn incr_load_failed(&self) {
+        self.load_failed_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    //增加虚拟机初始化异常数量
+    pub(crate) fn incr_init_panic(&self) {
+        self.init_panic_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    //记录任务从调用到开始执行的等待延迟
+    pub(crate) fn record_queue_wait(&self, latency: Duration) {
+        self.queue_wait.record(latency);
+    }
+
+    //记录任务同步执行的延迟
+    pub(crate) fn record_execution(&self, latency: Duration) {
+        self.execution.record(latency);
+    }
+
+    //获取虚拟机工厂指标的快照，包括虚拟机工厂当前的虚拟机数量和任务调度队列长度
+    pub fn snapshot(&self) -> FactoryMetricsSnapshot {
+        let (size, free, queue_len, scheduling_count) = match VM_FACTORY_REGISTERS.read().unwrap().get(&self.name) {
+            None => (0, 0, 0, 0),
+            Some(factory) => (factory.size(), factory.free_pool_size() + factory.free_buf_size(), factory.queue_len(), factory.scheduling_count()),
+        };
+
+        FactoryMetricsSnapshot {
+            name: self.name.clone(),
+            size,
+            free,
+            queue_len,
+            scheduling_count,
+            call_count: self.call_count.load(Ordering::Relaxed),
+            finish_task_count: self.finish_task_count.load(Ordering::Relaxed),
+            run_panic_count: self.run_panic_count.load(Ordering::Relaxed),
+            refuse_count: self.refuse_count.load(Ordering::Relaxed),
+            new_count: self.new_count.load(Ordering::Relaxed),
+            load_failed_count: self.load_failed_count.load(Ordering::Relaxed),
+            init_panic_count: self.init_panic_count.load(Ordering::Relaxed),
+            throw_count: self.throw_count.load(Ordering::Relaxed),
+            load_time: self.load_time.snapshot(),
+            queue_wait: self.queue_wait.snapshot(),
+            execution: self.execution.snapshot(),
+        }
+    }
+}
+
+/*
+* 虚拟机工厂指标的快照
+*/
+#[derive(Debug, Clone)]
+pub struct FactoryMetricsSnapshot {
+    name:               String,             //虚拟机工厂名
+    size:               usize,              //当前虚拟机数量
+    free:               usize,              //当前空闲虚拟机数量
+    queue_len:          usize,              //当前任务调度队列长度
+    scheduling_count:   usize,              //调度次数
+    call_count:         usize,              //调用数量
+    finish_task_count:  usize,              //完成任务数量
+    run_panic_count:    usize,              //运行异常数量
+    refuse_count:       usize,              //拒绝任务数量
+    new_count:          usize,              //构建虚拟机数量
+    load_failed_count:  usize,              //构建或加载虚拟机失败数量
+    init_panic_count:   usize,              //虚拟机初始化异常数量
+    throw_count:        usize,              //丢弃虚拟机数量
+    load_time:          HistogramSnapshot,  //构建并加载虚拟机的延迟
+    queue_wait:         HistogramSnapshot,  //任务从调用到开始执行的等待延迟
+    execution:          HistogramSnapshot,  //任务同步执行的延迟
+}
+
+impl FactoryMetricsSnapshot {
+    //获取虚拟机工厂名
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    //获取当前虚拟机数量
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    //获取当前空闲虚拟机数量
+    pub fn free(&self) -> usize {
+        self.free
+    }
+
+    //获取当前任务调度队列长度
+    pub fn queue_len(&self) -> usize {
+        self.queue_len
+    }
+
+    //获取调度次数
+    pub fn scheduling_count(&self) -> usize {
+        self.scheduling_count
+    }
+
+    //获取调用数量
+    pub fn call_count(&self) -> usize {
+        self.call_count
+    }
+
+    //获取完成任务数量
+    pub fn finish_task_count(&self) -> usize {
+        self.finish_task_count
+    }
+
+    //获取运行异常数量
+    pub fn run_panic_count(&self) -> usize {
+        self.run_panic_count
+    }
+
+    //获取拒绝任务数量
+    pub fn refuse_count(&self) -> usize {
+        self.refuse_count
+    }
+
+    //获取构建虚拟机数量
+    pub fn new_count(&self) -> usize {
+        self.new_count
+    }
+
+    //获取构建或加载虚拟机失败数量
+    pub fn load_failed_count(&self) -> usize {
+        self.load_failed_count
+    }
+
+    //获取虚拟机初始化异常数量
+    pub fn init_panic_count(&self) -> usize {
+        self.init_panic_count
+    }
+
+    //获取丢弃虚拟机数量
+    pub fn throw_count(&self) -> usize {
+        self.throw_count
+    }
+
+    //获取构建并加载虚拟机的延迟
+    pub fn load_time(&self) -> &HistogramSnapshot {
+        &self.load_time
+    }
+
+    //获取任务从调用到开始执行的等待延迟
+    pub fn queue_wait(&self) -> &HistogramSnapshot {
+        &self.queue_wait
+    }
+
+    //获取任务同步执行的延迟
+    pub fn execution(&self) -> &HistogramSnapshot {
+        &self.execution
+    }
+}
+
+/*
+* 进程工厂指标
+*/
+pub struct ProcessMetrics {
+    name:               String,         //进程工厂名
+    spawn_count:        AtomicUsize,    //生成进程数量
+    spawn_failed_count: AtomicUsize,    //生成进程失败数量
+    close_count:        AtomicUsize,    //关闭进程数量
+    send_count:         AtomicUsize,    //发送消息数量
+    throw_count:        AtomicUsize,    //抛出异常数量
+}
+
+impl ProcessMetrics {
+    //构建进程工厂指标
+    fn new(name: &str) -> Self {
+        ProcessMetrics {
+            name: name.to_string(),
+            spawn_count: AtomicUsize::new(0),
+            spawn_failed_count: AtomicUsize::new(0),
+            close_count: AtomicUsize::new(0),
+            send_count: AtomicUsize::new(0),
+            throw_count: AtomicUsize::new(0),
+        }
+    }
+
+    //获取进程工厂名
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    //增加生成进程数量
+    pub(crate) fn incr_spawn(&self, is_ok: bool) {
+        if is_ok {
+            self.spawn_count.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.spawn_failed_count.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    //增加关闭进程数量
+    pub(crate) fn incr_close(&self) {
+        self.close_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    //增加发送消息数量
+    pub(crate) fn incr_send(&self) {
+        self.send_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    //增加抛出异常数量
+    pub(crate) fn incr_throw(&self) {
+        self.throw_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    //获取进程工厂指标的快照
+    pub fn snapshot(&self) -> ProcessMetricsSnapshot {
+        ProcessMetricsSnapshot {
+            name: self.name.clone(),
+            spawn_count: self.spawn_count.load(Ordering::Relaxed),
+            spawn_failed_count: self.spawn_failed_count.load(Ordering::Relaxed),
+            close_count: self.close_count.load(Ordering::Relaxed),
+            send_count: self.send_count.load(Ordering::Relaxed),
+            throw_count: self.throw_count.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/*
+* 进程工厂指标的快照
+*/
+#[derive(Debug, Clone)]
+pub struct ProcessMetricsSnapshot {
+    name:               String, //进程工厂名
+    spawn_count:        usize,  //生成进程数量
+    spawn_failed_count: usize,  //生成进程失败数量
+    close_count:        usize,  //关闭进程数量
+    send_count:         usize,  //发送消息数量
+    throw_count:        usize,  //抛出异常数量
+}
+
+impl ProcessMetricsSnapshot {
+    //获取进程工厂名
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    //获取生成进程数量
+    pub fn spawn_count(&self) -> usize {
+        self.spawn_count
+    }
+
+    //获取生成进程失败数量
+    pub fn spawn_failed_count(&self) -> usize {
+        self.spawn_failed_count
+    }
+
+    //获取关闭进程数量
+    pub fn close_count(&self) -> usize {
+        self.close_count
+    }
+
+    //获取发送消息数量
+    pub fn send_count(&self) -> usize {
+        self.send_count
+    }
+
+    //获取抛出异常数量
+    pub fn throw_count(&self) -> usize {
+        self.throw_count
+    }
+}
+
+/*
+* 所有指标的快照
+*/
+#[derive(Debug, Clone)]
+pub struct MetricsSnapshot {
+    time:       usize,                          //快照时间，单位us
+    factories:  Vec<FactoryMetricsSnapshot>,    //虚拟机工厂指标的快照列表，按虚拟机工厂名排列
+    processes:  Vec<ProcessMetricsSnapshot>,    //进程工厂指标的快照列表，按进程工厂名排列
+}
+
+impl MetricsSnapshot {
+    //获取快照时间
+    pub fn time(&self) -> usize {
+        self.time
+    }
+
+    //获取虚拟机工厂指标的快照列表
+    pub fn factories(&self) -> &[FactoryMetricsSnapshot] {
+        self.factories.as_slice()
+    }
+
+    //获取指定虚拟机工厂指标的快照
+    pub fn factory(&self, name: &str) -> Option<&FactoryMetricsSnapshot> {
+        self.factories.iter().find(|factory| factory.name == name)
+    }
+
+    //获取进程工厂指标的快照列表
+    pub fn processes(&self) -> &[ProcessMetricsSnapshot] {
+        self.processes.as_slice()
+    }
+
+    //获取指定进程工厂指标的快照
+    pub fn process(&self, name: &str) -> Option<&ProcessMetricsSnapshot> {
+        self.processes.iter().find(|process| process.name == name)
+    }
+}
+
+//获取指定虚拟机工厂的指标，不存在则构建，同名的虚拟机工厂共享指标
+pub fn factory_metrics(name: &str) -> Arc<FactoryMetrics> {
+    if let Some(metrics) = VM_FACTORY_METRICS.read().get(name) {
+        return metrics.clone();
+    }
+
+    VM_FACTORY_METRICS.write().entry(name.to_string()).or_insert_with(|| Arc::new(FactoryMetrics::new(name))).clone()
+}
+
+//获取指定进程工厂的指标，不存在则构建
+pub fn process_metrics(name: &str) -> Arc<ProcessMetrics> {
+    if let Some(metrics) = PROCESS_FACTORY_METRICS.read().get(name) {
+        return metrics.clone();
+    }
+
+    PROCESS_FACTORY_METRICS.write().entry(name.to_string()).or_insert_with(|| Arc::new(ProcessMetrics::new(name))).clone()
+}
+
+//获取所有虚拟机工厂和进程工厂指标的快照
+pub fn snapshot() -> MetricsSnapshot {
+    let mut factories: Vec<FactoryMetricsSnapshot> = VM_FACTORY_METRICS.read().values().map(|metrics| metrics.snapshot()).collect();
+    factories.sort_by(|x, y| x.name.cmp(&y.name));
+
+    let mut processes: Vec<ProcessMetricsSnapshot> = PROCESS_FACTORY_METRICS.read().values().map(|metrics| metrics.snapshot()).collect();
+    processes.sort_by(|x, y| x.name.cmp(&y.name));
+
+    MetricsSnapshot {
+        time: now_utc(),
+        factories,
+        processes,
+    }
+}
@@ -12,9 +12,9 @@ use adapter::{JSStatus, JS, JSType, dukc_vm_status_switch, dukc_throw, dukc_swit
 
 lazy_static! {
     //虚拟机同步调用数量
-    static ref VM_SYNC_CALL_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_sync_call_count"), 0).unwrap();
+    pub(crate) static ref VM_SYNC_CALL_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_sync_call_count"), 0).unwrap();
     //虚拟机同步阻塞调用数量
-    static ref VM_BLOCK_CALL_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_block_call_count"), 0).unwrap();
+    pub(crate) static ref VM_BLOCK_CALL_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_block_call_count"), 0).unwrap();
 }
 
 //调用NativeObject函数
@@ -52,6 +52,7 @@ pub extern "C" fn native_object_function_call(
             None => {
                 //没有立即返回，则表示会阻塞，并异步返回
                 VM_BLOCK_CALL_COUNT.sum(1);
+                js.set_block_start(hash); //记录同步阻塞调用的开始时间，用于在回应时检查慢调用
 
                 unsafe {
                     dukc_switch_context(vm);
@@ -1,22 +1,32 @@
 use std::ffi::CString;
-use std::sync::{Arc, RwLock};
-use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Condvar, RwLock};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
 use std::sync::atomic::{AtomicBool, AtomicUsize, AtomicIsize, Ordering};
 
 use libc::c_char;
 use crossbeam_channel::{Sender, Receiver, unbounded};
 
 use worker::task::TaskType;
-use worker::impls::{create_js_task_queue, unlock_js_task_queue, cast_js_task, remove_js_task_queue};
 use handler::Handler;
 use atom::Atom;
+use gray::GrayVersion;
 use apm::allocator::{get_max_alloced_limit, is_alloced_limit, all_alloced_size};
 use apm::counter::{GLOBAL_PREF_COLLECT, PrefCounter, PrefTimer};
 use lfstack::{CollectResult, LFStack};
+use timer::{TIMER, FuncRuner};
 
-use adapter::{VM_FACTORY_REGISTERS, JSStatus, JS, JSType, pause, js_reply_callback, handle_async_callback, dukc_vm_status_check, dukc_vm_status_switch, dukc_new_error, dukc_wakeup, dukc_continue, now_utc};
+use adapter::{VM_FACTORY_REGISTERS, VM_COLLECT_QUEUE, JSStatus, JS, JSType, pause, js_reply_callback, handle_async_callback, collect_vm, dukc_vm_status_check, dukc_vm_status_switch, dukc_new_error, dukc_wakeup, dukc_continue, now_utc};
 use channel_map::VMChannelMap;
 use bonmgr::NativeObjsAuth;
+use reuse::{ReusePolicy, DefaultReusePolicy};
+use scaling::{ScalingConfig, ScalingState};
+use affinity::AffinityConfig;
+use metrics::{FactoryMetrics, factory_metrics};
+use event::{VmEvent, ThrowReason, is_listening, emit, emit_thrown};
+use trace::{TraceContext, Span, is_tracing, current, enter, record};
+use slow::{SlowKind, start_slow, check_slow};
+use scheduler::{create_js_task_queue, unlock_js_task_queue, cast_js_task, remove_js_task_queue, is_in_vm_task};
 use std::sync::atomic::Ordering::SeqCst;
 
 /*
@@ -24,6 +34,21 @@ use std::sync::atomic::Ordering::SeqCst;
 */
 const JS_TASK_PRIORITY: usize = 100;
 
+/*
+* 阻塞策略下，单次等待任务调度队列空闲位置的最大时长，单位ms
+*/
+const MAX_QUEUE_BLOCK_WAIT_TIME: u64 = 10;
+
+/*
+* 关闭虚拟机工厂时，单次等待正在运行的虚拟机完成的最大时长，单位ms
+*/
+const MAX_SHUTDOWN_WAIT_TIME: u64 = 10;
+
+/*
+* 单独记录调用数量的最大租户数量，超过后其它租户的调用数量合并记录
+*/
+const MAX_TENANT_CALL_COUNTS: usize = 256;
+
 /*
 * 虚拟机通道
 */
@@ -35,22 +60,36 @@ lazy_static! {
 * 虚拟机工厂同步任务队列表
 */
 lazy_static! {
-	pub static ref VM_FACTORY_QUEUES: Arc<RwLock<HashMap<usize, isize>>> = Arc::new(RwLock::new(HashMap::new()));
+	pub static ref VM_FACTORY_QUEUES: Arc<RwLock<HashMap<usize, Arc<SourceQueue>>>> = Arc::new(RwLock::new(HashMap::new()));
+	//同步任务队列整理定时器是否已停止
+	static ref VM_QUEUE_SWEEP_STOPPED: AtomicBool = AtomicBool::new(false);
 }
 
 lazy_static! {
     //虚拟机数量
-    static ref VM_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_count"), 0).unwrap();
+    pub(crate) static ref VM_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_count"), 0).unwrap();
     //虚拟机构建总时长
-    static ref VM_NEW_TIME: PrefTimer = GLOBAL_PREF_COLLECT.new_static_timer(Atom::from("vm_new_time"), 0).unwrap();
+    pub(crate) static ref VM_NEW_TIME: PrefTimer = GLOBAL_PREF_COLLECT.new_static_timer(Atom::from("vm_new_time"), 0).unwrap();
     //虚拟机加载总时长
-    static ref VM_LOAD_TIME: PrefTimer = GLOBAL_PREF_COLLECT.new_static_timer(Atom::from("vm_load_time"), 0).unwrap();
+    pub(crate) static ref VM_LOAD_TIME: PrefTimer = GLOBAL_PREF_COLLECT.new_static_timer(Atom::from("vm_load_time"), 0).unwrap();
+    //虚拟机复制总时长
+    pub(crate) static ref VM_CLONE_TIME: PrefTimer = GLOBAL_PREF_COLLECT.new_static_timer(Atom::from("vm_clone_time"), 0).unwrap();
+    //虚拟机内存泄漏数量
+    pub(crate) static ref VM_LEAK_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_leak_count"), 0).unwrap();
+    //虚拟机复制失败数量
+    pub(crate) static ref VM_CLONE_FAILED_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_clone_failed_count"), 0).unwrap();
+    //空闲同步任务队列的整理数量
+    pub(crate) static ref VM_QUEUE_SWEEP_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_queue_sweep_count"), 0).unwrap();
     //虚拟机调用数量
-    static ref VM_CALL_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_call_count"), 0).unwrap();
+    pub(crate) static ref VM_CALL_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_call_count"), 0).unwrap();
+    //虚拟机租户调用数量表，最多记录MAX_TENANT_CALL_COUNTS个租户
+    static ref VM_TENANT_CALL_COUNTS: Mutex<HashMap<Atom, PrefCounter>> = Mutex::new(HashMap::new());
+    //超过最大租户数量后，其它租户的调用数量
+    pub(crate) static ref VM_OTHER_TENANT_CALL_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_call_count_other_tenant"), 0).unwrap();
     //虚拟机推送异步回调数量
-    static ref VM_PUSH_CALLBACK_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_push_callback_count"), 0).unwrap();
+    pub(crate) static ref VM_PUSH_CALLBACK_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_push_callback_count"), 0).unwrap();
     //虚拟机异步请求数量
-    static ref VM_ASYNC_REQUEST_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_async_request_count"), 0).unwrap();
+    pub(crate) static ref VM_ASYNC_REQUEST_COUNT: PrefCounter = GLOBAL_PREF_COLLECT.new_static_counter(Atom::from("vm_async_request_count"), 0).unwrap();
 }
 
 /*
@@ -83,6 +122,286 @@ impl VMFactoryLoader {
     }
 }
 
+/*
+* 虚拟机工厂任务调度队列的过载策略
+*/
+#[derive(Debug, Clone, Copy)]
+pub enum OverloadPolicy {
+    RejectNewest,       //拒绝最新的任务
+    DropOldest,         //丢弃最旧的任务
+    Block(Duration),    //阻塞调用者，直到任务调度队列有空闲位置或超时
+}
+
+/*
+* 虚拟机工厂拒绝任务的原因
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefuseReason {
+    Rejected,   //任务调度队列已满，拒绝最新的任务
+    Dropped,    //任务调度队列已满，丢弃最旧的任务
+    Timeout,    //任务调度队列已满，阻塞调用者超时
+    Expired,    //任务在任务调度队列中等待超时
+    Closed,     //虚拟机工厂已关闭
+}
+
+/*
+* 虚拟机工厂关闭报告
+*/
+#[derive(Debug, Clone)]
+pub struct ShutdownReport {
+    finished:   usize,      //关闭期间完成的任务数量，包括关闭前已在运行的任务
+    dropped:    usize,      //关闭期间未完成而被丢弃的任务数量
+    destroyed:  usize,      //关闭期间销毁的空闲虚拟机数量
+    remaining:  usize,      //关闭超时后仍在运行的虚拟机数量，这些虚拟机将在运行完成后销毁
+    elapsed:    Duration,   //关闭耗时
+}
+
+impl ShutdownReport {
+    //获取关闭期间完成的任务数量
+    pub fn finished(&self) -> usize {
+        self.finished
+    }
+
+    //获取关闭期间被丢弃的任务数量
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    //获取关闭期间销毁的空闲虚拟机数量
+    pub fn destroyed(&self) -> usize {
+        self.destroyed
+    }
+
+    //获取关闭超时后仍在运行的虚拟机数量
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    //获取关闭耗时
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    //判断是否在宽限时间内完成关闭
+    pub fn is_graceful(&self) -> bool {
+        self.dropped == 0 && self.remaining == 0
+    }
+}
+
+/*
+* 虚拟机工厂调用选项
+*/
+#[derive(Debug, Clone, Default)]
+pub struct CallOptions {
+    priority:   Option<usize>,            //任务优先级，为空则使用默认优先级
+    deadline:   Option<Instant>,          //任务截止时间，到期未开始执行的任务将被丢弃，为空表示无限制
+    tenant_id:  Option<Atom>,             //租户id
+    order_key:  Option<usize>,            //任务源的排序键，相同排序键的任务会按顺序同步执行，为空表示异步执行
+    session:    Option<usize>,            //会话，虚拟机工厂启用会话亲和时，相同会话的任务会在同一个虚拟机上按顺序执行，为空表示不绑定
+    gray:       Option<usize>,            //灰度版本，通过灰度虚拟机工厂调用时，路由到指定版本，为空表示根据灰度路由规则路由
+    trace:      Option<TraceContext>,     //跟踪上下文，启用跟踪时，调用的跨度将作为此上下文的子跨度，为空则开始新的跟踪
+}
+
+impl CallOptions {
+    //构建默认的调用选项
+    pub fn new() -> Self {
+        CallOptions::default()
+    }
+
+    //设置任务优先级
+    pub fn set_priority(mut self, priority: usize) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+
+    //设置任务截止时间
+    pub fn set_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    //设置租户id
+    pub fn set_tenant_id(mut self, tenant_id: Atom) -> Self {
+        self.tenant_id = Some(tenant_id);
+        self
+    }
+
+    //设置任务源的排序键
+    pub fn set_order_key(mut self, src: usize) -> Self {
+        self.order_key = Some(src);
+        self
+    }
+
+    //设置会话
+    pub fn set_session(mut self, session: usize) -> Self {
+        self.session = Some(session);
+        self
+    }
+
+    //设置灰度版本
+    pub fn set_gray(mut self, gray: usize) -> Self {
+        self.gray = Some(gray);
+        self
+    }
+
+    //设置跟踪上下文
+    pub fn set_trace(mut self, trace: TraceContext) -> Self {
+        self.trace = Some(trace);
+        self
+    }
+
+    //获取任务优先级
+    pub fn priority(&self) -> Option<usize> {
+        self.priority
+    }
+
+    //获取任务截止时间
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    //获取跟踪上下文的跟踪id
+    pub fn trace_id(&self) -> Option<u64> {
+        self.trace.map(|trace| trace.trace_id())
+    }
+
+    //获取租户id
+    pub fn tenant_id(&self) -> Option<&Atom> {
+        self.tenant_id.as_ref()
+    }
+
+    //获取任务源的排序键
+    pub fn order_key(&self) -> Option<usize> {
+        self.order_key
+    }
+
+    //获取会话
+    pub fn session(&self) -> Option<usize> {
+        self.session
+    }
+
+    //获取灰度版本
+    pub fn gray(&self) -> Option<usize> {
+        self.gray
+    }
+
+    //获取跟踪上下文
+    pub fn trace(&self) -> Option<TraceContext> {
+        self.trace
+    }
+
+    //判断任务是否已过截止时间
+    pub fn is_expired(&self) -> bool {
+        if let Some(deadline) = self.deadline {
+            Instant::now() >= deadline
+        } else {
+            false
+        }
+    }
+}
+
+impl GrayVersion for CallOptions {
+    fn get_gray(&self) -> &Option<usize> {
+        &self.gray
+    }
+
+    fn set_gray(&mut self, gray: Option<usize>) {
+        self.gray = gray
+    }
+
+    //会话优先于任务源的排序键，都为空则为0
+    fn get_id(&self) -> usize {
+        self.session.or(self.order_key).unwrap_or(0)
+    }
+}
+
+/*
+* 批量调用中单个调用的错误
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BatchError {
+    NotFound(String),       //调用的js全局函数不存在
+    Invoke(usize, String),  //调用异常，包括调用的序号和异常信息
+}
+
+/*
+* 虚拟机工厂任务的参数
+*/
+enum TaskArgs {
+    Call(Box<FnOnce(Arc<JS>) -> usize>),    //单次调用，返回调用参数的数量
+    Batch(Box<FnOnce(Arc<JS>)>),            //批量调用，在任务中连续同步调用后结束任务
+}
+
+/*
+* 虚拟机工厂等待调度的任务
+*/
+struct FactoryTask {
+    options:    CallOptions,    //调用选项
+    port:       Atom,           //调用的js全局函数名
+    args:       TaskArgs,       //调用参数
+    info:       Atom,           //任务信息
+    time:       Instant,        //任务加入任务调度队列的时间
+}
+
+/*
+* 虚拟机工厂的模板虚拟机
+*/
+enum VMTemplate {
+    Uninit,         //未构建，下次构建虚拟机时构建
+    Unavailable,    //构建失败或虚拟机工厂已关闭，不再构建，所有虚拟机都重新加载字节码
+    Ready(Arc<JS>), //已构建
+}
+
+/*
+* 绑定会话的虚拟机
+*/
+struct SessionVm {
+    vm:         Arc<JS>,                //绑定的虚拟机
+    is_busy:    bool,                   //虚拟机是否正在运行会话的任务
+    is_unpin:   bool,                   //是否在虚拟机空闲后解绑会话
+    last_time:  Instant,                //会话最近运行时间
+    waits:      VecDeque<FactoryTask>,  //等待虚拟机空闲的会话任务队列
+}
+
+/*
+* 虚拟机内存泄漏报告
+*/
+#[derive(Debug, Clone)]
+pub struct LeakReport {
+    factory:    String,     //虚拟机工厂名
+    vm_id:      usize,      //虚拟机id
+    heap_sizes: Vec<usize>, //虚拟机最近重置全局环境后的堆大小列表，从旧到新排列
+    infos:      Vec<Atom>,  //虚拟机最近执行的任务信息列表，从旧到新排列
+    is_retired: bool,       //虚拟机是否被主动丢弃
+}
+
+impl LeakReport {
+    //获取虚拟机工厂名
+    pub fn factory(&self) -> &str {
+        self.factory.as_str()
+    }
+
+    //获取虚拟机id
+    pub fn vm_id(&self) -> usize {
+        self.vm_id
+    }
+
+    //获取虚拟机最近重置全局环境后的堆大小列表
+    pub fn heap_sizes(&self) -> &[usize] {
+        self.heap_sizes.as_slice()
+    }
+
+    //获取虚拟机最近执行的任务信息列表
+    pub fn infos(&self) -> &[Atom] {
+        self.infos.as_slice()
+    }
+
+    //判断虚拟机是否被主动丢弃
+    pub fn is_retired(&self) -> bool {
+        self.is_retired
+    }
+}
+
 /*
 * 虚拟机工厂
 */
@@ -96,6 +415,7 @@ pub struct VMFactory {
     max_reused_count:   usize,                                                                  //虚拟机最大执行次数，当达到虚拟机最大堆限制后才会检查
     heap_size:          usize,                                                                  //虚拟机堆大小
     max_heap_size:      usize,                                                                  //虚拟机最大堆大小，当达到限制后释放可回收的内存
+    heap_limit:         usize,                                                                  //虚拟机堆限制，分配内存时检查，超过限制则分配失败，0表示无限制
     codes:              Arc<Vec<Arc<Vec<u8>>>>,                                                 //字节码列表
     mods:               Arc<Vec<String>>,                                                       //虚拟机工厂依赖的模块名列表
     pool:               Arc<LFStack<Arc<JS>>>,                                                  //虚拟机池
@@ -103,9 +423,27 @@ pub struct VMFactory {
     auth:               Arc<NativeObjsAuth>,                                                    //虚拟机工厂本地对象授权
     vm_buf_sent:        Sender<Arc<JS>>,                                                        //虚拟机临时缓冲发送器
     vm_buf_recv:        Receiver<Arc<JS>>,                                                      //虚拟机临时缓冲接收器
-    queue_sent:         Sender<(Option<usize>, Atom, Box<FnOnce(Arc<JS>) -> usize>, Atom)>,     //虚拟机工厂等待调度的任务队列发送器
-    queue_recv:         Receiver<(Option<usize>, Atom, Box<FnOnce(Arc<JS>) -> usize>, Atom)>,   //虚拟机工厂等待调度的任务队列接收器
+    queue_sent:         Sender<FactoryTask>,                                                    //虚拟机工厂等待调度的任务队列发送器
+    queue_recv:         Receiver<FactoryTask>,                                                  //虚拟机工厂等待调度的任务队列接收器
+    queue_capacity:     usize,                                                                  //虚拟机工厂任务调度队列容量，0表示无限制
+    queue_reserved:     Arc<AtomicUsize>,                                                       //虚拟机工厂任务调度队列已占用的位置数量，包括已预留但未加入的位置
+    queue_space:        Arc<(Mutex<VecDeque<Instant>>, Condvar)>,                               //虚拟机工厂任务调度队列中任务的调用时间，及有空闲位置的通知
+    overload_policy:    OverloadPolicy,                                                         //虚拟机工厂任务调度队列的过载策略
+    task_timeout:       Option<Duration>,                                                       //任务在任务调度队列中的最长等待时间，为空表示无限制
+    refuse_handler:     Option<Arc<Fn(RefuseReason, Atom) + Send + Sync>>,                      //虚拟机工厂拒绝任务处理器
     refuse_count:       Arc<AtomicUsize>,                                                       //虚拟机工厂拒绝任务次数
+    finished:           Arc<(Mutex<usize>, Condvar)>,                                           //虚拟机工厂完成的任务数量，及任务完成或虚拟机归还的通知
+    queue_latency:      Arc<AtomicUsize>,                                                       //虚拟机工厂最近入队或出队时采样的任务等待时长，单位us
+    scaling:            Option<ScalingConfig>,                                                  //虚拟机工厂伸缩配置，为空表示不自动伸缩
+    scaling_state:      Arc<Mutex<ScalingState>>,                                               //虚拟机工厂伸缩状态，随虚拟机工厂释放
+    template:           Option<Arc<Mutex<VMTemplate>>>,                                         //虚拟机工厂的模板虚拟机，为空表示未启用模板
+    leak_detect:        Option<(usize, bool)>,                                                  //虚拟机内存泄漏检查，包括连续增长的复用次数和是否主动丢弃，为空表示不检查
+    leak_handler:       Option<Arc<Fn(LeakReport) + Send + Sync>>,                              //虚拟机内存泄漏处理器
+    reuse_policy:       Arc<ReusePolicy>,                                                       //虚拟机复用策略
+    closed:             Arc<AtomicBool>,                                                        //虚拟机工厂是否已关闭
+    affinity:           Option<AffinityConfig>,                                                 //虚拟机工厂会话亲和配置，为空表示不绑定会话
+    sessions:           Arc<Mutex<HashMap<usize, SessionVm>>>,                                  //会话绑定表
+    metrics:            Arc<FactoryMetrics>,                                                    //虚拟机工厂指标，同名的虚拟机工厂共享指标
 }
 
 unsafe impl Send for VMFactory {}
@@ -145,6 +483,7 @@ impl VMFactory {
             max_reused_count,
             heap_size,
             max_heap_size,
+            heap_limit: 0,
             codes: Arc::new(Vec::new()),
             mods: Arc::new(Vec::new()),
             pool: Arc::new(LFStack::new()),
@@ -154,8 +493,131 @@ impl VMFactory {
             vm_buf_recv,
             queue_sent,
             queue_recv,
+            queue_capacity: 0,
+            queue_reserved: Arc::new(AtomicUsize::new(0)),
+            queue_space: Arc::new((Mutex::new(VecDeque::new()), Condvar::new())),
+            overload_policy: OverloadPolicy::RejectNewest,
+            task_timeout: None,
+            refuse_handler: None,
             refuse_count: Arc::new(AtomicUsize::new(0)),
+            finished: Arc::new((Mutex::new(0), Condvar::new())),
+            queue_latency: Arc::new(AtomicUsize::new(0)),
+            scaling: None,
+            scaling_state: Arc::new(Mutex::new(ScalingState::default())),
+            template: None,
+            leak_detect: None,
+            leak_handler: None,
+            reuse_policy: Arc::new(DefaultReusePolicy),
+            closed: Arc::new(AtomicBool::new(false)),
+            affinity: None,
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+            metrics: factory_metrics(name),
+        }
+    }
+
+    //为指定虚拟机工厂启用模板虚拟机，启用后将从已加载所有字节码的模板虚拟机复制新的虚拟机，复制失败则重新加载字节码，必须使用所有权，复制对象将无法启用
+    //模板虚拟机需要dukc提供堆复制，未启用vm-template时忽略
+    pub fn enable_template(mut self) -> Self {
+        if cfg!(not(feature = "vm-template")) {
+            warn!("!!!> Vm Factory Enable Template Error, vm-template disabled, factory: {:?}",
+                  (&self.name).to_string());
+            return self;
+        }
+
+        if self.template.is_none() {
+            self.template = Some(Arc::new(Mutex::new(VMTemplate::Uninit)));
+        }
+        self
+    }
+
+    //为指定虚拟机工厂设置虚拟机堆限制，超过限制的虚拟机会在js中抛出RangeError，并在当前任务执行完成后丢弃，必须使用所有权，复制对象将无法设置
+    pub fn set_heap_limit(mut self, limit: usize) -> Self {
+        self.heap_limit = limit;
+        self
+    }
+
+    //为指定虚拟机工厂设置虚拟机内存泄漏检查，虚拟机重置全局环境后的堆大小在连续指定复用次数中持续增长，则报告内存泄漏，并根据需要主动丢弃虚拟机，复用次数至少为2，必须使用所有权，复制对象将无法设置
+    pub fn set_leak_detect(mut self, count: usize, is_retire: bool) -> Self {
+        if count < 2 {
+            self.leak_detect = None;
+        } else {
+            self.leak_detect = Some((count, is_retire));
         }
+        self
+    }
+
+    //为指定虚拟机工厂设置虚拟机内存泄漏处理器，未设置则只记录日志，必须使用所有权，复制对象将无法设置
+    pub fn set_leak_handler(mut self, handler: Arc<Fn(LeakReport) + Send + Sync>) -> Self {
+        self.leak_handler = Some(handler);
+        self
+    }
+
+    //为指定虚拟机工厂设置任务调度队列的容量和过载策略，容量为0表示无限制，必须使用所有权，复制对象将无法设置
+    pub fn set_queue_capacity(mut self, capacity: usize, policy: OverloadPolicy) -> Self {
+        self.queue_capacity = capacity;
+        self.overload_policy = policy;
+        self
+    }
+
+    //为指定虚拟机工厂设置任务在任务调度队列中的最长等待时间，超时的任务将被丢弃，必须使用所有权，复制对象将无法设置
+    pub fn set_task_timeout(mut self, timeout: Duration) -> Self {
+        self.task_timeout = Some(timeout);
+        self
+    }
+
+    //为指定虚拟机工厂设置拒绝任务处理器，处理器参数分别为拒绝原因和任务信息，必须使用所有权，复制对象将无法设置
+    pub fn set_refuse_handler(mut self, handler: Arc<Fn(RefuseReason, Atom) + Send + Sync>) -> Self {
+        self.refuse_handler = Some(handler);
+        self
+    }
+
+    //为指定虚拟机工厂设置伸缩配置，由虚拟机工厂伸缩定时器自动伸缩，必须使用所有权，复制对象将无法设置
+    //设置后，调用时没有空闲虚拟机将不会立即构建新的虚拟机，任务会加入任务调度队列，由伸缩定时器根据等待时长扩容
+    pub fn set_scaling(mut self, config: ScalingConfig) -> Self {
+        self.scaling = Some(config);
+        self
+    }
+
+    //为指定虚拟机工厂设置会话亲和配置，设置了会话的调用将绑定到同一个虚拟机，虚拟机在会话的任务之间不会重置全局环境，必须使用所有权，复制对象将无法设置
+    pub fn set_affinity(mut self, config: AffinityConfig) -> Self {
+        self.affinity = Some(config);
+        self
+    }
+
+    //获取虚拟机工厂会话亲和配置
+    pub fn affinity(&self) -> Option<&AffinityConfig> {
+        self.affinity.as_ref()
+    }
+
+    //获取虚拟机工厂当前绑定的会话数量
+    pub fn session_size(&self) -> usize {
+        self.sessions.lock().unwrap().len()
+    }
+
+    //获取虚拟机工厂伸缩配置
+    pub fn scaling(&self) -> Option<&ScalingConfig> {
+        self.scaling.as_ref()
+    }
+
+    //获取虚拟机工厂伸缩状态
+    pub(crate) fn scaling_state(&self) -> &Mutex<ScalingState> {
+        &self.scaling_state
+    }
+
+    //为指定虚拟机工厂设置虚拟机复用策略，默认使用DefaultReusePolicy，必须使用所有权，复制对象将无法设置
+    pub fn set_reuse_policy(mut self, policy: Arc<ReusePolicy>) -> Self {
+        self.reuse_policy = policy;
+        self
+    }
+
+    //获取虚拟机复用策略
+    pub fn reuse_policy(&self) -> Arc<ReusePolicy> {
+        self.reuse_policy.clone()
+    }
+
+    //判断虚拟机工厂是否启用了模板虚拟机
+    pub fn is_template(&self) -> bool {
+        self.template.is_some()
     }
 
     //为指定虚拟机工厂增加代码，必须使用所有权，以保证运行时不会不安全的增加代码，复制对象将无法增加代码
@@ -190,6 +652,11 @@ impl VMFactory {
         (*self.name).to_string()
     }
 
+    //判断虚拟机工厂是否已关闭
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+
     //获取虚拟机池的限制容量
     pub fn limit_capacity(&self) -> usize {
         self.limit_capacity.load(Ordering::Relaxed)
@@ -243,6 +710,11 @@ impl VMFactory {
         self.max_heap_size
     }
 
+    //获取虚拟机堆限制
+    pub fn heap_limit(&self) -> usize {
+        self.heap_limit
+    }
+
     //获取虚拟机工厂调度次数
     pub fn scheduling_count(&self) -> usize {
         self.scheduling_count.load(Ordering::Relaxed)
@@ -258,11 +730,30 @@ impl VMFactory {
         self.queue_recv.len()
     }
 
+    //获取虚拟机工厂，任务调度队列的容量
+    pub fn queue_capacity(&self) -> usize {
+        self.queue_capacity
+    }
+
+    //获取任务调度队列的等待时长，为最近采样的等待时长和当前最旧任务已等待时长中的较大值
+    pub fn queue_latency(&self) -> Duration {
+        let sampled = Duration::from_micros(self.queue_latency.load(Ordering::Relaxed) as u64);
+        match self.queue_space.0.lock().unwrap().front() {
+            None => sampled,
+            Some(time) => sampled.max(time.elapsed()),
+        }
+    }
+
     //获取虚拟机工厂，任务拒绝的次数
     pub fn refuse_count(&self) -> usize {
         self.refuse_count.load(Ordering::Relaxed)
     }
 
+    //获取虚拟机工厂指标
+    pub fn metrics(&self) -> Arc<FactoryMetrics> {
+        self.metrics.clone()
+    }
+
     //重置虚拟机工厂，任务拒绝的次数
     pub fn reset_refuse_count(&self) {
         self.refuse_count.store(0, Ordering::SeqCst);
@@ -316,21 +807,73 @@ impl VMFactory {
 
     //复用指定虚拟机
     pub fn reuse(&self, vm: Arc<JS>) {
-        if let Ok((src, port, args, info)) = self.queue_recv.try_recv() {
-            //当前虚拟机工厂的任务调度队列中有待运行的任务，则立即使用当前虚拟机，异步运行此任务
-            self.async_run(vm, src, port, args, info);
-        } else {
-            //当前虚拟机工厂的任务调度队列中没有待运行的任务，则将当前虚拟机还给当前虚拟机工厂
-            if let Err(_) = self.pool.try_push(vm.clone()) {
-                //虚拟机池已阻塞，则将空闲虚拟机加入虚拟机临时缓冲区
-                self.vm_buf_sent.send(vm);
+        while let Some(task) = self.dequeue() {
+            if self.is_expired(&task) {
+                //任务已等待超时，则丢弃，并继续获取下一个任务
+                self.refuse(RefuseReason::Expired, &task.options, task.info);
+                continue;
             }
+
+            //当前虚拟机工厂的任务调度队列中有待运行的任务，则立即使用当前虚拟机，异步运行此任务
+            self.async_run(vm, task.options, task.port, task.args, task.info, task.time);
+            return;
+        }
+
+        if self.is_closed() {
+            //虚拟机工厂已关闭，则销毁当前虚拟机
+            self.throw(1);
+            info!("===> Vm Destroy by Factory Shutdown, factory: {:?}, vm: {:?}", (&self.name).to_string(), vm);
+            emit_thrown(&vm, ThrowReason::Shutdown);
+            return;
         }
+
+        //当前虚拟机工厂的任务调度队列中没有待运行的任务，则将当前虚拟机还给当前虚拟机工厂
+        if let Err(_) = self.pool.try_push(vm.clone()) {
+            //虚拟机池已阻塞，则将空闲虚拟机加入虚拟机临时缓冲区
+            self.vm_buf_sent.send(vm);
+        }
+        self.notify_idle();
     }
 
     //丢弃指定数量的虚拟机，返回最近虚拟机池中虚拟机数量
     pub fn throw(&self, count: usize) -> usize {
-        self.size.fetch_sub(count, Ordering::SeqCst)
+        self.metrics.incr_throw(count);
+        let size = self.size.fetch_sub(count, Ordering::SeqCst);
+        self.notify_idle();
+        size
+    }
+
+    //记录虚拟机完成了一个任务，在虚拟机的执行回应中调用
+    pub(crate) fn finish_task(&self) {
+        let (lock, cvar) = &*self.finished;
+        *lock.lock().unwrap() += 1;
+        cvar.notify_all();
+    }
+
+    //通知等待的关闭者，有虚拟机已归还或已丢弃
+    fn notify_idle(&self) {
+        let (lock, cvar) = &*self.finished;
+        let _guard = lock.lock().unwrap();
+        cvar.notify_all();
+    }
+
+    //丢弃指定数量的空闲虚拟机，优先丢弃虚拟机临时缓冲区中的空闲虚拟机，返回实际丢弃的数量
+    pub fn shrink(&self, count: usize) -> usize {
+        let mut shrinked = 0;
+        while shrinked < count {
+            match self.vm_buf_recv.try_recv().or_else(|_| self.pool.try_pop()) {
+                Ok(vm) => {
+                    shrinked += 1;
+                    emit_thrown(&vm, ThrowReason::Shrink);
+                },
+                Err(_) => break,
+            }
+        }
+
+        if shrinked > 0 {
+            self.throw(shrinked);
+        }
+        shrinked
     }
 
     //重置指定数量的虚拟机，返回生成前虚拟机池中虚拟机数量
@@ -341,7 +884,13 @@ impl VMFactory {
 
     //生成并取出一个无法复用的虚拟机，但未加载字节码
     pub fn take(&self) -> Option<Arc<JS>> {
-        JS::new(self.alloc_id.fetch_add(1, Ordering::Relaxed), self.name.clone(), self.auth.clone(), None)
+        let vm = JS::new(self.alloc_id.fetch_add(1, Ordering::Relaxed), self.name.clone(), self.auth.clone(), None);
+        if let Some(vm) = &vm {
+            if self.heap_limit > 0 {
+                vm.set_heap_limit(self.heap_limit);
+            }
+        }
+        vm
     }
 
     //获取虚拟机工厂字节码加载器
@@ -353,25 +902,69 @@ impl VMFactory {
         }
     }
 
-    //从虚拟机池中获取一个虚拟机，根据源创建同步任务队列，并调用指定的js全局函数
-    pub fn call(&self, src: Option<usize>, port: Atom, args: Box<FnOnce(Arc<JS>) -> usize>, info: Atom) {
+    //从虚拟机池中获取一个虚拟机，根据源创建同步任务队列，并调用指定的js全局函数，任务被拒绝则返回拒绝原因
+    pub fn call(&self, src: Option<usize>, port: Atom, args: Box<FnOnce(Arc<JS>) -> usize>, info: Atom) -> Result<(), RefuseReason> {
+        let options = match src {
+            None => CallOptions::new(),
+            Some(src) => CallOptions::new().set_order_key(src),
+        };
+        self.call_with_options(options, port, args, info)
+    }
+
+    //从虚拟机池中获取一个虚拟机，根据调用选项的排序键创建同步任务队列，并使用调用选项调用指定的js全局函数，任务被拒绝则返回拒绝原因
+    pub fn call_with_options(&self, options: CallOptions, port: Atom, args: Box<FnOnce(Arc<JS>) -> usize>, info: Atom) -> Result<(), RefuseReason> {
+        self.call_task(options, port, TaskArgs::Call(args), info)
+    }
+
+    //从虚拟机池中获取一个虚拟机，运行单次调用或批量调用的任务，任务被拒绝则返回拒绝原因
+    fn call_task(&self, options: CallOptions, port: Atom, args: TaskArgs, info: Atom) -> Result<(), RefuseReason> {
+        if self.is_closed() {
+            //虚拟机工厂已关闭，则立即拒绝
+            self.refuse(RefuseReason::Closed, &options, info);
+            return Err(RefuseReason::Closed);
+        }
+
+        if options.is_expired() {
+            //已过截止时间，则立即拒绝
+            self.refuse(RefuseReason::Expired, &options, info);
+            return Err(RefuseReason::Expired);
+        }
+
+        if let (Some(_), Some(session)) = (&self.affinity, options.session()) {
+            //已启用会话亲和，且调用设置了会话，则在会话绑定的虚拟机上运行
+            return self.call_session(session, FactoryTask {
+                options,
+                port,
+                args,
+                info,
+                time: Instant::now(),
+            });
+        }
+
+        let mut result = Ok(());
+
         //弹出虚拟机，以保证同一时间只有一个线程访问同一个虚拟机
         match self.pool.try_pop() {
             Ok(vm) => {
                 //有空闲虚拟机，则运行
-                self.async_run(vm, src, port, args, info);
+                self.async_run(vm, options, port, args, info, Instant::now());
             },
             _ => {
                 //当前虚拟机池没有空闲虚拟机，或当前虚拟机池已阻塞
                 if let Ok(vm) = self.vm_buf_recv.try_recv() {
                     //虚拟机临时缓冲区，有空闲虚拟机，则运行
-                    self.async_run(vm, src, port, args, info);
+                    self.async_run(vm, options, port, args, info, Instant::now());
                 } else {
                     //虚拟机临时缓冲区，没有空闲虚拟机
-                    if is_alloced_limit() {
-                        //当前进程内存已达到最大堆限制，则拒绝任务立即执行，并将任务加入当前虚拟机的任务调度队列中，记录当前拒绝的次数
-                        self.queue_sent.send((src, port, args, info));
-                        self.refuse_count.fetch_add(1, Ordering::Relaxed);
+                    if is_alloced_limit() || self.scaling.is_some() {
+                        //当前进程内存已达到最大堆限制，或已设置伸缩配置，则拒绝任务立即执行，并将任务加入当前虚拟机的任务调度队列中，记录当前拒绝的次数
+                        result = self.enqueue(FactoryTask {
+                            options,
+                            port,
+                            args,
+                            info,
+                            time: Instant::now(),
+                        });
                     } else {
                         //当前进程内存未达到最大堆限制，则立即构建新的虚拟机
                         match self.new_vm(self.auth.clone()) {
@@ -382,7 +975,7 @@ impl VMFactory {
                             },
                             Some(vm) => {
                                 //构建完成，则运行
-                                self.async_run(vm, src, port, args, info);
+                                self.async_run(vm, options, port, args, info, Instant::now());
                             },
                         }
                     }
@@ -391,115 +984,838 @@ impl VMFactory {
         }
 
         self.scheduling_count.fetch_add(1, Ordering::Relaxed); //增加虚拟机工厂调度次数
+        result
     }
 
-    //整理虚拟机工厂的虚拟机池
-    pub fn collect(&self, handler: Arc<Fn(&mut Arc<JS>) -> CollectResult>) {
-        self.pool.collect_from_bottom(handler); //从栈底开始整理
-    }
+    //在指定会话绑定的虚拟机上运行任务，会话未绑定则绑定一个空闲虚拟机，虚拟机正在运行则等待虚拟机空闲
+    //会话等待的任务与任务调度队列共享容量，任务调度队列已满时，丢弃最旧任务的策略丢弃会话最旧的等待任务，其它策略拒绝最新的任务，会话等待不会阻塞调用者
+    fn call_session(&self, session: usize, task: FactoryTask) -> Result<(), RefuseReason> {
+        let mut evicted = None;
+        let vm = {
+            let mut sessions = self.sessions.lock().unwrap();
+            if let Some(pinned) = sessions.get_mut(&session) {
+                if pinned.is_busy {
+                    //会话绑定的虚拟机正在运行，则预留任务调度队列的位置后等待虚拟机空闲，记录当前拒绝的次数
+                    self.refuse_count.fetch_add(1, Ordering::Relaxed);
+                    if self.try_reserve() {
+                        pinned.waits.push_back(task);
+                        return Ok(());
+                    }
 
-    //清空整理时被移除的栈帧
-    pub fn clear_collected(&self) {
-        self.pool.clear();
-    }
+                    let (reason, refused) = match self.overload_policy {
+                        OverloadPolicy::DropOldest if !pinned.waits.is_empty() => {
+                            //丢弃会话最旧的等待任务，最新的任务使用其位置
+                            let oldest = pinned.waits.pop_front().unwrap();
+                            pinned.waits.push_back(task);
+                            (RefuseReason::Dropped, oldest)
+                        },
+                        _ => (RefuseReason::Rejected, task),
+                    };
+                    drop(sessions);
+                    self.refuse(reason, &refused.options, refused.info);
+                    return if reason == RefuseReason::Dropped { Ok(()) } else { Err(reason) };
+                }
 
-    //构建一个虚拟机，加载所有字节码，并提供虚拟机本地对象授权，不会检查是否达到虚拟机工厂限制容量上限
-    fn new_vm(&self, auth: Arc<NativeObjsAuth>) -> Option<Arc<JS>> {
-        let start = VM_NEW_TIME.start();
+                pinned.is_busy = true;
+                pinned.last_time = Instant::now();
+                pinned.vm.clone()
+            } else {
+                let max_pinned = self.affinity.as_ref().map(|config| config.max_pinned()).unwrap_or(0);
+                if max_pinned > 0 && sessions.len() >= max_pinned {
+                    //绑定会话的虚拟机已达上限，则解绑最久未运行的空闲会话
+                    let lru = sessions.iter()
+                        .filter(|(_, pinned)| !pinned.is_busy)
+                        .min_by_key(|(_, pinned)| pinned.last_time)
+                        .map(|(key, _)| *key);
+                    match lru {
+                        None => {
+                            //没有空闲会话，则拒绝
+                            self.refuse(RefuseReason::Rejected, &task.options, task.info);
+                            return Err(RefuseReason::Rejected);
+                        },
+                        Some(key) => {
+                            if let Some(pinned) = sessions.remove(&key) {
+                                evicted = Some((key, pinned.vm));
+                            }
+                        },
+                    }
+                }
 
-        let mut curr_size = self.size();
-        loop {
-            match self.size.compare_and_swap(curr_size, curr_size + 1, Ordering::SeqCst) {
-                new_curr_size if new_curr_size == curr_size => {
-                    //原子增加当前虚拟机数量成功，则继续构建虚拟机
-                    break;
-                },
-                new_curr_size  => {
-                    //原子增加当前虚拟机数量失败，则从新的当前虚拟机数量开始重试
-                    curr_size = new_curr_size;
-                    pause();
+                let vm = match self.pool.try_pop().or_else(|_| self.vm_buf_recv.try_recv()) {
+                    Ok(vm) => Some(vm),
+                    Err(_) if is_alloced_limit() => None,
+                    Err(_) => self.new_vm(self.auth.clone()),
+                };
+                match vm {
+                    None => {
+                        //没有空闲虚拟机，且无法构建新的虚拟机，则释放会话绑定表的锁后拒绝
+                        drop(sessions);
+                        self.refuse(RefuseReason::Rejected, &task.options, task.info);
+                        if let Some((key, vm)) = evicted {
+                            self.evict_session(key, vm);
+                        }
+                        return Err(RefuseReason::Rejected);
+                    },
+                    Some(vm) => {
+                        vm.set_session(Some(session));
+                        sessions.insert(session, SessionVm {
+                            vm: vm.clone(),
+                            is_busy: true,
+                            is_unpin: false,
+                            last_time: Instant::now(),
+                            waits: VecDeque::new(),
+                        });
+                        info!("===> Vm Factory Pin Session Ok, factory: {:?}, session: {}, vm: {:?}", (&self.name).to_string(), session, vm);
+                        vm
+                    },
                 }
             }
-        }
-
-        let result = if !self.is_reused {
-            //构建一个无法复用的虚拟机
-            JS::new(self.alloc_id.fetch_add(1, Ordering::Relaxed), self.name.clone(), auth.clone(), None)
-        } else {
-            //构建一个可以复用的虚拟机
-            JS::new(self.alloc_id.fetch_add(1, Ordering::Relaxed), self.name.clone(), auth.clone(), Some((Arc::new(AtomicBool::new(false)), Arc::new(self.clone()))))
         };
 
-        match result {
-            None => None,
+        if let Some((key, vm)) = evicted {
+            self.evict_session(key, vm);
+        }
+
+        self.scheduling_count.fetch_add(1, Ordering::Relaxed); //增加虚拟机工厂调度次数
+        self.async_run(vm, task.options, task.port, task.args, task.info, task.time);
+        Ok(())
+    }
+
+    //绑定会话的虚拟机完成任务后，继续运行会话等待的任务，没有等待的任务则标记为空闲，需要解绑则解绑会话
+    pub(crate) fn release_session(&self, vm: Arc<JS>) {
+        let session = match vm.session() {
+            None => return,
+            Some(session) => session,
+        };
+
+        if vm.is_wait_throw() {
+            //虚拟机等待丢弃，则解绑会话并丢弃虚拟机，会话等待的任务将在新绑定的虚拟机上运行，会话状态将丢失
+            let waits = self.sessions.lock().unwrap().remove(&session).map(|pinned| pinned.waits).unwrap_or_default();
+            self.release_session_waits(waits.len());
+            vm.set_session(None);
+            self.throw(1);
+            warn!("!!!> Vm Factory Session Throw, factory: {:?}, session: {}, waits: {}, vm: {:?}", (&self.name).to_string(), session, waits.len(), vm);
+            emit_thrown(&vm, ThrowReason::WaitThrow);
+            for task in waits {
+                let _ = self.call_session(session, task);
+            }
+            return;
+        }
+
+        //在锁内取出会话的下一个任务，在锁外拒绝超时的任务和运行下一个任务，以避免持有会话绑定表的锁
+        let mut expired = Vec::new();
+        let (next, is_unpin) = {
+            let mut sessions = self.sessions.lock().unwrap();
+            let (next, is_unpin) = match sessions.get_mut(&session) {
+                None => (None, true),
+                Some(pinned) => {
+                    pinned.last_time = Instant::now();
+                    let mut next = None;
+                    while let Some(task) = pinned.waits.pop_front() {
+                        if self.is_expired(&task) {
+                            //任务已等待超时，则丢弃，并继续获取下一个任务
+                            expired.push(task);
+                            continue;
+                        }
+
+                        next = Some(task);
+                        break;
+                    }
+
+                    if next.is_some() {
+                        (next, false)
+                    } else {
+                        pinned.is_busy = false;
+                        (None, pinned.is_unpin || self.is_closed())
+                    }
+                },
+            };
+
+            if is_unpin {
+                //需要解绑会话
+                sessions.remove(&session);
+            }
+            (next, is_unpin)
+        };
+
+        //释放取出的任务占用的位置，并采样下一个任务的等待时长
+        self.release_session_waits(expired.len() + next.iter().count());
+        if let Some(task) = &next {
+            self.queue_latency.store(task.time.elapsed().as_micros() as usize, Ordering::Relaxed);
+        }
+
+        for task in expired {
+            self.refuse(RefuseReason::Expired, &task.options, task.info);
+        }
+
+        if let Some(task) = next {
+            //会话有等待的任务，则继续在当前虚拟机上运行
+            self.async_run(vm, task.options, task.port, task.args, task.info, task.time);
+            return;
+        }
+
+        if is_unpin {
+            self.evict_session(session, vm);
+        }
+    }
+
+    //解绑指定会话，会话空闲则立即解绑，否则在会话所有任务完成后解绑，返回会话是否存在
+    pub fn unpin_session(&self, session: usize) -> bool {
+        let vm = {
+            let mut sessions = self.sessions.lock().unwrap();
+            match sessions.get_mut(&session) {
+                None => return false,
+                Some(pinned) => {
+                    if pinned.is_busy {
+                        //会话正在运行，则在会话所有任务完成后解绑
+                        pinned.is_unpin = true;
+                        return true;
+                    }
+                },
+            }
+            sessions.remove(&session).unwrap().vm
+        };
+
+        self.evict_session(session, vm);
+        true
+    }
+
+    //解绑所有空闲超时的会话，返回解绑的会话数量
+    pub fn evict_idle_sessions(&self) -> usize {
+        let idle_timeout = match &self.affinity {
+            None => return 0,
+            Some(config) => config.idle_timeout(),
+        };
+
+        let evicted: Vec<(usize, Arc<JS>)> = {
+            let mut sessions = self.sessions.lock().unwrap();
+            let keys: Vec<usize> = sessions.iter()
+                .filter(|(_, pinned)| !pinned.is_busy && pinned.last_time.elapsed() >= idle_timeout)
+                .map(|(key, _)| *key)
+                .collect();
+            keys.into_iter().filter_map(|key| sessions.remove(&key).map(|pinned| (key, pinned.vm))).collect()
+        };
+
+        let count = evicted.len();
+        for (session, vm) in evicted {
+            self.evict_session(session, vm);
+        }
+        count
+    }
+
+    //解绑空闲虚拟机的会话，解绑前调用会话解绑处理器，解绑后重置虚拟机的全局环境并复用
+    fn evict_session(&self, session: usize, vm: Arc<JS>) {
+        if let Some(handler) = self.affinity.as_ref().and_then(|config| config.evict_handler()) {
+            handler(session, vm.clone());
+        }
+
+        vm.set_session(None);
+        info!("===> Vm Factory Unpin Session Ok, factory: {:?}, session: {}, vm: {:?}", (&self.name).to_string(), session, vm);
+
+        //解绑后的虚拟机与完成任务的虚拟机一样，在虚拟机任务中整理，以保证不会在调用者线程中重置全局环境
+        let func = Box::new(move |_lock| {
+            collect_vm(vm);
+        });
+        cast_js_task(TaskType::Async(false), JS_TASK_PRIORITY, None, func, Atom::from("vm session evict task"));
+    }
+
+    //从虚拟机池中获取一个虚拟机，在一个任务中连续调用指定的js全局函数，每个调用参数对应一次调用，所有调用完成后只整理一次虚拟机，任务被拒绝则返回拒绝原因
+    //每次调用完成后同步调用结果处理器，参数分别为虚拟机、调用的序号和调用结果，调用结果只在处理器中有效，批量调用中的js函数只允许同步执行
+    pub fn call_batch(&self,
+                      options: CallOptions,
+                      port: Atom,
+                      batch: Vec<Box<FnOnce(Arc<JS>) -> usize>>,
+                      mut handler: Box<FnMut(Arc<JS>, usize, Result<&JSType, BatchError>)>,
+                      info: Atom) -> Result<(), RefuseReason> {
+        let port_copy = port.clone();
+        let batch = Box::new(move |vm: Arc<JS>| {
+            let port = (&port_copy).to_string();
+            for (index, args) in batch.into_iter().enumerate() {
+                if !vm.get_link_function(port.clone()) {
+                    //调用的js全局函数不存在
+                    handler(vm.clone(), index, Err(BatchError::NotFound(port.clone())));
+                    continue;
+                }
+
+                let len = args(vm.clone());
+                match vm.try_invoke(len) {
+                    Err(reason) => handler(vm.clone(), index, Err(BatchError::Invoke(index, reason))),
+                    Ok(result) => handler(vm.clone(), index, Ok(&result)),
+                }
+            }
+        });
+
+        self.call_task(options, port, TaskArgs::Batch(batch), info)
+    }
+
+    //将任务加入任务调度队列，任务调度队列已满则根据过载策略处理，阻塞策略会阻塞当前线程，在虚拟机任务中使用阻塞策略则拒绝最新的任务
+    fn enqueue(&self, task: FactoryTask) -> Result<(), RefuseReason> {
+        self.refuse_count.fetch_add(1, Ordering::Relaxed);
+
+        while !self.try_reserve() {
+            //任务调度队列已满
+            match self.overload_policy {
+                OverloadPolicy::RejectNewest => {
+                    self.refuse(RefuseReason::Rejected, &task.options, task.info);
+                    return Err(RefuseReason::Rejected);
+                },
+                OverloadPolicy::DropOldest => {
+                    //丢弃最旧的任务后，重新预留位置，位置可能已被其它调用者占用
+                    if let Some(oldest) = self.dequeue() {
+                        self.refuse(RefuseReason::Dropped, &oldest.options, oldest.info);
+                        continue;
+                    }
+
+                    //任务调度队列为空，但位置已被其它调用者预留，则与阻塞策略一样等待预留的任务加入或位置释放，避免空转
+                    let (lock, cvar) = &*self.queue_space;
+                    let guard = lock.lock().unwrap();
+                    if self.try_reserve() {
+                        break;
+                    }
+                    let _ = cvar.wait_timeout(guard, Duration::from_millis(MAX_QUEUE_BLOCK_WAIT_TIME)).unwrap();
+                },
+                OverloadPolicy::Block(_) if is_in_vm_task() => {
+                    //阻塞虚拟机任务会占用执行虚拟机任务的线程，可能导致任务调度队列无法被消费，所以拒绝最新的任务
+                    warn!("!!!> Vm Factory Block In Vm Task, factory: {:?}", (&self.name).to_string());
+                    self.refuse(RefuseReason::Rejected, &task.options, task.info);
+                    return Err(RefuseReason::Rejected);
+                },
+                OverloadPolicy::Block(timeout) => {
+                    let elapsed = task.time.elapsed();
+                    if elapsed >= timeout {
+                        self.refuse(RefuseReason::Timeout, &task.options, task.info);
+                        return Err(RefuseReason::Timeout);
+                    }
+
+                    //在锁内再次尝试预留位置，以保证不会错过空闲位置的通知，等待时长有上限，以应对通知前已出队的情况
+                    let (lock, cvar) = &*self.queue_space;
+                    let guard = lock.lock().unwrap();
+                    if self.try_reserve() {
+                        break;
+                    }
+                    let wait = (timeout - elapsed).min(Duration::from_millis(MAX_QUEUE_BLOCK_WAIT_TIME));
+                    let _ = cvar.wait_timeout(guard, wait).unwrap();
+                },
+            }
+        }
+
+        //在锁内加入任务，以保证任务的调用时间与任务的顺序一致，并采样最旧任务的等待时长，加入后通知等待丢弃最旧任务的调用者
+        let (lock, cvar) = &*self.queue_space;
+        let mut times = lock.lock().unwrap();
+        if let Some(time) = times.front() {
+            self.queue_latency.store(time.elapsed().as_micros() as usize, Ordering::Relaxed);
+        }
+        times.push_back(task.time);
+        self.queue_sent.send(task);
+        cvar.notify_all();
+        Ok(())
+    }
+
+    //原子的预留任务调度队列的一个位置，任务调度队列已满返回false
+    fn try_reserve(&self) -> bool {
+        let mut reserved = self.queue_reserved.load(Ordering::SeqCst);
+        loop {
+            if self.queue_capacity > 0 && reserved >= self.queue_capacity {
+                return false;
+            }
+
+            match self.queue_reserved.compare_exchange_weak(reserved, reserved + 1, Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(_) => return true,
+                Err(current) => reserved = current,
+            }
+        }
+    }
+
+    //释放会话等待的任务占用的任务调度队列的位置，并通知阻塞的调用者
+    fn release_session_waits(&self, count: usize) {
+        if count == 0 {
+            return;
+        }
+
+        let (lock, cvar) = &*self.queue_space;
+        let _guard = lock.lock().unwrap();
+        self.queue_reserved.fetch_sub(count, Ordering::SeqCst);
+        cvar.notify_all();
+    }
+
+    //从任务调度队列中取出最旧的任务，采样任务的等待时长，并释放任务占用的位置，通知阻塞的调用者
+    fn dequeue(&self) -> Option<FactoryTask> {
+        let (lock, cvar) = &*self.queue_space;
+        let mut times = lock.lock().unwrap();
+        match self.queue_recv.try_recv() {
+            Err(_) => {
+                self.queue_latency.store(0, Ordering::Relaxed);
+                None
+            },
+            Ok(task) => {
+                times.pop_front();
+                self.queue_latency.store(task.time.elapsed().as_micros() as usize, Ordering::Relaxed);
+                self.queue_reserved.fetch_sub(1, Ordering::SeqCst);
+                cvar.notify_all();
+                Some(task)
+            },
+        }
+    }
+
+    //使用空闲虚拟机运行任务调度队列中等待的任务，返回使用的空闲虚拟机数量
+    pub(crate) fn dispatch_queued(&self) -> usize {
+        let mut count = 0;
+        while self.queue_len() > 0 {
+            match self.vm_buf_recv.try_recv().or_else(|_| self.pool.try_pop()) {
+                Err(_) => break,
+                Ok(vm) => {
+                    self.reuse(vm);
+                    count += 1;
+                },
+            }
+        }
+        count
+    }
+
+    //判断任务是否在任务调度队列中等待超时，或已过截止时间
+    fn is_expired(&self, task: &FactoryTask) -> bool {
+        if task.options.is_expired() {
+            return true;
+        }
+
+        if let Some(timeout) = self.task_timeout {
+            task.time.elapsed() >= timeout
+        } else {
+            false
+        }
+    }
+
+    //拒绝任务，并通知拒绝任务处理器
+    fn refuse(&self, reason: RefuseReason, options: &CallOptions, info: Atom) {
+        warn!("!!!> Vm Factory Refuse Task, factory: {:?}, reason: {:?}, options: {:?}, info: {:?}",
+              (&self.name).to_string(), reason, options, info);
+        self.metrics.incr_refuse();
+        if is_listening() {
+            emit(VmEvent::Refused {
+                factory: (&self.name).to_string(),
+                reason,
+                info: info.clone(),
+            });
+        }
+
+        if let Some(handler) = &self.refuse_handler {
+            handler(reason, info);
+        }
+    }
+
+    //关闭虚拟机工厂，关闭后拒绝所有调用，并在宽限时间内完成正在运行的任务和任务调度队列中的任务，超时未完成的任务将被丢弃，
+    //最后从全局虚拟机工厂注册表和虚拟机整理队列中移除，并销毁所有空闲虚拟机，会阻塞当前线程
+    pub fn shutdown(&self, grace: Duration) -> ShutdownReport {
+        let start = Instant::now();
+        let finished_start = *self.finished.0.lock().unwrap();
+        let mut destroyed = 0;
+        self.closed.store(true, Ordering::SeqCst);
+        info!("===> Vm Factory Shutdown Start, factory: {:?}, size: {}, queue: {}", (&self.name).to_string(), self.size(), self.queue_len());
+
+        //解绑所有会话，正在运行的会话将在完成所有等待的任务后解绑
+        let sessions: Vec<usize> = self.sessions.lock().unwrap().keys().cloned().collect();
+        for session in sessions {
+            self.unpin_session(session);
+        }
+
+        //在宽限时间内完成正在运行的任务和任务调度队列中的任务
+        while (self.size() > 0 || self.queue_len() > 0) && start.elapsed() < grace {
+            match self.vm_buf_recv.try_recv().or_else(|_| self.pool.try_pop()) {
+                Ok(vm) => {
+                    if let Some(task) = self.dequeue() {
+                        //有空闲虚拟机，且有等待的任务，则运行
+                        if self.is_expired(&task) {
+                            self.refuse(RefuseReason::Expired, &task.options, task.info);
+                            self.reuse(vm);
+                        } else {
+                            self.async_run(vm, task.options, task.port, task.args, task.info, task.time);
+                        }
+                    } else {
+                        //有空闲虚拟机，且没有等待的任务，则销毁
+                        self.throw(1);
+                        emit_thrown(&vm, ThrowReason::Shutdown);
+                        destroyed += 1;
+                    }
+                },
+                Err(_) => {
+                    //没有空闲虚拟机，则等待正在运行的虚拟机完成，等待时长有上限，以应对等待前已归还的情况
+                    let elapsed = start.elapsed();
+                    if elapsed >= grace {
+                        break;
+                    }
+
+                    let (lock, cvar) = &*self.finished;
+                    let guard = lock.lock().unwrap();
+                    let wait = (grace - elapsed).min(Duration::from_millis(MAX_SHUTDOWN_WAIT_TIME));
+                    let _ = cvar.wait_timeout(guard, wait).unwrap();
+                },
+            }
+        }
+        let finished = *self.finished.0.lock().unwrap() - finished_start;
+
+        //丢弃宽限时间内未完成的任务
+        let mut dropped = 0;
+        while let Some(task) = self.dequeue() {
+            self.refuse(RefuseReason::Closed, &task.options, task.info);
+            dropped += 1;
+        }
+
+        //销毁剩余的空闲虚拟机
+        destroyed += self.shrink(usize::max_value());
+
+        //从全局虚拟机工厂注册表和虚拟机整理队列中移除
+        let factory_name = (&self.name).to_string();
+        VM_FACTORY_REGISTERS.write().unwrap().remove(&factory_name);
+        VM_COLLECT_QUEUE.lock().unwrap().retain(|name| name != &factory_name);
+
+        //释放模板虚拟机
+        if let Some(template) = &self.template {
+            *template.lock().unwrap() = VMTemplate::Unavailable;
+        }
+
+        let report = ShutdownReport {
+            finished,
+            dropped,
+            destroyed,
+            remaining: self.size(),
+            elapsed: start.elapsed(),
+        };
+        info!("===> Vm Factory Shutdown Finish, factory: {:?}, report: {:?}", factory_name, report);
+        report
+    }
+
+    //记录指定虚拟机重置全局环境后的堆大小，并检查是否在连续复用中持续增长，返回是否需要主动丢弃虚拟机
+    pub fn check_leak(&self, vm: &Arc<JS>) -> bool {
+        let (count, is_retire) = match self.leak_detect {
+            None => return false,
+            Some(detect) => detect,
+        };
+
+        let heap_sizes = vm.record_reset_heap_size(vm.heap_size(), count);
+        if heap_sizes.len() < count || heap_sizes.windows(2).any(|sizes| sizes[0] >= sizes[1]) {
+            //复用次数不足，或堆未持续增长
+            return false;
+        }
+
+        //堆持续增长，则报告内存泄漏，并重新开始记录
+        vm.clear_reset_heap_sizes();
+        VM_LEAK_COUNT.sum(1);
+        let report = LeakReport {
+            factory: (&self.name).to_string(),
+            vm_id: vm.get_id(),
+            heap_sizes,
+            infos: vm.task_infos(),
+            is_retired: is_retire,
+        };
+        warn!("!!!> Vm Leak, factory: {:?}, vm: {:?}, heap sizes: {:?}, infos: {:?}",
+              report.factory, vm, report.heap_sizes, report.infos);
+
+        if let Some(handler) = &self.leak_handler {
+            handler(report);
+        }
+
+        is_retire
+    }
+
+    //整理虚拟机工厂的虚拟机池
+    pub fn collect(&self, handler: Arc<Fn(&mut Arc<JS>) -> CollectResult>) {
+        self.pool.collect_from_bottom(handler); //从栈底开始整理
+    }
+
+    //清空整理时被移除的栈帧
+    pub fn clear_collected(&self) {
+        self.pool.clear();
+    }
+
+    //构建一个虚拟机，加载所有字节码，并提供虚拟机本地对象授权，不会检查是否达到虚拟机工厂限制容量上限
+    fn new_vm(&self, auth: Arc<NativeObjsAuth>) -> Option<Arc<JS>> {
+        let start = VM_NEW_TIME.start();
+        let now = Instant::now();
+
+        let mut curr_size = self.size();
+        loop {
+            match self.size.compare_and_swap(curr_size, curr_size + 1, Ordering::SeqCst) {
+                new_curr_size if new_curr_size == curr_size => {
+                    //原子增加当前虚拟机数量成功，则继续构建虚拟机
+                    break;
+                },
+                new_curr_size  => {
+                    //原子增加当前虚拟机数量失败，则从新的当前虚拟机数量开始重试
+                    curr_size = new_curr_size;
+                    pause();
+                }
+            }
+        }
+
+        let vm_id = self.alloc_id.fetch_add(1, Ordering::Relaxed);
+        if let Some(vm) = self.clone_vm(vm_id, auth.clone()) {
+            //从模板虚拟机复制成功，则不需要加载字节码
+            VM_NEW_TIME.timing(start);
+            let result = self.init_vm(vm);
+            self.created_vm(vm_id, &result, now.elapsed());
+            return result;
+        }
+
+        //构建虚拟机，可以复用的虚拟机需要绑定回收器
+        let result = match JS::new(vm_id, self.name.clone(), auth.clone(), self.new_collection()) {
+            None => None,
             Some(vm) => {
                 VM_NEW_TIME.timing(start);
                 let start = VM_LOAD_TIME.start();
 
                 //为当前虚拟机加载当前虚拟机工厂绑定的所有字节码
-                for code in self.codes.iter() {
-                    if vm.load(code.as_slice()) {
-                        while !vm.is_ran() {
-                            pause();
-                        }
-                        continue;
+                if !self.load_codes(&vm) {
+                    None
+                } else {
+                    let result = self.init_vm(vm);
+                    if result.is_some() {
+                        VM_LOAD_TIME.timing(start);
                     }
-                    return None;
+                    result
                 }
+            }
+        };
+        self.created_vm(vm_id, &result, now.elapsed());
+        result
+    }
 
-                //如果是可以复用的虚拟机，则需要创建全局对象模板，并替换当前全局对象
-                if self.is_reused {
-                    if !vm.new_global_template() {
-                        warn!("!!!> Vm Factory Create Vm Error, new global template failed, factory: {:?}",
-                                 (&self.name).to_string());
-                        return None;
-                    }
+    //记录虚拟机构建的结果，并通知虚拟机事件监听器
+    fn created_vm(&self, vm_id: usize, result: &Option<Arc<JS>>, elapsed: Duration) {
+        match result {
+            None => {
+                self.metrics.incr_load_failed();
+                if is_listening() {
+                    emit(VmEvent::LoadFailed {
+                        factory: (&self.name).to_string(),
+                        vm_id,
+                        elapsed,
+                    });
+                }
+            },
+            Some(vm) => {
+                self.metrics.record_load(elapsed);
+                if is_listening() {
+                    emit(VmEvent::Created {
+                        factory: (&self.name).to_string(),
+                        vm_id,
+                        heap_size: vm.heap_size(),
+                        elapsed,
+                    });
+                }
+            },
+        }
+    }
 
-                    if !vm.alloc_global() {
-                        warn!("!!!> Vm Factory Create Vm Error, alloc global failed, factory: {:?}",
-                                 (&self.name).to_string());
-                        return None;
-                    }
+    //构建可以复用的虚拟机的回收器，无法复用的虚拟机返回空
+    fn new_collection(&self) -> Option<(Arc<AtomicBool>, Arc<VMFactory>)> {
+        if self.is_reused {
+            Some((Arc::new(AtomicBool::new(false)), Arc::new(self.clone())))
+        } else {
+            None
+        }
+    }
 
-                    vm.unlock_collection(); //解锁回收器，必须在虚拟机初始化、加载代码、运行代码等操作后解锁
+    //为指定虚拟机加载当前虚拟机工厂绑定的所有字节码
+    fn load_codes(&self, vm: &Arc<JS>) -> bool {
+        for code in self.codes.iter() {
+            if vm.load(code.as_slice()) {
+                while !vm.is_ran() {
+                    pause();
                 }
+                continue;
+            }
+            return false;
+        }
+
+        true
+    }
+
+    //从模板虚拟机复制一个虚拟机，模板虚拟机未构建则构建模板虚拟机，未启用模板、模板不可用或复制失败则返回空
+    fn clone_vm(&self, vm_id: usize, auth: Arc<NativeObjsAuth>) -> Option<Arc<JS>> {
+        let template = match &self.template {
+            None => return None,
+            Some(template) => template,
+        };
+
+        //锁住模板虚拟机，以保证同一时间只有一个线程构建或复制模板虚拟机
+        let mut template = template.lock().unwrap();
+        if let VMTemplate::Uninit = *template {
+            //模板虚拟机未构建，则构建一个无法复用的模板虚拟机，并加载所有字节码，构建失败则不再重试
+            *template = match JS::new(self.alloc_id.fetch_add(1, Ordering::Relaxed), self.name.clone(), self.auth.clone(), None) {
+                Some(ref vm) if !self.load_codes(vm) => {
+                    warn!("!!!> Vm Factory Create Template Error, load code failed, factory: {:?}",
+                          (&self.name).to_string());
+                    VMTemplate::Unavailable
+                },
+                Some(ref vm) if vm.has_native_objects() => {
+                    //本地对象无法复制，则不使用模板
+                    warn!("!!!> Vm Factory Create Template Error, native objects can not be cloned, factory: {:?}",
+                          (&self.name).to_string());
+                    VMTemplate::Unavailable
+                },
+                Some(vm) => {
+                    info!("===> Vm Factory Create Template Ok, factory: {:?}, vm: {:?}",
+                          (&self.name).to_string(), vm);
+                    VMTemplate::Ready(vm)
+                },
+                None => {
+                    warn!("!!!> Vm Factory Create Template Error, new vm failed, factory: {:?}",
+                          (&self.name).to_string());
+                    VMTemplate::Unavailable
+                },
+            };
+        }
 
-                vm.update_last_heap_size(); //更新初始化后虚拟机的堆大小和内存占用
-                vm.update_last_time(); //更新虚拟机初始运行时间
+        let vm = match *template {
+            VMTemplate::Ready(ref vm) => vm,
+            _ => return None,
+        };
 
-                info!("===> Vm Factory Create Vm Ok, factory: {:?}, vm: {:?}",
-                         (&self.name).to_string(), vm);
+        let start = VM_CLONE_TIME.start();
+        if let Some(vm) = JS::clone_from(vm, vm_id, self.name.clone(), auth, self.new_collection()) {
+            VM_CLONE_TIME.timing(start);
+            return Some(vm);
+        }
 
-                VM_LOAD_TIME.timing(start);
-                VM_COUNT.sum(1);
+        VM_CLONE_FAILED_COUNT.sum(1);
+        warn!("!!!> Vm Factory Clone Vm Error, reload code, factory: {:?}",
+              (&self.name).to_string());
+        None
+    }
+
+    //初始化已加载所有字节码的虚拟机
+    fn init_vm(&self, vm: Arc<JS>) -> Option<Arc<JS>> {
+        //如果是可以复用的虚拟机，则需要创建全局对象模板，并替换当前全局对象
+        if self.is_reused {
+            if !vm.new_global_template() {
+                warn!("!!!> Vm Factory Create Vm Error, new global template failed, factory: {:?}",
+                         (&self.name).to_string());
+                return None;
+            }
 
-                Some(vm)
+            if !vm.alloc_global() {
+                warn!("!!!> Vm Factory Create Vm Error, alloc global failed, factory: {:?}",
+                         (&self.name).to_string());
+                return None;
             }
+
+            vm.unlock_collection(); //解锁回收器，必须在虚拟机初始化、加载代码、运行代码等操作后解锁
+        }
+
+        if self.heap_limit > 0 {
+            //设置了虚拟机堆限制，则在加载代码后限制虚拟机堆
+            vm.set_heap_limit(self.heap_limit);
         }
+
+        vm.update_last_heap_size(); //更新初始化后虚拟机的堆大小和内存占用
+        vm.update_last_time(); //更新虚拟机初始运行时间
+
+        info!("===> Vm Factory Create Vm Ok, factory: {:?}, vm: {:?}",
+                 (&self.name).to_string(), vm);
+
+        VM_COUNT.sum(1);
+
+        Some(vm)
     }
 
-    //异步运行指定虚拟机
-    fn async_run(&self, vm: Arc<JS>, src: Option<usize>, port: Atom, args: Box<FnOnce(Arc<JS>) -> usize>, info: Atom) {
+    //异步运行指定虚拟机，时间为任务的调用时间，用于记录任务的等待延迟
+    fn async_run(&self, vm: Arc<JS>, options: CallOptions, port: Atom, args: TaskArgs, info: Atom, time: Instant) {
         let vm_copy = vm.clone();
+        let metrics = self.metrics.clone();
+        let task_info = info.clone();
+        let priority = options.priority();
+        let source = options.order_key().map(|src_id| acquire_queue(src_id));
+        let source_copy = source.clone();
+        if let Some(tenant_id) = options.tenant_id() {
+            //记录租户调用数量
+            tenant_call_count(tenant_id);
+        }
+
+        //启用跟踪时，为本次调用构建子跨度，并替换调用选项的跟踪上下文，本地函数可以通过调用选项获取
+        //调用选项没有跟踪上下文时，使用当前线程正在执行的跟踪上下文，以保证在本地函数或生成进程中的调用可以延续跟踪
+        let (options, span) = if is_tracing() {
+            let parent = options.trace().or_else(current);
+            let context = parent.map(|parent| parent.child()).unwrap_or_else(TraceContext::new);
+            (options.set_trace(context), Some((context, parent.map(|parent| parent.span_id()), self.name.clone(), info.clone())))
+        } else {
+            (options, None)
+        };
+
         let func = Box::new(move |lock: Option<isize>| {
+            let queue_wait = time.elapsed();
+            metrics.record_queue_wait(queue_wait); //记录任务从调用到开始执行的等待延迟
             if let Some(queue) = lock {
                 //为虚拟机设置当前任务的队列，将会重置可复用虚拟机的当前任务队列
                 vm_copy.set_tasks(queue);
             }
-            vm_copy.get_link_function((&port).to_string());
-            let args_size = args(vm_copy.clone());
-            vm_copy.call(args_size);
+            vm_copy.push_task_info(task_info.clone()); //记录当前任务信息
+            vm_copy.set_call_options(Some(options)); //设置当前任务的调用选项，本地函数可以在调用期间获取
+            vm_copy.set_source(source_copy); //设置当前任务所属源的同步任务队列，在虚拟机解锁同步任务队列时完成
+            let args = match args {
+                TaskArgs::Call(args) => {
+                    vm_copy.get_link_function((&port).to_string());
+                    Ok(args(vm_copy.clone()))
+                },
+                TaskArgs::Batch(batch) => Err(batch), //批量调用需要在跟踪上下文中执行，以保证本地函数可以获取
+            };
+            let vm_id = vm_copy.get_id();
+            let last = span.as_ref().map(|(context, _, _, _)| enter(Some(*context))); //设置当前线程正在执行的跟踪上下文
+            start_slow(&vm_copy, SlowKind::Task, || (&port).to_string(), &task_info, queue_wait); //在调用完成的回应中检查慢调用，调用返回后虚拟机可能已被复用
+            let start = Instant::now();
+            match args {
+                Ok(args_size) => vm_copy.call(args_size),
+                Err(batch) => {
+                    //连续同步调用后结束任务，结束时与调用完成一样处理消息队列并整理虚拟机
+                    batch(vm_copy.clone());
+                    JS::finish(vm_copy);
+                },
+            }
+            let elapsed = start.elapsed();
+            metrics.record_execution(elapsed); //记录任务同步执行的延迟
+
+            if let Some((context, parent_id, factory, info)) = span {
+                //恢复当前线程的跟踪上下文，并记录本次调用的跨度
+                enter(last.unwrap_or(None));
+                record(Span::new(context, parent_id, (&port).to_string(), queue_wait, elapsed, vec![
+                    ("factory", (&factory).to_string()),
+                    ("info", (&info).to_string()),
+                    ("vm", vm_id.to_string()),
+                ]));
+            }
         });
-        match src {
+        match source {
             None => {
-                cast_js_task(TaskType::Async(false), JS_TASK_PRIORITY, None, func, info);
+                cast_js_task(TaskType::Async(false), priority.unwrap_or(JS_TASK_PRIORITY), None, func, info);
             },
-            Some(src_id) => {
-                cast_js_task(TaskType::Sync(true), 0, Some(new_queue(src_id)), func, info);
+            Some(source) => {
+                cast_js_task(TaskType::Sync(true), priority.unwrap_or(0), Some(source.queue()), func, info);
             },
         }
 
         VM_CALL_COUNT.sum(1);
+        self.metrics.incr_call();
+    }
+}
+
+//增加指定租户的调用数量，租户数量已达上限，则记录为其它租户的调用数量
+fn tenant_call_count(tenant_id: &Atom) {
+    let mut counts = VM_TENANT_CALL_COUNTS.lock().unwrap();
+    if !counts.contains_key(tenant_id) {
+        if counts.len() >= MAX_TENANT_CALL_COUNTS {
+            VM_OTHER_TENANT_CALL_COUNT.sum(1);
+            return;
+        }
+
+        match GLOBAL_PREF_COLLECT.new_static_counter(Atom::from(format!("vm_call_count_{}", (&tenant_id).to_string())), 0) {
+            None => return,
+            Some(counter) => {
+                counts.insert(tenant_id.clone(), counter);
+            },
+        }
+    }
+
+    if let Some(counter) = counts.get(tenant_id) {
+        counter.sum(1);
     }
 }
 
@@ -513,37 +1829,176 @@ pub enum BlockError {
     SetGlobalVar(String),
 }
 
-//线程安全的构建指定源的同步任务队列，如果已存在，则忽略
+/*
+* 源的同步任务队列
+*/
+pub struct SourceQueue {
+    queue:      isize,          //同步任务队列
+    last_time:  AtomicUsize,    //最近使用时间，单位us
+    pending:    AtomicUsize,    //已投递但未执行完成，或仍锁住同步任务队列的任务数量
+    sweepable:  AtomicBool,     //是否允许空闲时被自动整理
+}
+
+impl SourceQueue {
+    //构建源的同步任务队列
+    fn new(sweepable: bool) -> Self {
+        SourceQueue {
+            queue: create_js_task_queue(JS_TASK_PRIORITY, false),
+            last_time: AtomicUsize::new(now_utc()),
+            pending: AtomicUsize::new(0),
+            sweepable: AtomicBool::new(sweepable),
+        }
+    }
+
+    //获取同步任务队列
+    pub fn queue(&self) -> isize {
+        self.queue
+    }
+
+    //获取最近使用时间
+    pub fn last_time(&self) -> usize {
+        self.last_time.load(Ordering::Relaxed)
+    }
+
+    //获取已投递但未执行完成的任务数量
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::SeqCst)
+    }
+
+    //判断是否允许空闲时被自动整理
+    pub fn is_sweepable(&self) -> bool {
+        self.sweepable.load(Ordering::SeqCst)
+    }
+
+    //更新最近使用时间
+    fn touch(&self) {
+        self.last_time.store(now_utc(), Ordering::Relaxed);
+    }
+
+    //任务执行完成，在解锁同步任务队列时调用
+    pub(crate) fn finish(&self) {
+        self.touch();
+        self.pending.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+//线程安全的构建指定源的同步任务队列，如果已存在，则忽略，通过此函数获取的同步任务队列不会被自动整理，需要调用remove_queue移除
 pub fn new_queue(src: usize) -> isize {
     //检查指定源的同步任务队列是否存在
     {
         let queues = VM_FACTORY_QUEUES.read().unwrap();
         if let Some(q) = (*queues).get(&src) {
             //存在，则返回
-            return q.clone();
+            q.sweepable.store(false, Ordering::SeqCst);
+            q.touch();
+            return q.queue;
         }
     }
 
     //为指定源创建同步任务队列
     {
-        let queue = create_js_task_queue(JS_TASK_PRIORITY, false);
         let mut queues = VM_FACTORY_QUEUES.write().unwrap();
-        (*queues).insert(src, queue.clone());
-        queue
+        let q = (*queues).entry(src).or_insert_with(|| Arc::new(SourceQueue::new(false)));
+        q.sweepable.store(false, Ordering::SeqCst);
+        q.queue
+    }
+}
+
+//线程安全的获取指定源的同步任务队列，并增加等待任务数量，如果不存在，则构建可自动整理的同步任务队列
+//必须在持有表锁时增加等待任务数量，以保证不会整理正在投递任务的同步任务队列
+fn acquire_queue(src: usize) -> Arc<SourceQueue> {
+    {
+        let queues = VM_FACTORY_QUEUES.read().unwrap();
+        if let Some(q) = (*queues).get(&src) {
+            q.pending.fetch_add(1, Ordering::SeqCst);
+            q.touch();
+            return q.clone();
+        }
     }
+
+    let mut queues = VM_FACTORY_QUEUES.write().unwrap();
+    let q = (*queues).entry(src).or_insert_with(|| Arc::new(SourceQueue::new(true)));
+    q.pending.fetch_add(1, Ordering::SeqCst);
+    q.touch();
+    q.clone()
 }
 
 //线程安全的移除指定源的同步任务队列，如果不存在，则忽略
 pub fn remove_queue(src: usize) -> Option<isize> {
     let mut queues = VM_FACTORY_QUEUES.write().unwrap();
     if let Some(q) = (*queues).remove(&src) {
-        if remove_js_task_queue(q) {
-            return Some(q);
+        if remove_js_task_queue(q.queue) {
+            return Some(q.queue);
         }
     }
     None
 }
 
+//获取同步任务队列的数量
+pub fn queue_size() -> usize {
+    VM_FACTORY_QUEUES.read().unwrap().len()
+}
+
+//线程安全的整理空闲的同步任务队列，只移除允许自动整理、没有等待任务且空闲时长超过指定时长的同步任务队列，返回移除的数量
+//被移除的源再次调用时会构建新的同步任务队列，因为被移除时没有等待任务，所以不会破坏源的任务顺序
+pub fn sweep_idle_queues(ttl: Duration) -> usize {
+    let now = now_utc();
+    let ttl = ttl.as_micros() as usize;
+    let mut count = 0;
+    let mut queues = VM_FACTORY_QUEUES.write().unwrap();
+    queues.retain(|src, q| {
+        if !q.is_sweepable() || q.pending() > 0 || now.saturating_sub(q.last_time()) < ttl {
+            //不允许整理、有等待任务或未超过空闲时长，则保留
+            return true;
+        }
+
+        if !remove_js_task_queue(q.queue) {
+            warn!("!!!> Sweep Idle Queue Error, remove js task queue failed, src: {}, queue: {}", src, q.queue);
+            return true;
+        }
+
+        count += 1;
+        false
+    });
+
+    if count > 0 {
+        VM_QUEUE_SWEEP_COUNT.sum(count);
+        info!("===> Sweep Idle Queue Ok, count: {}, remaining: {}", count, queues.len());
+    }
+    count
+}
+
+//注册同步任务队列整理定时器，定时整理空闲时长超过指定时长的同步任务队列
+pub fn register_queue_sweep_timer(interval: usize, ttl: Duration) {
+    if VM_QUEUE_SWEEP_STOPPED.load(Ordering::SeqCst) {
+        //同步任务队列整理定时器已停止，则忽略
+        return;
+    }
+
+    let runner = FuncRuner::new(Box::new(move || {
+        let func = Box::new(move |_lock| {
+            if VM_QUEUE_SWEEP_STOPPED.load(Ordering::SeqCst) {
+                //同步任务队列整理定时器已停止，则不再整理，也不再注册下次整理
+                return;
+            }
+
+            sweep_idle_queues(ttl);
+
+            if interval > 0 {
+                register_queue_sweep_timer(interval, ttl);
+            }
+        });
+        cast_js_task(TaskType::Async(false), JS_TASK_PRIORITY, None, func, Atom::from("vm queue sweep task"));
+    }));
+
+    TIMER.set_timeout(runner, interval as u32);
+}
+
+//停止同步任务队列整理定时器，停止后无法重新注册
+pub fn stop_queue_sweep_timer() {
+    VM_QUEUE_SWEEP_STOPPED.store(true, Ordering::SeqCst);
+}
+
 /*
 * 线程安全的在阻塞调用中设置全局变量，设置成功后执行下一个操作
 * 全局变量构建函数执行成功后，当前值栈必须存在且只允许存在一个值，失败则必须移除在值栈上的构建的所有值
@@ -610,6 +2065,7 @@ pub fn block_reply(js: Arc<JS>, result: Box<FnOnce(Arc<JS>)>, info: Atom) {
                 let status = dukc_vm_status_switch(copy_js.get_vm(), JSStatus::MultiTask as i8, JSStatus::SingleTask as i8);
                 if status == JSStatus::MultiTask as i8 {
                     //同步任务已阻塞虚拟机，则返回指定的值，并唤醒虚拟机继续同步执行
+                    check_block_slow(&copy_js, &copy_info);
                     dukc_wakeup(copy_js.get_vm(), 0);
                     result(copy_js.clone());
                     dukc_continue(copy_js.get_vm(), js_reply_callback);
@@ -646,6 +2102,7 @@ pub fn block_throw(js: Arc<JS>, reason: String, info: Atom) {
                 let status = dukc_vm_status_switch(copy_js.get_vm(), JSStatus::MultiTask as i8, JSStatus::SingleTask as i8);
                 if status == JSStatus::MultiTask as i8 {
                     //同步任务已阻塞虚拟机，则抛出指定原因的错误，并唤醒虚拟机继续同步执行
+                    check_block_slow(&copy_js, &copy_info);
                     let reason_ptr = CString::into_raw(CString::new(reason).unwrap());
                     dukc_wakeup(copy_js.get_vm(), 1);
                     dukc_new_error(copy_js.get_vm(), reason_ptr as *const c_char);
@@ -668,6 +2125,13 @@ pub fn block_throw(js: Arc<JS>, reason: String, info: Atom) {
     }
 }
 
+//检查同步阻塞调用从阻塞到回应的时长是否超过慢调用阈值，必须在唤醒虚拟机前调用，以获取阻塞时的js栈
+fn check_block_slow(js: &Arc<JS>, info: &Atom) {
+    if let Some((hash, start)) = js.take_block_start() {
+        check_slow(js, SlowKind::Block, || format!("native {}", hash), info, Duration::new(0, 0), start.elapsed());
+    }
+}
+
 /*
 * 线程安全的向虚拟机推送异步回调函数，延迟任务必须返回任务句柄，其它任务根据是否是动态任务确定是否返回任务句柄
 */
@@ -726,6 +2190,15 @@ pub fn register_async_request(name: Atom, handler: Arc<Handler<A = Arc<Vec<u8>>,
     (*channels).set(name, handler)
 }
 
+/*
+* 线程安全的获取虚拟机通道已注册的异步调用数量
+*/
+pub fn async_request_size() -> usize {
+    let ref lock = &**VM_CHANNELS;
+    let channels = lock.read().unwrap();
+    (*channels).size()
+}
+
 /*
 * 线程安全的在虚拟机通道注销异步调用
 */
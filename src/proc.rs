@@ -4,6 +4,8 @@ use std::sync::atomic::{AtomicU8, Ordering};
 
 use handler::GenType;
 
+use trace::TraceContext;
+
 /*
 * 进程运行状态
 */
@@ -72,9 +74,10 @@ impl From<ProcStatus> for Arc<AtomicU8> {
 * 进程消息
 */
 pub struct ProcInfo<Payload: 'static> {
-    src:        u64,        //消息源
-    dst:        u64,        //消息目标
-    payload:    Payload,    //消息负载
+    src:        u64,                    //消息源
+    dst:        u64,                    //消息目标
+    payload:    Payload,                //消息负载
+    trace:      Option<TraceContext>,   //发送消息的跟踪上下文，接收消息的跨度将作为此上下文的子跨度，为空表示未跟踪
 }
 
 impl<Payload: 'static> ProcInfo<Payload> {
@@ -84,9 +87,16 @@ impl<Payload: 'static> ProcInfo<Payload> {
             src,
             dst,
             payload,
+            trace: None,
         }
     }
 
+    //设置发送消息的跟踪上下文
+    pub fn set_trace(mut self, trace: Option<TraceContext>) -> Self {
+        self.trace = trace;
+        self
+    }
+
     //获取消息源
     pub fn source(&self) -> u64 {
         self.src
@@ -97,10 +107,20 @@ impl<Payload: 'static> ProcInfo<Payload> {
         self.dst
     }
 
+    //获取发送消息的跟踪上下文
+    pub fn trace(&self) -> Option<TraceContext> {
+        self.trace
+    }
+
     //获取消息负载
     pub fn payload(&self) -> &Payload {
         &self.payload
     }
+
+    //取出消息负载
+    pub fn into_payload(self) -> Payload {
+        self.payload
+    }
 }
 
 /*
@@ -173,6 +193,11 @@ pub trait ProcessFactory: 'static {
     //向指定进程发送消息
     fn send(&self, src: u64, dst: u64, msg: GenType) -> Result<(), Self::Error>;
 
+    //向指定进程的异常捕获器抛出一个异常，默认忽略，不支持异常捕获器的进程工厂无需实现
+    fn throw(&self, _pid: u64, _reason: String) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
     //强制关闭指定进程
     fn close(&self, pid: u64, reason: String) -> Result<Option<String>, Self::Error>;
 }
\ No newline at end of file
@@ -1,4 +1,5 @@
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use std::io::{Error, ErrorKind};
 use std::sync::atomic::{AtomicU8, AtomicU64, Ordering};
 
@@ -9,6 +10,8 @@ use handler::GenType;
 use atom::Atom;
 
 use proc::{ProcStatus, ProcessFactory};
+use metrics::process_metrics;
+use trace::{TraceContext, Span, is_tracing, current, enter, record};
 
 /*
 * 全局进程池
@@ -64,18 +67,32 @@ pub fn spawn_process(name: Option<String>,
                      init: String,
                      args: GenType) -> Result<u64, Error> {
     if let Some(factory) = GLOBAL_PROCESS_POOL.factorys.read().get(&factory_name) {
+        let metrics = process_metrics(factory.name());
         let pid = GLOBAL_PROCESS_POOL.alloc_pid();
-        if let Err(e) = factory.new_process(pid, name) {
-            //构建指定工厂的进程错误，则立即返回错误原因
-            return Err(e);
-        }
 
-        if let Err(e) = factory.startup(pid, module, function, init, args) {
-            //启动进程错误，则立即返回错误原因
-            return Err(e);
+        //启用跟踪时，生成进程作为当前跟踪上下文的子跨度，没有当前跟踪上下文则开始新的跟踪，进程启动期间的调用将延续此跟踪
+        let span = if is_tracing() {
+            let parent = current();
+            let context = parent.map(|parent| parent.child()).unwrap_or_else(TraceContext::new);
+            Some((context, parent.map(|parent| parent.span_id()), format!("spawn_process {}.{}", module, function)))
+        } else {
+            None
+        };
+        let last = span.as_ref().map(|(context, _, _)| enter(Some(*context)));
+        let start = Instant::now();
+
+        //构建并启动指定工厂的进程，错误则立即返回错误原因
+        let result = factory.new_process(pid, name).and_then(|_| factory.startup(pid, module, function, init, args));
+        metrics.incr_spawn(result.is_ok());
+
+        if let Some((context, parent_id, span_name)) = span {
+            enter(last.unwrap_or(None));
+            record(Span::new(context, parent_id, span_name, Duration::from_millis(0), start.elapsed(), vec![
+                ("factory", factory.name().to_string()),
+                ("pid", pid.to_string()),
+            ]));
         }
-
-        return Ok(pid);
+        return result.map(|_| pid);
     }
 
     Err(Error::new(ErrorKind::Other, format!("process factory not exist, name: {:?}", factory_name)))
@@ -121,6 +138,20 @@ pub fn get_status(pid: u64) -> Option<ProcStatus> {
     None
 }
 
+/*
+* 线程安全的获取每种运行状态的进程数量
+*/
+pub fn status_counts() -> Vec<(ProcStatus, usize)> {
+    let mut counts = [0usize; 5];
+    for (status, _) in GLOBAL_PROCESS_POOL.processes.read().values() {
+        if let Some(count) = counts.get_mut(status.load(Ordering::SeqCst) as usize) {
+            *count += 1;
+        }
+    }
+
+    counts.iter().enumerate().map(|(status, count)| ((status as u8).into(), *count)).collect()
+}
+
 /*
 * 线程安全的获取指定进程的消息队列长度
 */
@@ -195,7 +226,11 @@ pub fn pid_send(src: u64, dst: u64, msg: GenType) -> Result<(), Error> {
     }
 
     if let Some((_, factory)) = GLOBAL_PROCESS_POOL.processes.read().get(&dst) {
-        factory.send(src, dst, msg)
+        let result = trace_send(factory.name(), src, dst, || factory.send(src, dst, msg));
+        if result.is_ok() {
+            process_metrics(factory.name()).incr_send();
+        }
+        result
     } else {
         //进程对应的工厂不存在
         Err(Error::new(ErrorKind::Other, format!("pid send failed, src: {:?}, dst: {:?}, reason: process factory not exist", src, dst)))
@@ -212,13 +247,62 @@ pub fn name_send(src: u64, dst: String, msg: GenType) -> Result<(), Error> {
     }
 
     if let Some((pid, factory)) = GLOBAL_PROCESS_POOL.names.read().get(&dst) {
-        factory.send(src, *pid, msg)
+        let result = trace_send(factory.name(), src, *pid, || factory.send(src, *pid, msg));
+        if result.is_ok() {
+            process_metrics(factory.name()).incr_send();
+        }
+        result
     } else {
         //进程对应的工厂不存在
         Err(Error::new(ErrorKind::Other, format!("name send failed, src: {:?}, dst: {:?}, reason: process factory not exist", src, dst)))
     }
 }
 
+//当前线程有跟踪上下文时，在发送消息的跨度中发送消息，进程工厂可以通过当前跟踪上下文将发送跨度携带在消息中，由目标进程在接收消息时构建子跨度
+fn trace_send<F: FnOnce() -> Result<(), Error>>(factory: &str, src: u64, dst: u64, send: F) -> Result<(), Error> {
+    let parent = if is_tracing() { current() } else { None };
+    match parent {
+        None => send(),
+        Some(parent) => {
+            let context = parent.child();
+            let last = enter(Some(context));
+            let start = Instant::now();
+            let result = send();
+            enter(last);
+
+            record(Span::new(context, Some(parent.span_id()), "process_send".to_string(), Duration::from_millis(0), start.elapsed(), vec![
+                ("factory", factory.to_string()),
+                ("src", src.to_string()),
+                ("dst", dst.to_string()),
+            ]));
+            result
+        },
+    }
+}
+
+/*
+* 线程安全的获取所有已注册进程的唯一id
+*/
+pub fn all_pids() -> Vec<u64> {
+    GLOBAL_PROCESS_POOL.processes.read().keys().cloned().collect()
+}
+
+/*
+* 线程安全的向指定进程的异常捕获器抛出一个异常
+*/
+pub fn throw_process(pid: u64, reason: String) -> Result<(), Error> {
+    if let Some((_, factory)) = GLOBAL_PROCESS_POOL.processes.read().get(&pid) {
+        let result = factory.throw(pid, reason);
+        if result.is_ok() {
+            process_metrics(factory.name()).incr_throw();
+        }
+        result
+    } else {
+        //进程对应的工厂不存在
+        Err(Error::new(ErrorKind::Other, format!("throw process failed, pid: {:?}, reason: process factory not exist", pid)))
+    }
+}
+
 /*
 * 线程安全的关闭指定进程
 */
@@ -232,6 +316,9 @@ pub fn close_process(pid: u64, reason: String) -> Result<(), Error> {
     let result;
     if let Some((_, factory)) = GLOBAL_PROCESS_POOL.processes.read().get(&pid) {
         result = factory.close(pid, reason);
+        if result.is_ok() {
+            process_metrics(factory.name()).incr_close();
+        }
     } else {
         //进程对应的工厂不存在
         return Err(Error::new(ErrorKind::Other, format!("close process failed, pid: {:?}, reason: process factory not exist", pid)))
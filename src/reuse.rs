@@ -0,0 +1,210 @@
+use std::sync::Mutex;
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::SmallRng;
+use apm::allocator::{get_max_alloced_limit, is_alloced_limit, vm_alloced_size};
+
+use adapter::{JS, now_utc};
+use pi_vm_impl::VMFactory;
+
+/*
+* 虚拟机释放可回收内存后，仍然需要丢弃的堆大小比例
+*/
+const FREE_THROW_HEAP_RATIO: f64 = 0.75;
+
+/*
+* 虚拟机整理时的处理方式
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReuseDecision {
+    Reuse,      //重置全局环境后复用
+    Free,       //重置全局环境，并释放可回收内存后复用
+    Throw,      //丢弃
+    Recreate,   //丢弃，并构建新的虚拟机替换
+}
+
+/*
+* 虚拟机整理时的统计
+*/
+#[derive(Debug, Clone)]
+pub struct VmStats {
+    vm_id:              usize,  //虚拟机id
+    heap_size:          usize,  //虚拟机当前堆大小
+    factory_heap_size:  usize,  //虚拟机工厂的虚拟机堆大小
+    max_heap_size:      usize,  //虚拟机工厂的虚拟机最大堆大小，0表示无限制
+    reused_count:       usize,  //虚拟机已复用次数
+    max_reused_count:   usize,  //虚拟机工厂的虚拟机最大执行次数，0表示无限制
+    last_time:          usize,  //虚拟机最近运行时间，单位us
+    idle_time:          usize,  //虚拟机距最近运行的时长，单位us
+    error_count:        usize,  //虚拟机运行异常次数
+}
+
+impl VmStats {
+    //获取指定虚拟机在指定虚拟机工厂中的统计
+    pub fn new(js: &JS, factory: &VMFactory) -> Self {
+        let last_time = js.last_time();
+        VmStats {
+            vm_id: js.get_id(),
+            heap_size: js.heap_size(),
+            factory_heap_size: factory.heap_size(),
+            max_heap_size: factory.max_heap_size(),
+            reused_count: js.reused_count(),
+            max_reused_count: factory.max_reused_count(),
+            last_time,
+            idle_time: now_utc().saturating_sub(last_time),
+            error_count: js.error_count(),
+        }
+    }
+
+    //获取虚拟机id
+    pub fn vm_id(&self) -> usize {
+        self.vm_id
+    }
+
+    //获取虚拟机当前堆大小
+    pub fn heap_size(&self) -> usize {
+        self.heap_size
+    }
+
+    //获取虚拟机工厂的虚拟机堆大小
+    pub fn factory_heap_size(&self) -> usize {
+        self.factory_heap_size
+    }
+
+    //获取虚拟机最大堆大小
+    pub fn max_heap_size(&self) -> usize {
+        self.max_heap_size
+    }
+
+    //获取虚拟机已复用次数
+    pub fn reused_count(&self) -> usize {
+        self.reused_count
+    }
+
+    //获取虚拟机最大执行次数
+    pub fn max_reused_count(&self) -> usize {
+        self.max_reused_count
+    }
+
+    //获取虚拟机最近运行时间
+    pub fn last_time(&self) -> usize {
+        self.last_time
+    }
+
+    //获取虚拟机距最近运行的时长
+    pub fn idle_time(&self) -> usize {
+        self.idle_time
+    }
+
+    //获取虚拟机运行异常次数
+    pub fn error_count(&self) -> usize {
+        self.error_count
+    }
+}
+
+/*
+* 虚拟机复用策略
+*/
+pub trait ReusePolicy: Send + Sync + 'static {
+    //虚拟机完成任务后，决定虚拟机的处理方式
+    fn decide(&self, stats: &VmStats) -> ReuseDecision;
+
+    //虚拟机释放可回收内存后，决定虚拟机的处理方式，Throw表示标记为等待丢弃，在下次执行后丢弃，Recreate表示立即丢弃并替换，其它表示复用
+    fn after_free(&self, stats: &VmStats) -> ReuseDecision {
+        let max_heap_size = stats.max_heap_size();
+        if (max_heap_size > 0) && (stats.heap_size() >= ((max_heap_size as f64 * FREE_THROW_HEAP_RATIO).ceil() as usize)) {
+            //释放后，仍然大于虚拟机堆限制的75%，则等待丢弃
+            ReuseDecision::Throw
+        } else {
+            ReuseDecision::Reuse
+        }
+    }
+}
+
+/*
+* 默认的虚拟机复用策略，达到虚拟机最大堆大小后释放可回收内存，释放后仍然大于虚拟机最大堆大小的75%，则等待丢弃
+*/
+#[derive(Debug, Clone, Default)]
+pub struct DefaultReusePolicy;
+
+impl ReusePolicy for DefaultReusePolicy {
+    fn decide(&self, stats: &VmStats) -> ReuseDecision {
+        let max_heap_size = stats.max_heap_size();
+        if (max_heap_size > 0) && stats.heap_size() >= max_heap_size {
+            //已达虚拟机最大堆限制，则释放
+            ReuseDecision::Free
+        } else {
+            //未达虚拟机最大堆限制，则复用
+            ReuseDecision::Reuse
+        }
+    }
+}
+
+/*
+* 限制虚拟机最大执行次数的复用策略，复用次数达到虚拟机工厂的最大执行次数后替换虚拟机，否则使用默认的虚拟机复用策略
+*/
+#[derive(Debug, Clone, Default)]
+pub struct MaxReusedCountPolicy;
+
+impl ReusePolicy for MaxReusedCountPolicy {
+    fn decide(&self, stats: &VmStats) -> ReuseDecision {
+        let max_reused_count = stats.max_reused_count();
+        if (max_reused_count > 0) && stats.reused_count() >= max_reused_count {
+            //已达虚拟机最大执行次数，则替换
+            ReuseDecision::Recreate
+        } else {
+            DefaultReusePolicy.decide(stats)
+        }
+    }
+}
+
+/*
+* 根据内存压力随机丢弃虚拟机的复用策略，所有虚拟机的内存占用越高，且虚拟机堆大小越接近虚拟机工厂的虚拟机堆大小，丢弃的机率越高，
+* 已达最大堆限制时只丢弃，否则丢弃并替换，未丢弃则使用默认的虚拟机复用策略
+*/
+pub struct MemoryPressurePolicy {
+    rng: Mutex<SmallRng>,   //随机数生成器
+}
+
+impl Default for MemoryPressurePolicy {
+    fn default() -> Self {
+        MemoryPressurePolicy {
+            rng: Mutex::new(SmallRng::from_entropy()),
+        }
+    }
+}
+
+impl ReusePolicy for MemoryPressurePolicy {
+    fn decide(&self, stats: &VmStats) -> ReuseDecision {
+        let heap_limit = stats.factory_heap_size();
+        let n = if heap_limit == 0 || stats.heap_size() >= heap_limit {
+            //当前堆大小超过堆限制，则堆大小余量比例为0
+            0.0
+        } else {
+            //当前堆大小未超过堆限制，则余量为(1 - 当前堆限制比例)
+            1.0 - stats.heap_size() as f64 / heap_limit as f64
+        };
+
+        let is_limit = is_alloced_limit();
+        let max_heap_limit = get_max_alloced_limit();
+        let s = if is_limit || max_heap_limit == 0 {
+            //当前所有虚拟机内存占用超过最大堆限制，则内存占用比例为1
+            1.0
+        } else {
+            //当前所有虚拟机内存占用未超过最大堆限制
+            (vm_alloced_size().max(0) as f64 / max_heap_limit as f64).min(1.0)
+        };
+
+        //求虚拟机丢弃机率
+        let r = s.powf(8.0).powf(n);
+        if self.rng.lock().unwrap().gen_bool(r) {
+            if is_limit {
+                ReuseDecision::Throw
+            } else {
+                ReuseDecision::Recreate
+            }
+        } else {
+            DefaultReusePolicy.decide(stats)
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use worker::task::TaskType;
+use apm::allocator::is_alloced_limit;
+use timer::{TIMER, FuncRuner};
+use atom::Atom;
+
+use adapter::VM_FACTORY_REGISTERS;
+use pi_vm_impl::VMFactory;
+use scheduler::cast_js_task;
+
+/*
+* 虚拟机工厂伸缩任务优先级
+*/
+const VM_SCALING_TASK_PRIORITY: usize = 100;
+
+lazy_static! {
+    //虚拟机工厂伸缩定时器是否已停止
+    static ref VM_SCALING_STOPPED: AtomicBool = AtomicBool::new(false);
+}
+
+/*
+* 虚拟机工厂伸缩配置
+*/
+#[derive(Debug, Clone)]
+pub struct ScalingConfig {
+    min_idle:               usize,      //最少空闲虚拟机数量
+    max_total:              usize,      //最多虚拟机数量，0表示无限制
+    target_queue_latency:   Duration,   //任务调度队列的目标等待时长，超过则扩容
+    scale_up_cooldown:      Duration,   //扩容冷却时长
+    scale_down_cooldown:    Duration,   //缩容冷却时长
+    scale_step:             usize,      //每次伸缩的最多虚拟机数量
+}
+
+impl ScalingConfig {
+    //构建虚拟机工厂伸缩配置
+    pub fn new(min_idle: usize, max_total: usize) -> Self {
+        ScalingConfig {
+            min_idle,
+            max_total,
+            target_queue_latency: Duration::from_millis(100),
+            scale_up_cooldown: Duration::from_secs(1),
+            scale_down_cooldown: Duration::from_secs(30),
+            scale_step: 1,
+        }
+    }
+
+    //设置任务调度队列的目标等待时长
+    pub fn set_target_queue_latency(mut self, latency: Duration) -> Self {
+        self.target_queue_latency = latency;
+        self
+    }
+
+    //设置扩容冷却时长
+    pub fn set_scale_up_cooldown(mut self, cooldown: Duration) -> Self {
+        self.scale_up_cooldown = cooldown;
+        self
+    }
+
+    //设置缩容冷却时长
+    pub fn set_scale_down_cooldown(mut self, cooldown: Duration) -> Self {
+        self.scale_down_cooldown = cooldown;
+        self
+    }
+
+    //设置每次伸缩的最多虚拟机数量，至少为1
+    pub fn set_scale_step(mut self, step: usize) -> Self {
+        self.scale_step = if step == 0 { 1 } else { step };
+        self
+    }
+
+    //获取最少空闲虚拟机数量
+    pub fn min_idle(&self) -> usize {
+        self.min_idle
+    }
+
+    //获取最多虚拟机数量
+    pub fn max_total(&self) -> usize {
+        self.max_total
+    }
+
+    //获取任务调度队列的目标等待时长
+    pub fn target_queue_latency(&self) -> Duration {
+        self.target_queue_latency
+    }
+
+    //获取扩容冷却时长
+    pub fn scale_up_cooldown(&self) -> Duration {
+        self.scale_up_cooldown
+    }
+
+    //获取缩容冷却时长
+    pub fn scale_down_cooldown(&self) -> Duration {
+        self.scale_down_cooldown
+    }
+
+    //获取每次伸缩的最多虚拟机数量
+    pub fn scale_step(&self) -> usize {
+        self.scale_step
+    }
+}
+
+/*
+* 虚拟机工厂伸缩状态，由虚拟机工厂持有，虚拟机工厂释放后同时释放
+*/
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ScalingState {
+    last_scale_up:      Option<Instant>,    //最近扩容时间
+    last_scale_down:    Option<Instant>,    //最近缩容时间
+}
+
+/*
+* 虚拟机工厂伸缩结果
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScalingResult {
+    Ignore,         //未伸缩
+    Up(usize),      //扩容了指定数量的虚拟机
+    Down(usize),    //缩容了指定数量的虚拟机
+}
+
+//根据伸缩配置，对指定虚拟机工厂进行一次伸缩，未设置伸缩配置则忽略
+pub fn scale_factory(factory: &VMFactory) -> ScalingResult {
+    let config = match factory.scaling() {
+        None => return ScalingResult::Ignore,
+        Some(config) => config,
+    };
+
+    let now = Instant::now();
+    let mut state = factory.scaling_state().lock().unwrap();
+
+    let size = factory.size();
+    let idle = factory.free_pool_size() + factory.free_buf_size();
+    let queue_len = factory.queue_len();
+
+    //检查是否需要扩容，在进程内存达到最大堆限制时不扩容，扩容后立即使用新的虚拟机运行等待的任务
+    let is_up_cooled = is_cooled(state.last_scale_up, config.scale_up_cooldown, now);
+    if is_up_cooled && !is_alloced_limit() {
+        let mut need = 0;
+        if idle < config.min_idle {
+            //空闲虚拟机不足，则补足空闲虚拟机
+            need = config.min_idle - idle;
+        }
+        if queue_len > 0 && factory.queue_latency() > config.target_queue_latency {
+            //任务调度队列等待时长超过目标等待时长，则为等待的任务扩容
+            need = need.max(queue_len);
+        }
+
+        if config.max_total > 0 {
+            need = need.min(config.max_total.saturating_sub(size));
+        }
+        need = need.min(config.scale_step);
+
+        if need > 0 {
+            state.last_scale_up = Some(now);
+            return match factory.produce(need) {
+                Err(e) => {
+                    warn!("!!!> Vm Factory Scale Up Error, factory: {:?}, count: {}, e: {:?}", factory.name(), need, e);
+                    ScalingResult::Ignore
+                },
+                Ok(_) => {
+                    let dispatched = factory.dispatch_queued();
+                    info!("===> Vm Factory Scale Up Ok, factory: {:?}, count: {}, size: {}, idle: {}, queue: {}, dispatched: {}", factory.name(), need, factory.size(), idle + need, queue_len, dispatched);
+                    ScalingResult::Up(need)
+                },
+            };
+        }
+    }
+
+    //检查是否需要缩容，任务调度队列为空且空闲虚拟机过多时缩容
+    let is_down_cooled = is_cooled(state.last_scale_down, config.scale_down_cooldown, now)
+        && is_cooled(state.last_scale_up, config.scale_down_cooldown, now);
+    if is_down_cooled && queue_len == 0 && idle > config.min_idle {
+        let count = (idle - config.min_idle).min(config.scale_step);
+        let count = factory.shrink(count);
+        if count > 0 {
+            state.last_scale_down = Some(now);
+            info!("===> Vm Factory Scale Down Ok, factory: {:?}, count: {}, size: {}", factory.name(), count, factory.size());
+            return ScalingResult::Down(count);
+        }
+    }
+
+    ScalingResult::Ignore
+}
+
+//注册虚拟机工厂伸缩定时器，定时对所有设置了伸缩配置的虚拟机工厂进行伸缩，与全局虚拟机整理相互独立
+pub fn register_vm_scaling_timer(interval: usize) {
+    if VM_SCALING_STOPPED.load(Ordering::SeqCst) {
+        //虚拟机工厂伸缩定时器已停止，则忽略
+        return;
+    }
+
+    let runner = FuncRuner::new(Box::new(move || {
+        let func = Box::new(move |_lock| {
+            if VM_SCALING_STOPPED.load(Ordering::SeqCst) {
+                //虚拟机工厂伸缩定时器已停止，则不再伸缩，也不再注册下次伸缩
+                return;
+            }
+
+            let factories: Vec<Arc<VMFactory>> = VM_FACTORY_REGISTERS.read().unwrap().values().cloned().collect();
+            for factory in factories {
+                scale_factory(&factory);
+            }
+
+            if interval > 0 {
+                register_vm_scaling_timer(interval);
+            }
+        });
+        cast_js_task(TaskType::Async(false), VM_SCALING_TASK_PRIORITY, None, func, Atom::from("vm factory scaling task"));
+    }));
+
+    TIMER.set_timeout(runner, interval as u32);
+}
+
+//停止虚拟机工厂伸缩定时器，停止后无法重新注册
+pub fn stop_vm_scaling_timer() {
+    VM_SCALING_STOPPED.store(true, Ordering::SeqCst);
+}
+
+//判断是否已过冷却时长
+fn is_cooled(last: Option<Instant>, cooldown: Duration, now: Instant) -> bool {
+    match last {
+        None => true,
+        Some(last) => now.duration_since(last) >= cooldown,
+    }
+}
@@ -0,0 +1,427 @@
+use std::sync::Arc;
+use std::cell::Cell;
+#[cfg(feature = "tokio")]
+use std::collections::{HashMap, HashSet, VecDeque};
+#[cfg(feature = "tokio")]
+use std::sync::atomic::{AtomicIsize, Ordering};
+#[cfg(feature = "tokio")]
+use std::time::Duration;
+#[cfg(feature = "tokio")]
+use std::pin::Pin;
+#[cfg(feature = "tokio")]
+use std::future::Future;
+#[cfg(feature = "tokio")]
+use std::task::{Context, Poll};
+#[cfg(feature = "tokio")]
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use once_cell::sync::OnceCell;
+#[cfg(feature = "tokio")]
+use parking_lot::Mutex;
+#[cfg(feature = "tokio")]
+use tokio::runtime::Handle;
+#[cfg(feature = "tokio")]
+use tokio::time::{Delay, delay_for};
+
+use worker::task::TaskType;
+use worker::impls;
+use atom::Atom;
+
+#[cfg(any(test, feature = "test-executor"))]
+use executor::TestExecutor;
+
+//全局虚拟机任务调度器，只允许设置一次，首次使用前未设置则使用工作者调度
+static VM_SCHEDULER: OnceCell<Arc<VmScheduler>> = OnceCell::new();
+
+thread_local! {
+    //当前线程是否正在执行虚拟机任务
+    static IN_VM_TASK: Cell<bool> = Cell::new(false);
+}
+
+/*
+* 虚拟机任务执行标记，在任务完成或异常退出时恢复当前线程的标记
+*/
+struct VmTaskGuard(bool);
+
+impl Drop for VmTaskGuard {
+    fn drop(&mut self) {
+        let last = self.0;
+        IN_VM_TASK.with(move |flag| flag.set(last));
+    }
+}
+
+//判断当前线程是否正在执行虚拟机任务，虚拟机任务中不允许阻塞等待，否则会占用执行虚拟机任务的线程
+pub fn is_in_vm_task() -> bool {
+    IN_VM_TASK.with(|flag| flag.get())
+}
+
+//包装任务函数，在任务执行期间标记当前线程正在执行虚拟机任务
+fn mark_vm_task(func: Box<FnOnce(Option<isize>)>) -> Box<FnOnce(Option<isize>)> {
+    Box::new(move |lock| {
+        let _guard = VmTaskGuard(IN_VM_TASK.with(|flag| flag.replace(true)));
+        func(lock);
+    })
+}
+
+/*
+* 虚拟机任务调度器，负责任务队列的管理和任务的执行
+* 从任务队列中取出任务执行时，调度器必须锁住任务队列，并将任务队列id传递给任务，由任务在完成后解锁
+*/
+pub trait VmScheduler: Send + Sync + 'static {
+    //创建指定优先级的任务队列，返回任务队列id
+    fn create_queue(&self, priority: usize, can_del: bool) -> isize;
+
+    //移除指定的任务队列
+    fn remove_queue(&self, queue: isize) -> bool;
+
+    //锁住指定的任务队列，锁住的任务队列中的任务不会被执行
+    fn lock_queue(&self, queue: isize) -> bool;
+
+    //解锁指定的任务队列
+    fn unlock_queue(&self, queue: isize) -> bool;
+
+    //投递任务，任务队列为空则投递全局任务
+    fn cast(&self, task_type: TaskType, priority: usize, queue: Option<isize>, func: Box<FnOnce(Option<isize>)>, info: Atom) -> Option<isize>;
+
+    //投递延迟任务，延迟时长单位ms，任务队列为空则投递全局任务，返回唯一的延迟任务句柄
+    fn cast_delay(&self, task_type: TaskType, priority: usize, queue: Option<isize>, func: Box<FnOnce(Option<isize>)>, timeout: u32, info: Atom) -> Option<isize>;
+
+    //取消未到期的延迟任务，成功返回true，默认不支持取消，总是返回false
+    fn cancel_delay(&self, _handle: isize) -> bool {
+        false
+    }
+}
+
+/*
+* 基于工作者的虚拟机任务调度器，需要启动js工作者池，不支持取消延迟任务
+*/
+pub struct WorkerScheduler;
+
+impl VmScheduler for WorkerScheduler {
+    fn create_queue(&self, priority: usize, can_del: bool) -> isize {
+        impls::create_js_task_queue(priority, can_del)
+    }
+
+    fn remove_queue(&self, queue: isize) -> bool {
+        impls::remove_js_task_queue(queue)
+    }
+
+    fn lock_queue(&self, queue: isize) -> bool {
+        impls::lock_js_task_queue(queue)
+    }
+
+    fn unlock_queue(&self, queue: isize) -> bool {
+        impls::unlock_js_task_queue(queue)
+    }
+
+    fn cast(&self, task_type: TaskType, priority: usize, queue: Option<isize>, func: Box<FnOnce(Option<isize>)>, info: Atom) -> Option<isize> {
+        impls::cast_js_task(task_type, priority, queue, func, info)
+    }
+
+    fn cast_delay(&self, task_type: TaskType, priority: usize, queue: Option<isize>, func: Box<FnOnce(Option<isize>)>, timeout: u32, info: Atom) -> Option<isize> {
+        impls::cast_js_delay_task(task_type, priority, queue, func, timeout, info)
+    }
+}
+
+//设置全局虚拟机任务调度器，只允许设置一次，已设置或已使用默认调度器则返回指定的调度器，必须在构建任何虚拟机、虚拟机工厂和shell之前设置
+pub fn set_scheduler(scheduler: Arc<VmScheduler>) -> Result<(), Arc<VmScheduler>> {
+    VM_SCHEDULER.set(scheduler)
+}
+
+//获取全局虚拟机任务调度器，设置后不会改变，所以读取时不需要加锁
+pub fn scheduler() -> &'static VmScheduler {
+    &**VM_SCHEDULER.get_or_init(|| Arc::new(WorkerScheduler))
+}
+
+/*
+* 虚拟机任务的调度入口，启用测试执行器且当前线程安装了测试执行器时由测试执行器调度，否则由全局虚拟机任务调度器调度
+*/
+
+//创建指定优先级的任务队列，返回任务队列id
+pub fn create_js_task_queue(priority: usize, can_del: bool) -> isize {
+    #[cfg(any(test, feature = "test-executor"))]
+    {
+        if let Some(executor) = TestExecutor::current() {
+            return executor.create_queue();
+        }
+    }
+
+    scheduler().create_queue(priority, can_del)
+}
+
+//移除指定的任务队列
+pub fn remove_js_task_queue(queue: isize) -> bool {
+    #[cfg(any(test, feature = "test-executor"))]
+    {
+        if let Some(executor) = TestExecutor::current() {
+            return executor.remove_queue(queue);
+        }
+    }
+
+    scheduler().remove_queue(queue)
+}
+
+//锁住指定的任务队列，锁住的任务队列中的任务不会被执行
+pub fn lock_js_task_queue(queue: isize) -> bool {
+    #[cfg(any(test, feature = "test-executor"))]
+    {
+        if let Some(executor) = TestExecutor::current() {
+            return executor.lock_queue(queue);
+        }
+    }
+
+    scheduler().lock_queue(queue)
+}
+
+//解锁指定的任务队列
+pub fn unlock_js_task_queue(queue: isize) -> bool {
+    #[cfg(any(test, feature = "test-executor"))]
+    {
+        if let Some(executor) = TestExecutor::current() {
+            return executor.unlock_queue(queue);
+        }
+    }
+
+    scheduler().unlock_queue(queue)
+}
+
+//投递任务，任务队列为空则投递全局任务
+pub fn cast_js_task(task_type: TaskType, priority: usize, queue: Option<isize>, func: Box<FnOnce(Option<isize>)>, info: Atom) -> Option<isize> {
+    let func = mark_vm_task(func);
+    #[cfg(any(test, feature = "test-executor"))]
+    {
+        if let Some(executor) = TestExecutor::current() {
+            return executor.cast(task_type, priority, queue, func, None, info);
+        }
+    }
+
+    scheduler().cast(task_type, priority, queue, func, info)
+}
+
+//投递延迟任务，延迟时长单位ms，任务队列为空则投递全局任务
+pub fn cast_js_delay_task(task_type: TaskType, priority: usize, queue: Option<isize>, func: Box<FnOnce(Option<isize>)>, timeout: u32, info: Atom) -> Option<isize> {
+    let func = mark_vm_task(func);
+    #[cfg(any(test, feature = "test-executor"))]
+    {
+        if let Some(executor) = TestExecutor::current() {
+            return executor.cast(task_type, priority, queue, func, Some(timeout), info);
+        }
+    }
+
+    scheduler().cast_delay(task_type, priority, queue, func, timeout, info)
+}
+
+//取消未到期的延迟任务，成功返回true
+pub fn cancel_js_delay_task(handle: isize) -> bool {
+    #[cfg(any(test, feature = "test-executor"))]
+    {
+        if let Some(executor) = TestExecutor::current() {
+            return executor.cancel_delay(handle);
+        }
+    }
+
+    scheduler().cancel_delay(handle)
+}
+
+/*
+* 可以跨线程移动的任务函数，虚拟机任务本身需要在工作者线程间移动，由虚拟机状态保证同一虚拟机不会被并发执行
+*/
+#[cfg(feature = "tokio")]
+struct SendTask(Box<FnOnce(Option<isize>)>);
+
+#[cfg(feature = "tokio")]
+unsafe impl Send for SendTask {}
+
+/*
+* tokio调度器中的任务队列
+*/
+#[cfg(feature = "tokio")]
+struct TokioQueue {
+    is_locked:  bool,                       //是否已锁住，锁住的任务队列中的任务不会被执行
+    tasks:      VecDeque<(SendTask, Atom)>, //等待执行的任务和任务信息
+}
+
+/*
+* 基于tokio的虚拟机任务调度器，在指定tokio运行时的阻塞线程池中执行虚拟机任务，不需要启动js工作者池，忽略任务优先级
+*/
+#[cfg(feature = "tokio")]
+#[derive(Clone)]
+pub struct TokioScheduler {
+    handle:     Handle,                                 //tokio运行时句柄
+    queue_id:   Arc<AtomicIsize>,                       //任务队列分配id
+    queues:     Arc<Mutex<HashMap<isize, TokioQueue>>>, //任务队列表
+    delay_id:   Arc<AtomicIsize>,                       //延迟任务句柄分配id
+    delays:     Arc<Mutex<HashSet<isize>>>,             //未到期且未取消的延迟任务句柄
+}
+
+#[cfg(feature = "tokio")]
+impl VmScheduler for TokioScheduler {
+    fn create_queue(&self, _priority: usize, _can_del: bool) -> isize {
+        let id = self.queue_id.fetch_add(1, Ordering::Relaxed);
+        self.queues.lock().insert(id, TokioQueue {
+            is_locked: false,
+            tasks: VecDeque::new(),
+        });
+        id
+    }
+
+    fn remove_queue(&self, queue: isize) -> bool {
+        self.queues.lock().remove(&queue).is_some()
+    }
+
+    fn lock_queue(&self, queue: isize) -> bool {
+        match self.queues.lock().get_mut(&queue) {
+            None => false,
+            Some(queue) => {
+                queue.is_locked = true;
+                true
+            },
+        }
+    }
+
+    fn unlock_queue(&self, queue: isize) -> bool {
+        let next = match self.queues.lock().get_mut(&queue) {
+            None => return false,
+            Some(q) => {
+                q.is_locked = false;
+                TokioScheduler::pop(q)
+            },
+        };
+
+        if let Some((task, info)) = next {
+            self.spawn(Some(queue), task, info);
+        }
+        true
+    }
+
+    fn cast(&self, _task_type: TaskType, _priority: usize, queue: Option<isize>, func: Box<FnOnce(Option<isize>)>, info: Atom) -> Option<isize> {
+        self.push(queue, SendTask(func), info)
+    }
+
+    fn cast_delay(&self, _task_type: TaskType, _priority: usize, queue: Option<isize>, func: Box<FnOnce(Option<isize>)>, timeout: u32, info: Atom) -> Option<isize> {
+        if let Some(id) = queue {
+            if !self.queues.lock().contains_key(&id) {
+                return None;
+            }
+        }
+
+        //延迟到期后再加入任务队列，延迟期间不占用阻塞线程
+        let id = self.delay_id.fetch_add(1, Ordering::Relaxed);
+        self.delays.lock().insert(id);
+        let delay = self.handle.enter(|| delay_for(Duration::from_millis(timeout as u64)));
+        self.handle.spawn(DelayTask {
+            id,
+            delay,
+            task: Some((self.clone(), queue, SendTask(func), info)),
+        });
+        Some(id)
+    }
+
+    fn cancel_delay(&self, handle: isize) -> bool {
+        self.delays.lock().remove(&handle)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl TokioScheduler {
+    //使用指定的tokio运行时句柄构建调度器
+    pub fn new(handle: Handle) -> Self {
+        TokioScheduler {
+            handle,
+            queue_id: Arc::new(AtomicIsize::new(1)),
+            queues: Arc::new(Mutex::new(HashMap::new())),
+            delay_id: Arc::new(AtomicIsize::new(1)),
+            delays: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    //使用当前tokio运行时构建调度器，必须在tokio运行时中调用
+    pub fn current() -> Self {
+        TokioScheduler::new(Handle::current())
+    }
+
+    //获取指定任务队列中等待执行的任务数量，任务队列不存在返回空
+    pub fn queue_len(&self, queue: isize) -> Option<usize> {
+        self.queues.lock().get(&queue).map(|queue| queue.tasks.len())
+    }
+
+    //将任务加入全局任务或指定的任务队列，任务队列未锁住则立即执行，返回等待执行的任务数量，任务队列不存在返回空
+    fn push(&self, queue: Option<isize>, task: SendTask, info: Atom) -> Option<isize> {
+        let id = match queue {
+            None => {
+                self.spawn(None, task, info);
+                return Some(0);
+            },
+            Some(id) => id,
+        };
+
+        let (next, len) = match self.queues.lock().get_mut(&id) {
+            None => return None,
+            Some(q) => {
+                q.tasks.push_back((task, info));
+                (TokioScheduler::pop(q), q.tasks.len())
+            },
+        };
+
+        if let Some((task, info)) = next {
+            self.spawn(Some(id), task, info);
+        }
+        Some(len as isize)
+    }
+
+    //任务队列未锁住，则取出队首任务，并锁住任务队列，由任务在完成后解锁
+    fn pop(queue: &mut TokioQueue) -> Option<(SendTask, Atom)> {
+        if queue.is_locked {
+            return None;
+        }
+
+        let next = queue.tasks.pop_front();
+        if next.is_some() {
+            queue.is_locked = true;
+        }
+        next
+    }
+
+    //在tokio运行时的阻塞线程池中执行任务，虚拟机任务会同步执行js，不允许在异步线程中执行
+    fn spawn(&self, lock: Option<isize>, task: SendTask, info: Atom) {
+        self.handle.enter(move || {
+            tokio::task::spawn_blocking(move || {
+                let SendTask(func) = task;
+                if catch_unwind(AssertUnwindSafe(move || func(lock))).is_err() {
+                    warn!("!!!> Tokio Scheduler Run Task Error, task panic, info: {:?}", (&info).to_string());
+                }
+            });
+        });
+    }
+}
+
+/*
+* tokio调度器中的延迟任务，延迟到期后将未取消的任务加入全局任务或指定的任务队列
+*/
+#[cfg(feature = "tokio")]
+struct DelayTask {
+    id:     isize,                                                  //延迟任务句柄
+    delay:  Delay,                                                  //延迟
+    task:   Option<(TokioScheduler, Option<isize>, SendTask, Atom)>,//调度器、任务队列、任务和任务信息
+}
+
+#[cfg(feature = "tokio")]
+impl Future for DelayTask {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.delay).poll(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(_) => {
+                if let Some((scheduler, queue, task, info)) = this.task.take() {
+                    if scheduler.delays.lock().remove(&this.id) {
+                        //延迟任务未被取消
+                        scheduler.push(queue, task, info);
+                    }
+                }
+                Poll::Ready(())
+            },
+        }
+    }
+}
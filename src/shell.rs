@@ -13,11 +13,12 @@ use libc::c_char;
 
 use atom::Atom;
 use worker::task::TaskType;
-use worker::impls::{create_js_task_queue, cast_js_task, unlock_js_task_queue};
 
 use adapter::{JSStatus, JS, dukc_vm_status_check, dukc_vm_status_switch, dukc_vm_status_sub, dukc_callback_count, dukc_top, dukc_to_string, dukc_pop, handle_async_callback};
 use pi_vm_impl::{VMFactoryLoader, VMFactory, new_queue, remove_queue};
 use bonmgr::{NativeObjsAuth, ptr_jstype};
+use scheduler::{create_js_task_queue, cast_js_task, unlock_js_task_queue};
+use slow::slow_calls;
 
 /*
 * shell源最小值
@@ -35,6 +36,7 @@ const SHELL_MAX_SRC: usize = 0x100100000;
 const SHELL_COMMAND_CLEAN: &[u8] = b"clean"; //清空所有缓存的已编译脚本
 const SHELL_CURRENT_DIR: &[u8] = b"pwd"; //当前工作目录
 const SHELL_CURRENT_EXE: &[u8] = b"exe"; //当前执行程序
+const SHELL_SLOW_CALLS: &[u8] = b"slow"; //最近的慢调用
 
 /*
 * shell脚本文件名
@@ -316,6 +318,15 @@ impl ShellManager {
             self.shells.remove(&id);
         }
     }
+
+    //关闭所有shell，返回关闭的shell数量
+    pub fn close_all(&mut self) -> usize {
+        let ids: Vec<usize> = self.shells.keys().cloned().collect();
+        for id in &ids {
+            self.close(*id);
+        }
+        ids.len()
+    }
 }
 
 /*
@@ -496,7 +507,13 @@ fn handle_command(shell: Arc<Shell>, script: &String) -> bool {
                 println!("{}", path.display());
             }
             true
-        }
+        },
+        SHELL_SLOW_CALLS => {
+            for call in slow_calls() {
+                println!("{}", call);
+            }
+            true
+        },
         _ => {
             //未定义指令，则忽略
             false
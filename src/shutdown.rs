@@ -0,0 +1,166 @@
+use std::thread;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use adapter::{VM_FACTORY_REGISTERS, stop_global_vm_heap_collect_timer};
+use pi_vm_impl::{VMFactory, ShutdownReport, stop_queue_sweep_timer};
+use scaling::stop_vm_scaling_timer;
+use shell::SHELL_MANAGER;
+use proc_pool::{all_pids, queue_len, throw_process, close_process};
+
+/*
+* 全局关闭时，通知进程关闭的原因
+*/
+const SHUTDOWN_REASON: &'static str = "pi_vm shutdown";
+
+/*
+* 等待进程处理关闭原因时，单次等待的最大时长，单位ms
+*/
+const MAX_PROCESS_WAIT_TIME: u64 = 10;
+
+/*
+* 全局关闭的汇总
+*/
+#[derive(Debug, Clone)]
+pub struct ShutdownSummary {
+    shells:             usize,                          //关闭的shell数量
+    processes:          usize,                          //关闭的进程数量
+    process_failed:     usize,                          //关闭失败的进程数量
+    factories:          Vec<(String, ShutdownReport)>,  //已关闭的虚拟机工厂名和关闭报告
+    elapsed:            Duration,                       //关闭耗时
+}
+
+impl ShutdownSummary {
+    //获取关闭的shell数量
+    pub fn shells(&self) -> usize {
+        self.shells
+    }
+
+    //获取关闭的进程数量
+    pub fn processes(&self) -> usize {
+        self.processes
+    }
+
+    //获取关闭失败的进程数量
+    pub fn process_failed(&self) -> usize {
+        self.process_failed
+    }
+
+    //获取已关闭的虚拟机工厂名和关闭报告
+    pub fn factories(&self) -> &[(String, ShutdownReport)] {
+        self.factories.as_slice()
+    }
+
+    //获取所有虚拟机工厂被丢弃的任务数量
+    pub fn dropped(&self) -> usize {
+        self.factories.iter().map(|(_, report)| report.dropped()).sum()
+    }
+
+    //获取所有虚拟机工厂关闭超时后仍在运行的虚拟机数量
+    pub fn remaining(&self) -> usize {
+        self.factories.iter().map(|(_, report)| report.remaining()).sum()
+    }
+
+    //获取关闭耗时
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    //判断是否在关闭时长内完成关闭，且没有任何任务或进程被中止
+    pub fn is_graceful(&self) -> bool {
+        self.process_failed == 0 && self.factories.iter().all(|(_, report)| report.is_graceful())
+    }
+}
+
+//关闭pi_vm，停止全局虚拟机整理和虚拟机工厂伸缩，关闭所有shell，通知并关闭所有进程，
+//最后在剩余的关闭时长内关闭所有已注册的虚拟机工厂，会阻塞当前线程
+pub fn shutdown(deadline: Duration) -> ShutdownSummary {
+    let start = Instant::now();
+    info!("===> Vm Shutdown Start, deadline: {:?}", deadline);
+
+    //停止全局定时器
+    stop_global_vm_heap_collect_timer();
+    stop_vm_scaling_timer();
+    stop_queue_sweep_timer();
+
+    //关闭所有shell
+    let shells = SHELL_MANAGER.write().unwrap().close_all();
+
+    //向所有进程的异常捕获器抛出关闭原因
+    let pids = all_pids();
+    let mut thrown = Vec::with_capacity(pids.len());
+    for pid in &pids {
+        match throw_process(*pid, SHUTDOWN_REASON.to_string()) {
+            Err(e) => info!("===> Vm Shutdown Throw Process Ignore, pid: {}, e: {:?}", pid, e),
+            Ok(_) => thrown.push(*pid),
+        }
+    }
+
+    //关闭进程会移除异常捕获器，所以在关闭前等待进程处理完关闭原因，最多使用一半的剩余关闭时长
+    let timeout = deadline.checked_sub(start.elapsed()).unwrap_or(Duration::from_millis(0)) / 2;
+    if !wait_processes(&thrown, timeout) {
+        warn!("!!!> Vm Shutdown Wait Process Timeout, processes: {}, timeout: {:?}", thrown.len(), timeout);
+    }
+
+    //关闭所有进程
+    let mut processes = 0;
+    let mut process_failed = 0;
+    for pid in pids {
+        match close_process(pid, SHUTDOWN_REASON.to_string()) {
+            Err(e) => {
+                process_failed += 1;
+                warn!("!!!> Vm Shutdown Close Process Error, pid: {}, e: {:?}", pid, e);
+            },
+            Ok(_) => processes += 1,
+        }
+    }
+
+    //在剩余的关闭时长内关闭所有已注册的虚拟机工厂，先关闭的虚拟机工厂可以使用更多的关闭时长
+    let registers: Vec<(String, Arc<VMFactory>)> = VM_FACTORY_REGISTERS.read().unwrap().iter().map(|(name, factory)| {
+        (name.clone(), factory.clone())
+    }).collect();
+    let mut factories = Vec::with_capacity(registers.len());
+    for (name, factory) in registers {
+        let grace = deadline.checked_sub(start.elapsed()).unwrap_or(Duration::from_millis(0));
+        factories.push((name, factory.shutdown(grace)));
+    }
+
+    let summary = ShutdownSummary {
+        shells,
+        processes,
+        process_failed,
+        factories,
+        elapsed: start.elapsed(),
+    };
+
+    if summary.is_graceful() {
+        info!("===> Vm Shutdown Ok, shells: {}, processes: {}, factories: {}, time: {:?}",
+              summary.shells, summary.processes, summary.factories.len(), summary.elapsed);
+    } else {
+        warn!("!!!> Vm Shutdown Timeout, shells: {}, processes: {}, process failed: {}, factories: {}, dropped: {}, remaining: {}, time: {:?}",
+              summary.shells, summary.processes, summary.process_failed, summary.factories.len(),
+              summary.dropped(), summary.remaining(), summary.elapsed);
+    }
+
+    summary
+}
+
+//等待指定进程的消息队列为空，超时返回false
+fn wait_processes(pids: &[u64], timeout: Duration) -> bool {
+    let start = Instant::now();
+    let mut wait = Duration::from_millis(1);
+    loop {
+        if pids.iter().all(|pid| queue_len(*pid).map(|len| len == 0).unwrap_or(true)) {
+            return true;
+        }
+
+        let elapsed = start.elapsed();
+        if elapsed >= timeout {
+            return false;
+        }
+
+        //退避等待，等待时长有上限
+        thread::sleep(wait.min(timeout - elapsed));
+        wait = (wait * 2).min(Duration::from_millis(MAX_PROCESS_WAIT_TIME));
+    }
+}
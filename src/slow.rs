@@ -0,0 +1,249 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use parking_lot::{Mutex, RwLock};
+
+use atom::Atom;
+use adapter::{JS, now_utc};
+
+/*
+* 默认的慢调用缓冲区容量
+*/
+const DEFAULT_SLOW_CALL_CAPACITY: usize = 256;
+
+/*
+* 慢调用最多记录的js栈帧数量
+*/
+const MAX_SLOW_CALL_FRAMES: u32 = 32;
+
+lazy_static! {
+    //虚拟机工厂的慢调用阈值表
+    static ref SLOW_THRESHOLDS: RwLock<HashMap<Atom, Duration>> = RwLock::new(HashMap::new());
+    //慢调用阈值数量，用于在没有设置阈值时快速跳过检查
+    static ref SLOW_THRESHOLD_COUNT: AtomicUsize = AtomicUsize::new(0);
+    //慢调用缓冲区容量，超过容量则丢弃最早的慢调用
+    static ref SLOW_CALL_CAPACITY: AtomicUsize = AtomicUsize::new(DEFAULT_SLOW_CALL_CAPACITY);
+    //最近的慢调用缓冲区
+    static ref SLOW_CALLS: Mutex<VecDeque<SlowCall>> = Mutex::new(VecDeque::new());
+}
+
+/*
+* 慢调用的类型
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlowKind {
+    Task,       //虚拟机工厂调用的任务
+    Callback,   //异步回调或异步消息
+    Block,      //本地函数的同步阻塞调用，从阻塞到回应
+}
+
+/*
+* 虚拟机正在执行的调用，在调用开始时记录，在调用完成的回应中检查是否为慢调用
+*/
+pub(crate) struct SlowStart {
+    kind:       SlowKind,   //调用的类型
+    target:     String,     //调用的js全局函数名或回调函数
+    info:       Atom,       //任务信息
+    queue_wait: Duration,   //开始执行前的等待时长
+    start:      Instant,    //开始执行的时间
+}
+
+/*
+* 慢调用
+*/
+#[derive(Debug, Clone)]
+pub struct SlowCall {
+    time:       usize,                  //慢调用完成的时间，单位us
+    factory:    String,                 //虚拟机工厂名
+    vm_id:      usize,                  //虚拟机id
+    kind:       SlowKind,               //慢调用的类型
+    target:     String,                 //调用的js全局函数名、回调函数或本地函数
+    info:       String,                 //任务信息
+    queue_wait: Duration,               //开始执行前的等待时长
+    execution:  Duration,               //执行时长
+    frames:     Vec<(String, isize)>,   //完成时的js栈帧，包括文件名和行号
+    stack:      String,                 //完成时的js值栈
+}
+
+impl fmt::Display for SlowCall {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "factory: {:?}, vm: {}, kind: {:?}, target: {:?}, info: {:?}, queue wait: {:?}, execution: {:?}, time: {}",
+               self.factory, self.vm_id, self.kind, self.target, self.info, self.queue_wait, self.execution, self.time)?;
+        for (file, line) in &self.frames {
+            write!(f, "\n    at {}:{}", file, line)?;
+        }
+        if !self.stack.is_empty() {
+            write!(f, "\n{}", self.stack)?;
+        }
+        Ok(())
+    }
+}
+
+impl SlowCall {
+    //获取慢调用完成的时间
+    pub fn time(&self) -> usize {
+        self.time
+    }
+
+    //获取虚拟机工厂名
+    pub fn factory(&self) -> &str {
+        self.factory.as_str()
+    }
+
+    //获取虚拟机id
+    pub fn vm_id(&self) -> usize {
+        self.vm_id
+    }
+
+    //获取慢调用的类型
+    pub fn kind(&self) -> SlowKind {
+        self.kind
+    }
+
+    //获取调用的js全局函数名、回调函数或本地函数
+    pub fn target(&self) -> &str {
+        self.target.as_str()
+    }
+
+    //获取任务信息
+    pub fn info(&self) -> &str {
+        self.info.as_str()
+    }
+
+    //获取开始执行前的等待时长
+    pub fn queue_wait(&self) -> Duration {
+        self.queue_wait
+    }
+
+    //获取执行时长
+    pub fn execution(&self) -> Duration {
+        self.execution
+    }
+
+    //获取完成时的js栈帧
+    pub fn frames(&self) -> &[(String, isize)] {
+        self.frames.as_slice()
+    }
+
+    //获取完成时的js值栈
+    pub fn stack(&self) -> &str {
+        self.stack.as_str()
+    }
+}
+
+//设置指定虚拟机工厂的慢调用阈值，等待时长和执行时长之和超过阈值的任务、回调和同步阻塞调用将被记录
+pub fn set_slow_threshold(factory: &str, threshold: Duration) {
+    if SLOW_THRESHOLDS.write().insert(Atom::from(factory), threshold).is_none() {
+        SLOW_THRESHOLD_COUNT.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+//移除指定虚拟机工厂的慢调用阈值
+pub fn remove_slow_threshold(factory: &str) -> Option<Duration> {
+    let threshold = SLOW_THRESHOLDS.write().remove(&Atom::from(factory));
+    if threshold.is_some() {
+        SLOW_THRESHOLD_COUNT.fetch_sub(1, Ordering::SeqCst);
+    }
+    threshold
+}
+
+//获取指定虚拟机工厂的慢调用阈值
+pub fn slow_threshold(factory: &Atom) -> Option<Duration> {
+    if SLOW_THRESHOLD_COUNT.load(Ordering::Relaxed) == 0 {
+        return None;
+    }
+
+    SLOW_THRESHOLDS.read().get(factory).cloned()
+}
+
+//设置慢调用缓冲区容量
+pub fn set_slow_call_capacity(capacity: usize) {
+    SLOW_CALL_CAPACITY.store(capacity, Ordering::Relaxed);
+    let mut calls = SLOW_CALLS.lock();
+    while calls.len() > capacity {
+        calls.pop_front();
+    }
+}
+
+//获取最近的慢调用，从旧到新排列
+pub fn slow_calls() -> Vec<SlowCall> {
+    SLOW_CALLS.lock().iter().cloned().collect()
+}
+
+//清空最近的慢调用，返回清空的数量
+pub fn clear_slow_calls() -> usize {
+    let mut calls = SLOW_CALLS.lock();
+    let len = calls.len();
+    calls.clear();
+    len
+}
+
+//所属虚拟机工厂设置了慢调用阈值时，记录指定虚拟机开始执行的调用，必须在虚拟机所在线程调用
+pub(crate) fn start_slow<F: FnOnce() -> String>(js: &JS, kind: SlowKind, target: F, info: &Atom, queue_wait: Duration) {
+    if slow_threshold(&js.get_name()).is_none() {
+        return;
+    }
+
+    js.set_slow_start(SlowStart {
+        kind,
+        target: target(),
+        info: info.clone(),
+        queue_wait,
+        start: Instant::now(),
+    });
+}
+
+//检查指定虚拟机开始执行的调用是否为慢调用，必须在调用完成的回应中，虚拟机被整理或复用前调用，以获取调用完成时的js栈
+pub(crate) fn finish_slow(js: &JS) {
+    if let Some(slow) = js.take_slow_start() {
+        let SlowStart { kind, target, info, queue_wait, start } = slow;
+        check_slow(js, kind, || target, &info, queue_wait, start.elapsed());
+    }
+}
+
+//检查指定虚拟机的调用是否超过所属虚拟机工厂的慢调用阈值，超过则记录js栈并写入慢调用缓冲区，必须在虚拟机所在线程调用
+pub(crate) fn check_slow<F: FnOnce() -> String>(js: &JS, kind: SlowKind, target: F, info: &Atom, queue_wait: Duration, execution: Duration) {
+    let threshold = match slow_threshold(&js.get_name()) {
+        None => return,
+        Some(threshold) => threshold,
+    };
+
+    if queue_wait + execution < threshold {
+        return;
+    }
+
+    let mut frames = Vec::new();
+    for index in 0..MAX_SLOW_CALL_FRAMES {
+        match js.stack_frame(index) {
+            None => break,
+            Some(frame) => frames.push(frame),
+        }
+    }
+
+    let call = SlowCall {
+        time: now_utc(),
+        factory: (&js.get_name()).to_string(),
+        vm_id: js.get_id(),
+        kind,
+        target: target(),
+        info: info.to_string(),
+        queue_wait,
+        execution,
+        frames,
+        stack: js.dump_stack(),
+    };
+    warn!("!!!> Vm Slow Call, threshold: {:?}, {}", threshold, call);
+
+    let capacity = SLOW_CALL_CAPACITY.load(Ordering::Relaxed);
+    if capacity == 0 {
+        return;
+    }
+
+    let mut calls = SLOW_CALLS.lock();
+    while calls.len() >= capacity {
+        calls.pop_front();
+    }
+    calls.push_back(call);
+}
@@ -0,0 +1,250 @@
+use std::fs::File;
+use std::path::Path;
+use std::cell::RefCell;
+use std::time::Duration;
+use std::sync::Mutex;
+use std::collections::VecDeque;
+use std::io::{Write, BufWriter, Result};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use rand::{thread_rng, Rng};
+
+use adapter::{JS, now_utc};
+use heap::escape_json;
+
+/*
+* 默认的跨度缓冲区容量
+*/
+const DEFAULT_SPAN_CAPACITY: usize = 65536;
+
+lazy_static! {
+    //是否启用跟踪
+    static ref TRACE_ENABLED: AtomicBool = AtomicBool::new(false);
+    //跨度缓冲区容量，超过容量则丢弃最早的跨度
+    static ref TRACE_SPAN_CAPACITY: AtomicUsize = AtomicUsize::new(DEFAULT_SPAN_CAPACITY);
+    //已完成的跨度缓冲区
+    static ref TRACE_SPANS: Mutex<VecDeque<Span>> = Mutex::new(VecDeque::new());
+}
+
+thread_local! {
+    //当前线程正在执行的跟踪上下文
+    static CURRENT_TRACE: RefCell<Option<TraceContext>> = RefCell::new(None);
+}
+
+/*
+* 跟踪上下文，包括跟踪id和当前跨度id，同一个请求的所有跨度有相同的跟踪id
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TraceContext {
+    trace_id:   u64,    //跟踪id
+    span_id:    u64,    //跨度id
+}
+
+impl TraceContext {
+    //构建一个新的跟踪的根上下文
+    pub fn new() -> Self {
+        TraceContext {
+            trace_id: alloc_id(),
+            span_id: alloc_id(),
+        }
+    }
+
+    //使用指定的跟踪id和跨度id构建跟踪上下文，用于延续外部传入的跟踪
+    pub fn with_ids(trace_id: u64, span_id: u64) -> Self {
+        TraceContext {
+            trace_id,
+            span_id,
+        }
+    }
+
+    //构建当前跨度的子跨度上下文
+    pub fn child(&self) -> Self {
+        TraceContext {
+            trace_id: self.trace_id,
+            span_id: alloc_id(),
+        }
+    }
+
+    //获取跟踪id
+    pub fn trace_id(&self) -> u64 {
+        self.trace_id
+    }
+
+    //获取跨度id
+    pub fn span_id(&self) -> u64 {
+        self.span_id
+    }
+}
+
+/*
+* 已完成的跨度
+*/
+#[derive(Debug, Clone)]
+pub struct Span {
+    context:    TraceContext,                   //跨度的跟踪上下文
+    parent_id:  Option<u64>,                    //父跨度id，为空表示根跨度
+    name:       String,                         //跨度名
+    start:      usize,                          //跨度开始执行的时间，单位us
+    queue_wait: Duration,                       //跨度开始执行前的等待时长
+    duration:   Duration,                       //跨度的执行时长
+    attrs:      Vec<(&'static str, String)>,    //跨度属性
+}
+
+impl Span {
+    //构建已完成的跨度，开始执行的时间为当前时间减去执行时长
+    pub(crate) fn new(context: TraceContext,
+                      parent_id: Option<u64>,
+                      name: String,
+                      queue_wait: Duration,
+                      duration: Duration,
+                      attrs: Vec<(&'static str, String)>) -> Self {
+        Span {
+            context,
+            parent_id,
+            name,
+            start: now_utc().saturating_sub(duration.as_micros() as usize),
+            queue_wait,
+            duration,
+            attrs,
+        }
+    }
+
+    //获取跨度的跟踪上下文
+    pub fn context(&self) -> TraceContext {
+        self.context
+    }
+
+    //获取父跨度id
+    pub fn parent_id(&self) -> Option<u64> {
+        self.parent_id
+    }
+
+    //获取跨度名
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    //获取跨度开始执行的时间
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    //获取跨度开始执行前的等待时长
+    pub fn queue_wait(&self) -> Duration {
+        self.queue_wait
+    }
+
+    //获取跨度的执行时长
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    //获取跨度属性
+    pub fn attrs(&self) -> &[(&'static str, String)] {
+        self.attrs.as_slice()
+    }
+}
+
+//启用跟踪，并设置跨度缓冲区容量
+pub fn enable_trace(capacity: usize) {
+    TRACE_SPAN_CAPACITY.store(capacity, Ordering::Relaxed);
+    TRACE_ENABLED.store(true, Ordering::SeqCst);
+}
+
+//停用跟踪，已完成的跨度不会被清除
+pub fn disable_trace() {
+    TRACE_ENABLED.store(false, Ordering::SeqCst);
+}
+
+//判断是否启用跟踪
+#[inline]
+pub fn is_tracing() -> bool {
+    TRACE_ENABLED.load(Ordering::Relaxed)
+}
+
+//获取当前线程正在执行的跟踪上下文，只在虚拟机任务同步执行期间或生成进程期间有效
+pub fn current() -> Option<TraceContext> {
+    CURRENT_TRACE.with(|current| *current.borrow())
+}
+
+//获取指定虚拟机当前任务的跟踪上下文，可以在本地函数和异步回调中使用
+pub fn vm_context(js: &JS) -> Option<TraceContext> {
+    js.call_options().and_then(|options| options.trace()).or_else(current)
+}
+
+//设置当前线程正在执行的跟踪上下文，返回上一个跟踪上下文
+pub(crate) fn enter(context: Option<TraceContext>) -> Option<TraceContext> {
+    CURRENT_TRACE.with(|current| current.replace(context))
+}
+
+//记录已完成的跨度，未启用跟踪则忽略
+pub(crate) fn record(span: Span) {
+    if !is_tracing() {
+        return;
+    }
+
+    let capacity = TRACE_SPAN_CAPACITY.load(Ordering::Relaxed);
+    let mut spans = TRACE_SPANS.lock().unwrap();
+    while spans.len() >= capacity && capacity > 0 {
+        spans.pop_front();
+    }
+    if capacity > 0 {
+        spans.push_back(span);
+    }
+}
+
+//取出所有已完成的跨度，按完成顺序排列
+pub fn take_spans() -> Vec<Span> {
+    TRACE_SPANS.lock().unwrap().drain(..).collect()
+}
+
+//取出所有已完成的跨度，并以Chrome跟踪格式导出到指定文件，同一个跟踪的跨度在同一行显示，返回导出的跨度数量
+pub fn export_chrome_trace<P: AsRef<Path>>(path: P) -> Result<usize> {
+    let spans = take_spans();
+    let mut writer = BufWriter::new(File::create(path)?);
+    write!(writer, "{{\"traceEvents\":[")?;
+    let mut is_first = true;
+    for span in &spans {
+        let mut args = format!("\"trace_id\":\"{:016x}\",\"span_id\":\"{:016x}\"", span.context.trace_id, span.context.span_id);
+        if let Some(parent_id) = span.parent_id {
+            args.push_str(&format!(",\"parent_id\":\"{:016x}\"", parent_id));
+        }
+        args.push_str(&format!(",\"queue_wait_us\":{}", span.queue_wait.as_micros()));
+        for (key, value) in &span.attrs {
+            args.push_str(&format!(",\"{}\":\"{}\"", key, escape_json(value)));
+        }
+
+        let tid = span.context.trace_id & 0xffffffff;
+        if span.queue_wait.as_micros() > 0 {
+            //记录跨度开始执行前的等待
+            if !is_first {
+                write!(writer, ",")?;
+            }
+            is_first = false;
+            write!(writer, "{{\"name\":\"{} (queue)\",\"cat\":\"queue\",\"ph\":\"X\",\"ts\":{},\"dur\":{},\"pid\":1,\"tid\":{},\"args\":{{{}}}}}",
+                   escape_json(&span.name), span.start.saturating_sub(span.queue_wait.as_micros() as usize),
+                   span.queue_wait.as_micros(), tid, args)?;
+        }
+
+        if !is_first {
+            write!(writer, ",")?;
+        }
+        is_first = false;
+        write!(writer, "{{\"name\":\"{}\",\"cat\":\"pi_vm\",\"ph\":\"X\",\"ts\":{},\"dur\":{},\"pid\":1,\"tid\":{},\"args\":{{{}}}}}",
+               escape_json(&span.name), span.start, span.duration.as_micros(), tid, args)?;
+    }
+    write!(writer, "]}}")?;
+    writer.flush()?;
+
+    Ok(spans.len())
+}
+
+//分配一个非0的随机id
+fn alloc_id() -> u64 {
+    loop {
+        let id = thread_rng().gen::<u64>();
+        if id != 0 {
+            return id;
+        }
+    }
+}
@@ -0,0 +1,82 @@
+extern crate atom;
+extern crate worker;
+extern crate handler;
+extern crate apm;
+extern crate pi_vm;
+
+#[macro_use]
+extern crate lazy_static;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use worker::worker_pool::WorkerPool;
+use worker::impls::{JS_TASK_POOL, JS_WORKER_WALKER};
+use worker::worker::WorkerType;
+
+use atom::Atom;
+use handler::GenType;
+use apm::allocator::set_max_alloced_limit;
+
+use pi_vm::adapter::{JSType, JS, register_native_object, set_vm_timeout};
+use pi_vm::bonmgr::{BON_MGR, NativeObjsAuth, FnMeta, CallResult};
+use pi_vm::proc_pool::{set_factory, spawn_process, set_catcher, throw_process, queue_len};
+use pi_vm::duk_proc::DukProcessFactory;
+use pi_vm::shutdown;
+
+lazy_static! {
+    static ref THROWN: Mutex<Vec<String>> = Mutex::new(Vec::new());
+}
+
+//测试全局关闭时，进程的异常捕获器在进程关闭前收到关闭原因，全局关闭会关闭所有进程和虚拟机工厂，所以使用独立的测试
+#[test]
+fn test_shutdown_throw_process() {
+    let worker_pool = Box::new(WorkerPool::new("js test".to_string(), WorkerType::Js, 8, 1024 * 1024, 30000, JS_WORKER_WALKER.clone()));
+    worker_pool.run(JS_TASK_POOL.clone());
+    set_max_alloced_limit(1073741824);
+    set_vm_timeout(30000);
+
+    register_native_object();
+    BON_MGR.regist_fun_meta(FnMeta::CallArg(js_test_register_catcher), 0x1);
+    BON_MGR.regist_fun_meta(FnMeta::CallArg(js_test_catch), 0x10);
+
+    let auth = Arc::new(NativeObjsAuth::new(None, None));
+    let opts = JS::new(1, Atom::from("test vm"), auth.clone(), None);
+    assert!(opts.is_some());
+    let js = opts.unwrap();
+    let opts = js.compile("test_shutdown_process.js".to_string(), "start = function() { onerror = function(e) { NativeObject.call(0x10, [e]); }; var index = callbacks.register(onerror); NativeObject.call(0x1, [_$pid, index]); };".to_string());
+    assert!(opts.is_some());
+    let code = opts.unwrap();
+
+    let factory_name = Atom::from("test_shutdown_proc_factory");
+    set_factory(factory_name.clone(), Arc::new(DukProcessFactory::new(factory_name.clone(), auth, Arc::new(vec![code]))));
+    let pid = spawn_process(Some("test_shutdown_process".to_string()), factory_name, "handler".to_string(), "start".to_string(), "start".to_string(), GenType::Array(vec![])).unwrap();
+    assert!(queue_len(pid).is_some());
+
+    //进程不存在，则抛出失败
+    assert!(throw_process(pid + 1, "test throw".to_string()).is_err());
+
+    let summary = shutdown(Duration::from_millis(5000));
+    assert_eq!(summary.processes(), 1);
+    assert_eq!(summary.process_failed(), 0);
+    assert_eq!(queue_len(pid), None);
+    assert_eq!(*THROWN.lock().unwrap(), vec!["pi_vm shutdown".to_string()]);
+}
+
+fn js_test_register_catcher(js: Arc<JS>, args: Vec<JSType>) -> Option<CallResult> {
+    let pid = args[0].get_u32() as u64;
+    let callback = args[1].get_u32();
+
+    if let Err(e) = set_catcher(pid, GenType::U32(callback)) {
+        return Some(CallResult::Err(e.to_string()));
+    }
+
+    js.new_undefined();
+    Some(CallResult::Ok)
+}
+
+fn js_test_catch(js: Arc<JS>, args: Vec<JSType>) -> Option<CallResult> {
+    THROWN.lock().unwrap().push(args[0].get_str());
+    js.new_undefined();
+    Some(CallResult::Ok)
+}
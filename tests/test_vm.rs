@@ -11,9 +11,11 @@ extern crate pi_vm;
 extern crate apm;
 
 use std::mem;
+use std::io::{Read, Write, Seek, SeekFrom};
+use std::net::TcpStream;
 use std::thread;
 use std::ffi::CString;
-use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::{Instant, Duration};
 use std::sync::{Arc, Mutex, Condvar};
 
@@ -28,6 +30,9 @@ extern crate env_logger;
 
 extern crate rand;
 
+#[cfg(feature = "tokio")]
+extern crate tokio;
+
 use rand::prelude::*;
 use rand::{Rng, SeedableRng};
 use rand::rngs::SmallRng;
@@ -40,9 +45,25 @@ use worker::task::TaskType;
 use worker::worker::WorkerType;
 use worker::worker_pool::WorkerPool;
 use worker::impls::{TASK_POOL_TIMER, JS_WORKER_WALKER, JS_TASK_POOL, create_js_task_queue, lock_js_task_queue, unlock_js_task_queue, cast_js_task};
-use pi_vm::pi_vm_impl::{VMFactory, block_reply, block_throw, push_callback, register_async_request};
-use pi_vm::adapter::{load_lib_backtrace, register_native_object, dukc_remove_value, dukc_top, JS, JSType, set_vm_timeout};
+use pi_vm::pi_vm_impl::{VM_FACTORY_QUEUES, VMFactory, CallOptions, OverloadPolicy, RefuseReason, BatchError, LeakReport, new_queue, remove_queue, sweep_idle_queues, block_reply, block_throw, push_callback, push_msg, register_async_request};
+use pi_vm::adapter::{load_lib_backtrace, register_native_object, register_heap_limit_handler, dukc_remove_value, dukc_top, JS, JSType, JSStatus, set_vm_timeout};
 use pi_vm::channel_map::VMChannel;
+use pi_vm::buffer::{JSBufferCursor, external_buffer_count};
+use pi_vm::heap::{HeapStats, write_heap_snapshot};
+use pi_vm::affinity::AffinityConfig;
+use pi_vm::scaling::{ScalingConfig, ScalingResult, scale_factory};
+use pi_vm::gray_factory::{GrayFactory, GrayRule};
+use pi_vm::metrics;
+use pi_vm::exposition::{render_prometheus, render_json, listen_metrics};
+use pi_vm::event::{VmEvent, ChannelListener, register_factory_listener, unregister_listener};
+use pi_vm::trace::{TraceContext, enable_trace, disable_trace, take_spans, export_chrome_trace};
+use pi_vm::reuse::{ReusePolicy, ReuseDecision, VmStats, DefaultReusePolicy, MaxReusedCountPolicy, MemoryPressurePolicy};
+use pi_vm::slow::{SlowKind, set_slow_threshold, remove_slow_threshold, slow_calls};
+use pi_vm::health::{HealthStatus, HealthThresholds, health_with};
+use pi_vm::executor::TestExecutor;
+use pi_vm::scheduler;
+#[cfg(feature = "tokio")]
+use pi_vm::scheduler::{VmScheduler, TokioScheduler};
 use pi_vm::proc::{Process, ProcInfo, ProcessFactory};
 use apm::allocator::set_max_alloced_limit;
 use pi_vm::bonmgr::{CallResult, NativeObjsAuth, FnMeta, BON_MGR};
@@ -267,6 +288,128 @@ fn base_test() {
     println!("js heap size: {}", js.heap_size());
 }
 
+#[test]
+fn test_typed_array() {
+    load_lib_backtrace();
+    register_native_object();
+    let opts = JS::new(1, Atom::from("test vm"), Arc::new(NativeObjsAuth::new(None, None)), None);
+    assert!(opts.is_some());
+    let js = opts.unwrap();
+
+    let mut val = js.new_float32_array(4);
+    assert!(val.is_float32_array() && val.is_typed_array() && !val.is_uint8_array());
+    {
+        let tmp = unsafe { val.to_f32_slice_mut().unwrap() };
+        assert!(tmp.len() == 4);
+        for i in 0..tmp.len() {
+            tmp[i] = i as f32 * 0.5;
+        }
+    }
+    assert!(val.to_f32_slice().unwrap() == &[0.0, 0.5, 1.0, 1.5]);
+    assert!(val.to_f64_slice().is_err()); //类型不匹配
+
+    let val = js.new_int16_array(8);
+    assert!(val.is_int16_array() && val.to_i16_slice().unwrap().len() == 8);
+    let val = js.new_uint32_array(8);
+    assert!(val.is_uint32_array() && val.to_u32_slice().unwrap().len() == 8);
+
+    //在已有的ArrayBuffer上构建视图
+    let buffer = js.new_array_buffer(32);
+    let mut view = js.new_float64_array_view(&buffer, 8, 2).unwrap();
+    assert!(view.is_float64_array());
+    {
+        let tmp = unsafe { view.to_f64_slice_mut().unwrap() };
+        assert!(tmp.len() == 2);
+        tmp[0] = 921.1356737853f64;
+    }
+    assert!(buffer.into_buffer().read_f64(8) == 921.1356737853f64);
+
+    let view = js.new_data_view(&buffer, 0, 16).unwrap();
+    assert!(view.is_data_view() && view.to_bytes().len() == 16);
+
+    let not_buffer = js.new_object();
+    assert!(js.new_int32_array_view(&not_buffer, 0, 1).is_err()); //只允许在ArrayBuffer上构建视图
+    assert!(js.new_int32_array_view(&buffer, 2, 1).is_err()); //偏移未按元素大小对齐
+    assert!(js.new_float64_array_view(&buffer, 8, 4).is_err()); //视图超出缓冲区
+    assert!(js.new_uint8_array_view(&buffer, 0, 33).is_err()); //视图超出缓冲区
+    assert!(js.new_uint16_array_view(&buffer, 24, 4).unwrap().to_u16_slice().unwrap().len() == 4);
+}
+
+#[test]
+fn test_js_buffer_cursor() {
+    load_lib_backtrace();
+    register_native_object();
+    let opts = JS::new(1, Atom::from("test vm"), Arc::new(NativeObjsAuth::new(None, None)), None);
+    assert!(opts.is_some());
+    let js = opts.unwrap();
+
+    let val = js.new_uint8_array(16);
+    let mut buf = val.into_buffer();
+    assert!(buf.try_write_u64(8, 0xffffffffffffffff).unwrap() == 16);
+    assert!(buf.try_read_u64(8).unwrap() == 0xffffffffffffffff);
+    assert!(buf.try_read_u64(9).is_err()); //越界读
+    assert!(buf.try_write_u32(14, 0).is_err()); //越界写
+    assert!(buf.try_read(usize::max_value(), 2).is_err()); //溢出
+
+    let mut cursor = JSBufferCursor::new(val.into_buffer());
+    assert!(cursor.write_varint(300).unwrap() == 2);
+    assert!(cursor.write_varint_signed(-1).unwrap() == 1);
+    assert!(cursor.write_string("Hello").unwrap() == 6);
+    assert!(cursor.write_string("Hello World!").is_err()); //剩余空间不足
+    assert!(cursor.position() == 9);
+
+    cursor.seek(SeekFrom::Start(0)).unwrap();
+    assert!(cursor.read_varint().unwrap() == 300);
+    assert!(cursor.read_varint_signed().unwrap() == -1);
+    assert!(cursor.read_string().unwrap() == "Hello".to_string());
+
+    let mut tmp = [0u8; 16];
+    cursor.seek(SeekFrom::End(-4)).unwrap();
+    assert!(cursor.read(&mut tmp).unwrap() == 4);
+    assert!(cursor.read(&mut tmp).unwrap() == 0);
+    assert!(cursor.seek(SeekFrom::Current(1)).is_err());
+}
+
+#[test]
+fn test_external_buffer() {
+    load_lib_backtrace();
+    register_native_object();
+    let opts = JS::new(1, Atom::from("test vm"), Arc::new(NativeObjsAuth::new(None, None)), None);
+    assert!(opts.is_some());
+    let js = opts.unwrap();
+
+    //独占的数据不复制，脚本直接读取外部数据
+    let data: Vec<u8> = (0..16).collect();
+    let ptr = data.as_ptr();
+    let count = external_buffer_count();
+    let val = js.new_external_uint8_array(data).unwrap();
+    assert!(val.is_uint8_array() && val.to_bytes().as_ptr() == ptr);
+    assert!(val.to_bytes() == (0..16).collect::<Vec<u8>>().as_slice());
+    assert!(external_buffer_count() == count + 1);
+    assert!(js.set_global_var("$ext".to_string(), val));
+    assert!(js.eval("$ext[1] + $ext[15];".to_string()).get_u32() == 16);
+
+    //外部数据在脚本对象被回收前保持有效，回收后释放
+    assert!(js.eval("var $view = new Uint8Array($ext.buffer, 8, 8); $ext = undefined; $view[7];".to_string()).get_u32() == 15);
+    assert!(js.free_global());
+    assert!(external_buffer_count() == count + 1);
+    js.eval("$view = undefined;".to_string());
+    assert!(js.free_global());
+    assert!(external_buffer_count() == count);
+
+    //共享数据不复制，没有其它引用的共享数据可写，有其它引用的共享数据只读
+    let unique = Arc::new(vec![1u8; 8]);
+    let ptr = unique.as_ptr();
+    let val = js.new_external_array_buffer(unique).unwrap();
+    assert!(val.is_array_buffer() && val.to_bytes().as_ptr() == ptr);
+    let shared = Arc::new(vec![2u8; 8]);
+    let val = js.new_external_uint8_array(shared.clone()).unwrap();
+    assert!(val.to_bytes().as_ptr() == shared.as_ptr() && val.to_bytes() == shared.as_slice());
+    assert!(js.set_global_var("$shared".to_string(), val));
+    assert!(js.eval("$shared[0] = 9; $shared[0];".to_string()).get_u32() == 2);
+    assert!(shared.as_slice() == [2u8; 8]);
+}
+
 //测试从虚拟机工厂进行虚拟机js执行
 #[test]
 fn test_vm_factory() {
@@ -303,10 +446,10 @@ fn test_vm_factory() {
                     js.new_f32(0.999999);
                     2usize
                 });
-                factory.call(None,
-                             Atom::from("call"),
-                             func,
-                             Atom::from("test factory call task"));
+                assert!(factory.call(None,
+                                     Atom::from("call"),
+                                     func,
+                                     Atom::from("test factory call task")).is_ok());
                 thread::sleep(Duration::from_millis(1000));
             }
             println!("!!!!!!time: {:?}", Instant::now() - now);
@@ -315,6 +458,372 @@ fn test_vm_factory() {
     thread::sleep(Duration::from_millis(100000));
 }
 
+//测试虚拟机堆统计和堆快照导出
+#[test]
+fn test_heap_stats() {
+    register_native_object();
+    let opts = JS::new(1, Atom::from("test heap stats"), Arc::new(NativeObjsAuth::new(None, None)), None);
+    assert!(opts.is_some());
+    let js = opts.unwrap();
+    let opts = js.compile("test_heap_stats.js".to_string(), "var leak = []; for(var i = 0; i < 1000; i++) { leak.push({ index: i, name: \"leak\" + i }); } var buf = new ArrayBuffer(1024 * 1024);".to_string());
+    assert!(opts.is_some());
+    assert!(js.load(opts.unwrap().as_slice()));
+    while !js.is_ran() {}
+
+    let stats = HeapStats::with_largest(&js, 5).unwrap();
+    println!("!!!!!!heap stats: {:?}", stats);
+    assert!(stats.objects().count() >= 1000);
+    assert!(stats.strings().count() >= 1000);
+    assert!(stats.buffers().size() >= 1024 * 1024);
+    assert!(stats.largest().len() <= 5);
+    assert!(stats.largest().windows(2).all(|objs| objs[0].size() >= objs[1].size()));
+
+    let path = std::env::temp_dir().join("test_heap_stats.heapsnapshot.json");
+    let count = write_heap_snapshot(&js, &path).unwrap();
+    assert!(count >= 2000);
+    let mut snapshot = String::new();
+    std::fs::File::open(&path).unwrap().read_to_string(&mut snapshot).unwrap();
+    assert!(snapshot.starts_with("{\"vm_id\":1,\"name\":\"test heap stats\""));
+    assert!(snapshot.ends_with("]}"));
+}
+
+//测试从模板虚拟机复制虚拟机的虚拟机工厂
+#[cfg(feature = "vm-template")]
+#[test]
+fn test_vm_factory_template() {
+    let executor = TestExecutor::install();
+    set_max_alloced_limit(1073741824);
+
+    register_native_object();
+    let opts = JS::new(1, Atom::from("test vm"), Arc::new(NativeObjsAuth::new(None, None)), None);
+    assert!(opts.is_some());
+    let js = opts.unwrap();
+    let opts = js.compile("test_vm_factory_template.js".to_string(), "var tmp = 0; function call(x, y) { tmp += 1; console.log(\"!!!!!!x: \" + x + \", y: \" + y + \", tmp: \" + tmp); };".to_string());
+    assert!(opts.is_some());
+    let code = opts.unwrap();
+
+    //复制的虚拟机拥有模板虚拟机的堆状态，且与模板虚拟机相互独立
+    assert!(js.load(code.as_slice()));
+    while !js.is_ran() {}
+    js.eval("tmp = 10;".to_string());
+    let copy = JS::clone_from(&js, 2, Atom::from("test vm"), Arc::new(NativeObjsAuth::new(None, None)), None).unwrap();
+    assert!(copy.eval("tmp;".to_string()).get_u32() == 10);
+    assert!(copy.eval("typeof call;".to_string()).get_str() == "function".to_string());
+    copy.eval("call(1, 2);".to_string());
+    assert!(copy.eval("tmp;".to_string()).get_u32() == 11);
+    assert!(js.eval("tmp;".to_string()).get_u32() == 10);
+    assert!(!js.has_native_objects());
+
+    let factory = VMFactory::new("test vm template", 3, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(Arc::new(code))
+        .enable_template();
+    assert!(factory.is_template());
+    match factory.produce(3) {
+        Err(e) => panic!("factory produce failed, e: {:?}", e),
+        Ok(len) => {
+            assert_eq!(len, 3);
+            for _ in 0..8 {
+                let func = Box::new(move |js: Arc<JS>| {
+                    js.new_str("Hello World".to_string()).unwrap();
+                    js.new_u32(0xffffffff);
+                    2usize
+                });
+                assert!(factory.call(None,
+                                     Atom::from("call"),
+                                     func,
+                                     Atom::from("test factory template call task")).is_ok());
+                assert!(executor.run_until_idle() >= 1);
+            }
+        },
+    }
+    assert_eq!(executor.infos().iter().filter(|info| *info == &Atom::from("test factory template call task")).count(), 8);
+
+    TestExecutor::uninstall();
+}
+
+//测试虚拟机堆限制
+#[test]
+fn test_vm_factory_heap_limit() {
+    let executor = TestExecutor::install();
+    set_max_alloced_limit(1073741824);
+
+    register_native_object();
+    let limited = Arc::new(AtomicUsize::new(0));
+    let limited_copy = limited.clone();
+    register_heap_limit_handler(Arc::new(move |vm: Arc<JS>, size: usize, limit: usize| {
+        println!("!!!!!!heap limit, vm: {:?}, size: {}, limit: {}", vm, size, limit);
+        assert!(vm.is_wait_throw());
+        limited_copy.fetch_add(1, Ordering::SeqCst);
+    }));
+
+    let opts = JS::new(1, Atom::from("test vm"), Arc::new(NativeObjsAuth::new(None, None)), None);
+    assert!(opts.is_some());
+    let js = opts.unwrap();
+    let opts = js.compile("test_vm_factory_heap_limit.js".to_string(), "function call() { try { var buf = new ArrayBuffer(64 * 1024 * 1024); } catch(e) { console.log(\"!!!!!!catch heap limit: \" + (e instanceof RangeError)); } };".to_string());
+    assert!(opts.is_some());
+    let code = opts.unwrap();
+
+    let factory = VMFactory::new("test vm heap limit", 1, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(Arc::new(code))
+        .set_heap_limit(16 * 1024 * 1024);
+    assert_eq!(factory.heap_limit(), 16 * 1024 * 1024);
+    factory.produce(1).unwrap();
+    assert!(factory.call(None, Atom::from("call"), Box::new(|_js: Arc<JS>| 0usize), Atom::from("test factory heap limit task")).is_ok());
+    executor.run_until_idle();
+    assert_eq!(limited.load(Ordering::SeqCst), 1);
+
+    TestExecutor::uninstall();
+}
+
+//总是替换虚拟机的复用策略
+struct AlwaysRecreatePolicy;
+
+impl ReusePolicy for AlwaysRecreatePolicy {
+    fn decide(&self, _stats: &VmStats) -> ReuseDecision {
+        ReuseDecision::Recreate
+    }
+}
+
+//测试虚拟机复用策略
+#[test]
+fn test_vm_factory_reuse_policy() {
+    let executor = TestExecutor::install();
+    set_max_alloced_limit(1073741824);
+
+    register_native_object();
+    let opts = JS::new(1, Atom::from("test vm"), Arc::new(NativeObjsAuth::new(None, None)), None);
+    assert!(opts.is_some());
+    let js = opts.unwrap();
+
+    //虚拟机堆大小达到虚拟机最大堆大小，则释放
+    let factory = VMFactory::new("test vm reuse stats", 0, 0, 1, 1, Arc::new(NativeObjsAuth::new(None, None)));
+    let stats = VmStats::new(&js, &factory);
+    assert_eq!(stats.reused_count(), 0);
+    assert_eq!(DefaultReusePolicy.decide(&stats), ReuseDecision::Free);
+    assert_eq!(MaxReusedCountPolicy.decide(&stats), ReuseDecision::Free);
+    //虚拟机堆大小超过虚拟机工厂的虚拟机堆大小，则一定丢弃
+    let decision = MemoryPressurePolicy::default().decide(&stats);
+    assert!(decision == ReuseDecision::Throw || decision == ReuseDecision::Recreate);
+
+    let factory = VMFactory::new("test vm reuse stats", 0, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)));
+    assert_eq!(MaxReusedCountPolicy.decide(&VmStats::new(&js, &factory)), ReuseDecision::Reuse);
+
+    //替换虚拟机时，异步构建新的虚拟机
+    let opts = js.compile("test_vm_factory_reuse_policy.js".to_string(), "function call(x) { return x; };".to_string());
+    assert!(opts.is_some());
+    let code = opts.unwrap();
+    let factory = VMFactory::new("test vm reuse policy", 1, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(Arc::new(code))
+        .set_reuse_policy(Arc::new(AlwaysRecreatePolicy));
+    factory.produce(1).unwrap();
+    let func = Box::new(move |js: Arc<JS>| {
+        js.new_u32(1);
+        1usize
+    });
+    assert!(factory.call(None, Atom::from("call"), func, Atom::from("test reuse policy task")).is_ok());
+    assert!(executor.run_once());
+    assert_eq!(executor.infos(), vec![Atom::from("test reuse policy task")]);
+    assert_eq!(factory.size(), 0); //已丢弃，且未同步构建新的虚拟机
+    assert!(executor.ready_len() > 0);
+    executor.run_until_idle();
+    assert!(executor.infos().contains(&Atom::from("vm recreate task")));
+    assert_eq!(factory.size(), 1);
+    assert_eq!(factory.free_pool_size() + factory.free_buf_size(), 1);
+
+    TestExecutor::uninstall();
+}
+
+//测试虚拟机复用时的内存泄漏检查
+#[test]
+fn test_vm_factory_leak_detect() {
+    let executor = TestExecutor::install();
+    set_max_alloced_limit(1073741824);
+
+    register_native_object();
+    let opts = JS::new(1, Atom::from("test vm"), Arc::new(NativeObjsAuth::new(None, None)), None);
+    assert!(opts.is_some());
+    let js = opts.unwrap();
+    //内置对象不会随全局环境重置，每次调用都在内置对象上泄漏对象
+    let opts = js.compile("test_vm_factory_leak_detect.js".to_string(), "function call(n) { if (!Array.__leak) { Array.__leak = []; } for (var i = 0; i < n; i++) { Array.__leak.push({ index: i, name: \"leak\" + i }); } };".to_string());
+    assert!(opts.is_some());
+    let code = opts.unwrap();
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let sender = Mutex::new(sender);
+    let factory = VMFactory::new("test vm leak detect", 1, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(Arc::new(code))
+        .set_leak_detect(3, true)
+        .set_leak_handler(Arc::new(move |report: LeakReport| {
+            sender.lock().unwrap().send(report).unwrap();
+        }));
+    factory.produce(1).unwrap();
+
+    for index in 0..3 {
+        let func = Box::new(move |js: Arc<JS>| {
+            js.new_u32(1000);
+            1usize
+        });
+        assert!(factory.call(None, Atom::from("call"), func, Atom::from(format!("test leak task {}", index))).is_ok());
+        executor.run_until_idle();
+        if index < 2 {
+            //连续增长的复用次数不足，不报告
+            assert!(receiver.try_recv().is_err());
+            assert_eq!(factory.size(), 1);
+        }
+    }
+
+    let report = receiver.try_recv().unwrap();
+    assert!(receiver.try_recv().is_err());
+    assert_eq!(report.factory(), "test vm leak detect");
+    assert_eq!(report.heap_sizes().len(), 3);
+    assert!(report.heap_sizes().windows(2).all(|sizes| sizes[0] < sizes[1]));
+    assert_eq!(report.infos(), &[Atom::from("test leak task 0"), Atom::from("test leak task 1"), Atom::from("test leak task 2")]);
+    assert!(report.is_retired());
+    assert_eq!(factory.size(), 0); //泄漏的虚拟机已被主动丢弃
+
+    TestExecutor::uninstall();
+}
+
+//测试虚拟机工厂任务调度队列的过载策略
+#[test]
+fn test_vm_factory_overload_policy() {
+    let executor = TestExecutor::install();
+    set_max_alloced_limit(1073741824);
+
+    register_native_object();
+    let opts = JS::new(1, Atom::from("test vm"), Arc::new(NativeObjsAuth::new(None, None)), None);
+    assert!(opts.is_some());
+    let js = opts.unwrap();
+    let opts = js.compile("test_vm_factory_overload_policy.js".to_string(), "function call(x) { return x; };".to_string());
+    assert!(opts.is_some());
+    let code = Arc::new(opts.unwrap());
+
+    //启用伸缩且不手动伸缩，则没有空闲虚拟机时，任务只能加入任务调度队列
+    let reject = VMFactory::new("test vm overload reject", 1, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(code.clone())
+        .set_scaling(ScalingConfig::new(1, 1))
+        .set_queue_capacity(1, OverloadPolicy::RejectNewest);
+    let block = Arc::new(VMFactory::new("test vm overload block", 1, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(code.clone())
+        .set_scaling(ScalingConfig::new(1, 1))
+        .set_queue_capacity(1, OverloadPolicy::Block(Duration::from_millis(5000))));
+    let timeout = VMFactory::new("test vm overload timeout", 1, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(code)
+        .set_scaling(ScalingConfig::new(1, 1))
+        .set_queue_capacity(1, OverloadPolicy::Block(Duration::from_millis(50)));
+    reject.produce(1).unwrap();
+    block.produce(1).unwrap();
+    timeout.produce(0).unwrap();
+
+    //任务调度队列已满，则拒绝最新的任务
+    assert!(reject.call(None, Atom::from("call"), Box::new(|js: Arc<JS>| { js.new_u32(0); 1usize }), Atom::from("test reject task 0")).is_ok());
+    assert!(reject.call(None, Atom::from("call"), Box::new(|js: Arc<JS>| { js.new_u32(1); 1usize }), Atom::from("test reject task 1")).is_ok());
+    assert_eq!(reject.queue_len(), 1);
+    assert_eq!(reject.call(None, Atom::from("call"), Box::new(|js: Arc<JS>| { js.new_u32(2); 1usize }), Atom::from("test reject task 2")),
+               Err(RefuseReason::Rejected));
+    assert_eq!(reject.queue_len(), 1);
+
+    //任务调度队列已满，且阻塞超时，则拒绝阻塞的任务
+    assert!(timeout.call(None, Atom::from("call"), Box::new(|js: Arc<JS>| { js.new_u32(0); 1usize }), Atom::from("test timeout task 0")).is_ok());
+    let now = Instant::now();
+    assert_eq!(timeout.call(None, Atom::from("call"), Box::new(|js: Arc<JS>| { js.new_u32(1); 1usize }), Atom::from("test timeout task 1")),
+               Err(RefuseReason::Timeout));
+    assert!(now.elapsed() >= Duration::from_millis(50));
+    assert_eq!(timeout.queue_len(), 1);
+
+    //在虚拟机任务中不允许阻塞，则立即拒绝最新的任务
+    let timeout_copy = timeout.clone();
+    let result = Arc::new(Mutex::new(None));
+    let result_copy = result.clone();
+    scheduler::cast_js_task(TaskType::Async(false), 0, None, Box::new(move |_lock| {
+        let now = Instant::now();
+        let r = timeout_copy.call(None, Atom::from("call"), Box::new(|js: Arc<JS>| { js.new_u32(2); 1usize }), Atom::from("test timeout task 2"));
+        *result_copy.lock().unwrap() = Some((r, now.elapsed()));
+    }), Atom::from("test block in vm task"));
+    executor.run_until_idle();
+    match result.lock().unwrap().take() {
+        Some((r, elapsed)) => assert!(r == Err(RefuseReason::Rejected) && elapsed < Duration::from_millis(50)),
+        None => panic!("block in vm task not run"),
+    }
+    assert_eq!(timeout.queue_len(), 1);
+    assert_eq!(reject.queue_len(), 0);
+
+    //任务调度队列已满，则阻塞调用者，直到任务出队
+    assert!(block.call(None, Atom::from("call"), Box::new(|js: Arc<JS>| { js.new_u32(0); 1usize }), Atom::from("test block task 0")).is_ok());
+    assert!(block.call(None, Atom::from("call"), Box::new(|js: Arc<JS>| { js.new_u32(1); 1usize }), Atom::from("test block task 1")).is_ok());
+    let block_copy = block.clone();
+    let handle = thread::spawn(move || {
+        block_copy.call(None, Atom::from("call"), Box::new(|js: Arc<JS>| { js.new_u32(2); 1usize }), Atom::from("test block task 2"))
+    });
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(block.queue_len(), 1); //调用者仍然阻塞
+    assert!(executor.run_once()); //运行完成后复用虚拟机，取出已排队的任务，唤醒阻塞的调用者
+    assert!(handle.join().unwrap().is_ok());
+    assert_eq!(block.queue_len(), 1);
+    executor.run_until_idle();
+    assert_eq!(block.queue_len(), 0);
+    assert!(executor.infos().ends_with(&[Atom::from("test block task 0"), Atom::from("test block task 1"), Atom::from("test block task 2")]));
+
+    TestExecutor::uninstall();
+}
+
+//测试虚拟机工厂根据任务调度队列的等待时长伸缩
+#[test]
+fn test_vm_factory_scaling() {
+    let executor = TestExecutor::install();
+    set_max_alloced_limit(1073741824);
+
+    register_native_object();
+    let opts = JS::new(1, Atom::from("test vm"), Arc::new(NativeObjsAuth::new(None, None)), None);
+    assert!(opts.is_some());
+    let js = opts.unwrap();
+    let opts = js.compile("test_vm_factory_scaling.js".to_string(), "function call(x) { return x; };".to_string());
+    assert!(opts.is_some());
+    let code = opts.unwrap();
+
+    let config = ScalingConfig::new(1, 2)
+        .set_target_queue_latency(Duration::from_millis(1))
+        .set_scale_up_cooldown(Duration::from_millis(0))
+        .set_scale_down_cooldown(Duration::from_millis(0));
+    let factory = VMFactory::new("test vm scaling", 2, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(Arc::new(code))
+        .set_scaling(config);
+    factory.produce(0).unwrap();
+
+    //空闲虚拟机不足，则补足空闲虚拟机
+    assert_eq!(scale_factory(&factory), ScalingResult::Up(1));
+    assert_eq!(factory.size(), 1);
+
+    //没有空闲虚拟机时，任务加入任务调度队列，等待时长超过目标等待时长后扩容，并立即运行等待的任务
+    assert!(factory.call(None, Atom::from("call"), Box::new(|js: Arc<JS>| { js.new_u32(0); 1usize }), Atom::from("test scaling task 0")).is_ok());
+    assert!(factory.call(None, Atom::from("call"), Box::new(|js: Arc<JS>| { js.new_u32(1); 1usize }), Atom::from("test scaling task 1")).is_ok());
+    assert_eq!(factory.size(), 1);
+    assert_eq!(factory.queue_len(), 1);
+    thread::sleep(Duration::from_millis(10));
+    assert!(factory.queue_latency() >= Duration::from_millis(10));
+    assert_eq!(scale_factory(&factory), ScalingResult::Up(1));
+    assert_eq!(factory.size(), 2);
+    assert_eq!(factory.queue_len(), 0);
+    assert!(factory.queue_latency() >= Duration::from_millis(10)); //出队时采样等待时长
+
+    //已达最多虚拟机数量，则不扩容，任务等待虚拟机复用
+    assert!(factory.call(None, Atom::from("call"), Box::new(|js: Arc<JS>| { js.new_u32(2); 1usize }), Atom::from("test scaling task 2")).is_ok());
+    thread::sleep(Duration::from_millis(10));
+    assert_eq!(scale_factory(&factory), ScalingResult::Ignore);
+    assert_eq!(factory.queue_len(), 1);
+    executor.run_until_idle();
+    assert_eq!(factory.queue_len(), 0);
+    assert_eq!(executor.infos(), vec![Atom::from("test scaling task 0"), Atom::from("test scaling task 1"), Atom::from("test scaling task 2")]);
+
+    //任务调度队列为空，且空闲虚拟机过多，则缩容
+    assert_eq!(factory.free_pool_size() + factory.free_buf_size(), 2);
+    assert_eq!(scale_factory(&factory), ScalingResult::Down(1));
+    assert_eq!(factory.size(), 1);
+    assert_eq!(scale_factory(&factory), ScalingResult::Ignore);
+
+    TestExecutor::uninstall();
+}
+
 //注册本地函数
 fn register_native_function(id: u32, fun: fn(Arc<JS>, Vec<JSType>) -> Option<CallResult>) {
     BON_MGR.regist_fun_meta(FnMeta::CallArg(fun), id);
@@ -358,10 +867,10 @@ fn test_vm_factory_sync_call() {
                     js.new_f32(0.999999);
                     2usize
                 });
-                factory.call(None,
-                             Atom::from("call"),
-                             func,
-                             Atom::from("test factory call task"));
+                assert!(factory.call(None,
+                                     Atom::from("call"),
+                                     func,
+                                     Atom::from("test factory call task")).is_ok());
                 thread::sleep(Duration::from_millis(1000));
             }
             println!("!!!!!!time: {:?}", Instant::now() - now);
@@ -375,6 +884,899 @@ fn js_test_vm_factory_sync_call(js: Arc<JS>, _args: Vec<JSType>) -> Option<CallR
     Some(CallResult::Ok)
 }
 
+//测试从虚拟机工厂使用调用选项进行虚拟机调用
+#[test]
+fn test_vm_factory_call_options() {
+    let executor = TestExecutor::install();
+    set_max_alloced_limit(1073741824);
+
+    register_native_object();
+    register_native_function(0x2, js_test_vm_factory_call_options);
+
+    let opts = JS::new(1, Atom::from("test vm"), Arc::new(NativeObjsAuth::new(None, None)), None);
+    assert!(opts.is_some());
+    let js = opts.unwrap();
+    let opts = js.compile("test_vm_factory_call_options.js".to_string(), "function call() { NativeObject.call(0x2, []); };".to_string());
+    assert!(opts.is_some());
+    let code = opts.unwrap();
+
+    let factory = VMFactory::new("test vm call options", 1, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(Arc::new(code));
+    factory.produce(1).unwrap();
+
+    let trace = TraceContext::new();
+    let options = CallOptions::new()
+        .set_priority(10)
+        .set_trace(trace)
+        .set_tenant_id(Atom::from("admin"));
+    assert!(factory.call_with_options(options, Atom::from("call"), Box::new(|_js: Arc<JS>| 0usize), Atom::from("test factory call options task")).is_ok());
+    executor.run_until_idle();
+
+    //在测试线程上检查本地函数获取的调用选项
+    let options = CALL_OPTIONS.lock().unwrap().take();
+    assert!(options.is_some());
+    let options = options.unwrap();
+    assert_eq!(options.priority(), Some(10));
+    assert_eq!(options.trace_id(), Some(trace.trace_id()));
+    assert_eq!(options.tenant_id(), Some(&Atom::from("admin")));
+
+    //已过截止时间的调用会被立即拒绝
+    let options = CallOptions::new().set_deadline(Instant::now());
+    assert_eq!(factory.call_with_options(options, Atom::from("call"), Box::new(|_js: Arc<JS>| 0usize), Atom::from("test factory expired task")),
+               Err(RefuseReason::Expired));
+    assert_eq!(executor.run_until_idle(), 0);
+    assert!(CALL_OPTIONS.lock().unwrap().is_none());
+
+    TestExecutor::uninstall();
+}
+
+lazy_static! {
+    static ref CALL_OPTIONS: Mutex<Option<CallOptions>> = Mutex::new(None);
+}
+
+fn js_test_vm_factory_call_options(js: Arc<JS>, _args: Vec<JSType>) -> Option<CallResult> {
+    *CALL_OPTIONS.lock().unwrap() = js.call_options();
+    js.new_undefined();
+    Some(CallResult::Ok)
+}
+
+//测试关闭虚拟机工厂
+#[test]
+fn test_vm_factory_shutdown() {
+    TIMER.run();
+    let worker_pool = Box::new(WorkerPool::new("js test".to_string(), WorkerType::Js, 8, 1024 * 1024, 30000, JS_WORKER_WALKER.clone()));
+    worker_pool.run(JS_TASK_POOL.clone());
+    set_max_alloced_limit(1073741824);
+    set_vm_timeout(30000);
+
+    register_native_object();
+    let opts = JS::new(1, Atom::from("test vm"), Arc::new(NativeObjsAuth::new(None, None)), None);
+    assert!(opts.is_some());
+    let js = opts.unwrap();
+    let opts = js.compile("test_vm_factory_shutdown.js".to_string(), "function call(x) { var n = 0; for(var i = 0; i < 100000; i++) { n += i; } console.log(\"!!!!!!x: \" + x + \", n: \" + n); };".to_string());
+    assert!(opts.is_some());
+    let code = opts.unwrap();
+
+    let factory = VMFactory::new("test vm shutdown", 2, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(Arc::new(code));
+    factory.produce(2).unwrap();
+    for index in 0..8 {
+        let func = Box::new(move |js: Arc<JS>| {
+            js.new_u32(index);
+            1usize
+        });
+        assert!(factory.call(None, Atom::from("call"), func, Atom::from("test factory shutdown task")).is_ok());
+    }
+
+    let report = factory.shutdown(Duration::from_millis(10000));
+    println!("!!!!!!shutdown report: {:?}", report);
+    assert!(factory.is_closed());
+    assert!(report.is_graceful());
+    assert_eq!(factory.size(), 0);
+    assert_eq!(factory.call(None, Atom::from("call"), Box::new(|_js: Arc<JS>| 0usize), Atom::from("test factory closed task")),
+               Err(RefuseReason::Closed));
+}
+
+//测试虚拟机工厂的会话亲和
+#[test]
+fn test_vm_factory_affinity() {
+    let executor = TestExecutor::install();
+    set_max_alloced_limit(1073741824);
+
+    register_native_object();
+    register_native_function(0x3, js_test_vm_factory_affinity);
+
+    let opts = JS::new(1, Atom::from("test vm"), Arc::new(NativeObjsAuth::new(None, None)), None);
+    assert!(opts.is_some());
+    let js = opts.unwrap();
+    let opts = js.compile("test_vm_factory_affinity.js".to_string(), "var count = 0; function call(x) { count += x; NativeObject.call(0x3, [count]); };".to_string());
+    assert!(opts.is_some());
+    let code = Arc::new(opts.unwrap());
+
+    let evicted = Arc::new(AtomicUsize::new(0));
+    let evicted_copy = evicted.clone();
+    let config = AffinityConfig::new(1)
+        .set_idle_timeout(Duration::from_millis(0))
+        .set_evict_handler(Arc::new(move |session: usize, _vm: Arc<JS>| {
+            println!("!!!!!!evict session: {}", session);
+            evicted_copy.fetch_add(1, Ordering::SeqCst);
+        }));
+    let factory = VMFactory::new("test vm affinity", 2, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(code.clone())
+        .set_affinity(config);
+    factory.produce(2).unwrap();
+
+    //同一个会话的任务在同一个虚拟机上运行，且全局环境在任务之间保留
+    for _ in 0..10 {
+        let func = Box::new(move |js: Arc<JS>| {
+            js.new_u32(1);
+            1usize
+        });
+        assert!(factory.call_with_options(CallOptions::new().set_session(1), Atom::from("call"), func, Atom::from("test factory affinity task")).is_ok());
+    }
+    executor.run_until_idle();
+    assert_eq!(AFFINITY_COUNT.load(Ordering::SeqCst), 10);
+    assert_eq!(factory.session_size(), 1);
+
+    //超过最多绑定会话的虚拟机数量，则解绑最久未运行的空闲会话
+    assert!(factory.call_with_options(CallOptions::new().set_session(2), Atom::from("call"), Box::new(|js: Arc<JS>| { js.new_u32(1); 1usize }), Atom::from("test factory affinity task")).is_ok());
+    executor.run_until_idle();
+    assert_eq!(evicted.load(Ordering::SeqCst), 1);
+    assert_eq!(factory.session_size(), 1);
+
+    //解绑空闲超时的会话
+    assert_eq!(factory.evict_idle_sessions(), 1);
+    assert_eq!(evicted.load(Ordering::SeqCst), 2);
+    assert_eq!(factory.session_size(), 0);
+
+    //会话等待的任务与任务调度队列共享容量，已满则拒绝最新的任务
+    let bounded = VMFactory::new("test vm affinity bounded", 1, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(code)
+        .set_affinity(AffinityConfig::new(1))
+        .set_queue_capacity(2, OverloadPolicy::RejectNewest);
+    bounded.produce(1).unwrap();
+    for index in 0..3 {
+        assert!(bounded.call_with_options(CallOptions::new().set_session(3), Atom::from("call"), Box::new(|js: Arc<JS>| { js.new_u32(1); 1usize }), Atom::from("test factory affinity bounded task")).is_ok(), "index: {}", index);
+    }
+    assert_eq!(bounded.call_with_options(CallOptions::new().set_session(3), Atom::from("call"), Box::new(|js: Arc<JS>| { js.new_u32(1); 1usize }), Atom::from("test factory affinity bounded task")),
+               Err(RefuseReason::Rejected));
+    assert_eq!(bounded.metrics().snapshot().refuse_count(), 1);
+    executor.run_until_idle();
+    assert_eq!(executor.infos().iter().filter(|info| *info == &Atom::from("test factory affinity bounded task")).count(), 3);
+
+    //会话等待的任务完成后释放位置
+    assert!(bounded.call_with_options(CallOptions::new().set_session(3), Atom::from("call"), Box::new(|js: Arc<JS>| { js.new_u32(1); 1usize }), Atom::from("test factory affinity bounded task")).is_ok());
+    executor.run_until_idle();
+
+    TestExecutor::uninstall();
+}
+
+lazy_static! {
+    static ref AFFINITY_COUNT: AtomicUsize = AtomicUsize::new(0);
+}
+
+fn js_test_vm_factory_affinity(js: Arc<JS>, args: Vec<JSType>) -> Option<CallResult> {
+    AFFINITY_COUNT.store(args[0].get_u32() as usize, Ordering::SeqCst);
+    js.new_undefined();
+    Some(CallResult::Ok)
+}
+
+//测试灰度虚拟机工厂的路由、提升和回滚
+#[test]
+fn test_gray_factory() {
+    let executor = TestExecutor::install();
+    set_max_alloced_limit(1073741824);
+
+    register_native_object();
+    let opts = JS::new(1, Atom::from("test vm"), Arc::new(NativeObjsAuth::new(None, None)), None);
+    assert!(opts.is_some());
+    let js = opts.unwrap();
+    let v1 = js.compile("test_gray_factory_v1.js".to_string(), "function call() { console.log(\"!!!!!!version: 1\"); };".to_string()).unwrap();
+    let v2 = js.compile("test_gray_factory_v2.js".to_string(), "function call() { console.log(\"!!!!!!version: 2\"); };".to_string()).unwrap();
+
+    let factory_v1 = VMFactory::new("test gray factory@1", 1, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(Arc::new(v1));
+    factory_v1.produce(1).unwrap();
+    let factory_v2 = VMFactory::new("test gray factory@2", 1, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(Arc::new(v2));
+    factory_v2.produce(1).unwrap();
+
+    let gray = GrayFactory::new("test gray factory", 1, Arc::new(factory_v1));
+    assert!(gray.add_version(2, Arc::new(factory_v2)));
+    assert!(!gray.set_gray(1, GrayRule::Percent(100)));
+    assert!(gray.set_gray(2, GrayRule::Percent(0)));
+
+    //灰度比例为0，则全部路由到稳定版本
+    for _ in 0..4 {
+        assert!(gray.call(None, Atom::from("call"), Box::new(|_js: Arc<JS>| 0usize), Atom::from("test gray factory task")).is_ok());
+    }
+    assert_eq!(gray.call_count(1), 4);
+    assert_eq!(gray.call_count(2), 0);
+
+    //明确指定灰度版本
+    assert!(gray.call_with_options(CallOptions::new().set_gray(2), Atom::from("call"), Box::new(|_js: Arc<JS>| 0usize), Atom::from("test gray factory task")).is_ok());
+    assert_eq!(gray.call_count(2), 1);
+
+    //相同源总是路由到相同版本
+    assert!(gray.set_gray(2, GrayRule::SourceHash(50)));
+    let (version, _) = gray.route(&CallOptions::new().set_order_key(7));
+    for _ in 0..4 {
+        assert_eq!(gray.route(&CallOptions::new().set_order_key(7)).0, version);
+    }
+
+    //灰度版本不允许移除
+    assert!(gray.retire(2, Duration::from_millis(5000)).is_none());
+    assert_eq!(gray.versions(), vec![1, 2]);
+
+    //回滚后全部路由到稳定版本，提升后全部路由到新的稳定版本
+    assert_eq!(gray.rollback(), Some(2));
+    assert_eq!(gray.route(&CallOptions::new()).0, 1);
+    assert!(gray.set_gray(2, GrayRule::Percent(5)));
+    assert_eq!(gray.promote(), Some(1));
+    assert_eq!(gray.stable_version(), 2);
+    assert_eq!(gray.route(&CallOptions::new()).0, 2);
+
+    executor.run_until_idle();
+    let report = gray.retire(1, Duration::from_millis(5000));
+    assert!(report.is_some());
+    assert_eq!(gray.versions(), vec![2]);
+
+    TestExecutor::uninstall();
+}
+
+//测试虚拟机工厂的批量调用
+#[test]
+fn test_vm_factory_call_batch() {
+    let executor = TestExecutor::install();
+    set_max_alloced_limit(1073741824);
+
+    register_native_object();
+    let opts = JS::new(1, Atom::from("test vm"), Arc::new(NativeObjsAuth::new(None, None)), None);
+    assert!(opts.is_some());
+    let js = opts.unwrap();
+    let opts = js.compile("test_vm_factory_call_batch.js".to_string(), "function add(x) { if(x == 5) { throw new Error(\"invalid x\"); } return x + 1; };".to_string());
+    assert!(opts.is_some());
+    let code = opts.unwrap();
+
+    let factory = VMFactory::new("test vm call batch", 1, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(Arc::new(code));
+    factory.produce(1).unwrap();
+
+    let mut batch: Vec<Box<FnOnce(Arc<JS>) -> usize>> = Vec::new();
+    for index in 0..10 {
+        batch.push(Box::new(move |js: Arc<JS>| {
+            js.new_u32(index);
+            1usize
+        }));
+    }
+
+    let results = Arc::new(Mutex::new(Vec::new()));
+    let results_copy = results.clone();
+    let handler = Box::new(move |_js: Arc<JS>, index: usize, result: Result<&JSType, BatchError>| {
+        match result {
+            Err(e) => results_copy.lock().unwrap().push((index, Err(e))),
+            Ok(value) => results_copy.lock().unwrap().push((index, Ok(value.get_u32()))),
+        }
+    });
+    assert!(factory.call_batch(CallOptions::new(), Atom::from("add"), batch, handler, Atom::from("test factory call batch task")).is_ok());
+    executor.run_until_idle();
+
+    let results = results.lock().unwrap();
+    assert_eq!(results.len(), 10);
+    for (index, result) in results.iter() {
+        if *index == 5 {
+            match result {
+                Err(BatchError::Invoke(5, reason)) => assert!(!reason.is_empty()),
+                _ => panic!("invalid batch result, index: {}, result: {:?}", index, result),
+            }
+        } else {
+            assert_eq!(result, &Ok(*index as u32 + 1));
+        }
+    }
+    assert_eq!(factory.size(), 1);
+
+    //批量调用作为一个任务完成，并记录执行延迟
+    let snapshot = factory.metrics().snapshot();
+    assert_eq!(snapshot.finish_task_count(), 1);
+    assert_eq!(snapshot.execution().count(), 1);
+
+    TestExecutor::uninstall();
+}
+
+//测试虚拟机工厂指标
+#[test]
+fn test_vm_factory_metrics() {
+    let executor = TestExecutor::install();
+    set_max_alloced_limit(1073741824);
+
+    register_native_object();
+    let opts = JS::new(1, Atom::from("test vm"), Arc::new(NativeObjsAuth::new(None, None)), None);
+    assert!(opts.is_some());
+    let js = opts.unwrap();
+    let opts = js.compile("test_vm_factory_metrics.js".to_string(), "function call(x) { if(x == 3) { throw new Error(\"invalid x\"); } return x; };".to_string());
+    assert!(opts.is_some());
+    let code = opts.unwrap();
+
+    let factory = VMFactory::new("test vm metrics", 2, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(Arc::new(code));
+    factory.produce(2).unwrap();
+
+    for index in 0..10 {
+        let func = Box::new(move |js: Arc<JS>| {
+            js.new_u32(index);
+            1usize
+        });
+        assert!(factory.call(None, Atom::from("call"), func, Atom::from("test metrics task")).is_ok());
+        executor.run_until_idle(); //每次调用完成后虚拟机回到虚拟机池，以保证不会构建新的虚拟机
+    }
+
+    let snapshot = metrics::snapshot();
+    let metrics = snapshot.factory("test vm metrics").unwrap();
+    assert_eq!(metrics.size(), 2);
+    assert_eq!(metrics.new_count(), 2);
+    assert_eq!(metrics.call_count(), 10);
+    assert_eq!(metrics.run_panic_count(), 1);
+    assert_eq!(metrics.queue_wait().count(), 10);
+    assert_eq!(metrics.execution().count(), 10);
+    assert_eq!(metrics.execution().counts().iter().sum::<usize>(), 10);
+    assert_eq!(metrics.load_time().count(), 2);
+    assert!(metrics.execution().percentile(0.99).is_some());
+    assert!(snapshot.factory("test vm metrics not exist").is_none());
+
+    TestExecutor::uninstall();
+}
+
+//测试虚拟机事件监听器
+#[test]
+fn test_vm_event_listener() {
+    TIMER.run();
+    let worker_pool = Box::new(WorkerPool::new("js test".to_string(), WorkerType::Js, 8, 1024 * 1024, 30000, JS_WORKER_WALKER.clone()));
+    worker_pool.run(JS_TASK_POOL.clone());
+    set_max_alloced_limit(1073741824);
+    set_vm_timeout(30000);
+
+    register_native_object();
+    let opts = JS::new(1, Atom::from("test vm"), Arc::new(NativeObjsAuth::new(None, None)), None);
+    assert!(opts.is_some());
+    let js = opts.unwrap();
+    let opts = js.compile("test_vm_event_listener.js".to_string(), "function call(x) { throw new Error(\"invalid x\"); };".to_string());
+    assert!(opts.is_some());
+    let code = opts.unwrap();
+
+    let (listener, receiver) = ChannelListener::new();
+    let id = register_factory_listener("test vm event", listener);
+
+    let factory = VMFactory::new("test vm event", 1, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(Arc::new(code));
+    factory.produce(1).unwrap();
+    match receiver.recv_timeout(Duration::from_millis(1000)).unwrap() {
+        VmEvent::Created { factory, .. } => assert_eq!(factory, "test vm event"),
+        event => panic!("invalid event, event: {:?}", event),
+    }
+
+    let func = Box::new(move |js: Arc<JS>| {
+        js.new_u32(1);
+        1usize
+    });
+    assert!(factory.call(None, Atom::from("call"), func, Atom::from("test event task")).is_ok());
+    let mut is_run_error = false;
+    while let Ok(event) = receiver.recv_timeout(Duration::from_millis(1000)) {
+        if let VmEvent::RunError { error, .. } = event {
+            assert!(error.contains("invalid x"));
+            is_run_error = true;
+            break;
+        }
+    }
+    assert!(is_run_error);
+
+    factory.shutdown(Duration::from_millis(1000));
+    let func = Box::new(move |js: Arc<JS>| {
+        js.new_u32(1);
+        1usize
+    });
+    assert_eq!(factory.call(None, Atom::from("call"), func, Atom::from("test event task")), Err(RefuseReason::Closed));
+    let mut is_refused = false;
+    while let Ok(event) = receiver.recv_timeout(Duration::from_millis(1000)) {
+        if let VmEvent::Refused { reason, .. } = event {
+            assert_eq!(reason, RefuseReason::Closed);
+            is_refused = true;
+            break;
+        }
+    }
+    assert!(is_refused);
+
+    assert!(unregister_listener(id).is_some());
+    assert!(unregister_listener(id).is_none());
+}
+
+//测试虚拟机工厂调用的跟踪
+#[test]
+fn test_vm_factory_trace() {
+    let executor = TestExecutor::install();
+    set_max_alloced_limit(1073741824);
+
+    register_native_object();
+    let opts = JS::new(1, Atom::from("test vm"), Arc::new(NativeObjsAuth::new(None, None)), None);
+    assert!(opts.is_some());
+    let js = opts.unwrap();
+    let opts = js.compile("test_vm_factory_trace.js".to_string(), "function call(x) { return x; };".to_string());
+    assert!(opts.is_some());
+    let code = opts.unwrap();
+
+    let factory = VMFactory::new("test vm trace", 1, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(Arc::new(code));
+    factory.produce(1).unwrap();
+
+    enable_trace(1024);
+    let root = TraceContext::new();
+    for index in 0..3 {
+        let func = Box::new(move |js: Arc<JS>| {
+            js.new_u32(index);
+            1usize
+        });
+        assert!(factory.call_with_options(CallOptions::new().set_trace(root), Atom::from("call"), func, Atom::from("test trace task")).is_ok());
+    }
+    executor.run_until_idle();
+    disable_trace();
+
+    let spans: Vec<_> = take_spans().into_iter().filter(|span| span.context().trace_id() == root.trace_id()).collect();
+    assert_eq!(spans.len(), 3);
+    for span in &spans {
+        assert_eq!(span.name(), "call");
+        assert_eq!(span.parent_id(), Some(root.span_id()));
+        assert_ne!(span.context().span_id(), root.span_id());
+        assert!(span.attrs().contains(&("factory", "test vm trace".to_string())));
+    }
+
+    enable_trace(1024);
+    let func = Box::new(move |js: Arc<JS>| {
+        js.new_u32(0);
+        1usize
+    });
+    assert!(factory.call_with_options(CallOptions::new().set_trace(root), Atom::from("call"), func, Atom::from("test trace task")).is_ok());
+    executor.run_until_idle();
+    disable_trace();
+
+    let path = std::env::temp_dir().join("test_vm_factory_trace.json");
+    assert!(export_chrome_trace(&path).unwrap() >= 1);
+    let text = std::fs::read_to_string(&path).unwrap();
+    assert!(text.starts_with("{\"traceEvents\":["));
+    assert!(text.contains(&format!("\"trace_id\":\"{:016x}\"", root.trace_id())));
+    assert!(text.contains(&format!("\"parent_id\":\"{:016x}\"", root.span_id())));
+    let _ = std::fs::remove_file(&path);
+
+    TestExecutor::uninstall();
+}
+
+//测试虚拟机工厂的慢调用记录
+#[test]
+fn test_vm_factory_slow_call() {
+    let executor = TestExecutor::install();
+    set_max_alloced_limit(1073741824);
+
+    register_native_object();
+    let opts = JS::new(1, Atom::from("test vm"), Arc::new(NativeObjsAuth::new(None, None)), None);
+    assert!(opts.is_some());
+    let js = opts.unwrap();
+    let opts = js.compile("test_vm_factory_slow_call.js".to_string(), "function call(x) { var start = Date.now(); while (Date.now() - start < 50) {}; return x; };".to_string());
+    assert!(opts.is_some());
+    let code = opts.unwrap();
+
+    let factory = VMFactory::new("test vm slow", 1, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(Arc::new(code));
+    factory.produce(1).unwrap();
+
+    set_slow_threshold("test vm slow", Duration::from_millis(10));
+    let func = Box::new(move |js: Arc<JS>| {
+        js.new_u32(0);
+        1usize
+    });
+    assert!(factory.call(None, Atom::from("call"), func, Atom::from("test slow task")).is_ok());
+    executor.run_until_idle();
+
+    let calls: Vec<_> = slow_calls().into_iter().filter(|call| call.factory() == "test vm slow").collect();
+    assert_eq!(calls.len(), 1);
+    assert_eq!(calls[0].kind(), SlowKind::Task);
+    assert_eq!(calls[0].target(), "call");
+    assert_eq!(calls[0].info(), "test slow task");
+    assert!(calls[0].execution() >= Duration::from_millis(50));
+    println!("!!!!!!slow call: {}", calls[0]);
+
+    assert_eq!(remove_slow_threshold("test vm slow"), Some(Duration::from_millis(10)));
+    let func = Box::new(move |js: Arc<JS>| {
+        js.new_u32(0);
+        1usize
+    });
+    assert!(factory.call(None, Atom::from("call"), func, Atom::from("test slow task")).is_ok());
+    executor.run_until_idle();
+    assert_eq!(slow_calls().into_iter().filter(|call| call.factory() == "test vm slow").count(), 1);
+
+    TestExecutor::uninstall();
+}
+
+//测试单线程的确定性测试执行器，不启动工作者
+#[test]
+fn test_vm_test_executor() {
+    let executor = TestExecutor::install();
+    set_max_alloced_limit(1073741824);
+
+    register_native_object();
+    let opts = JS::new(1, Atom::from("test vm"), Arc::new(NativeObjsAuth::new(None, None)), None);
+    assert!(opts.is_some());
+    let js = opts.unwrap();
+    register_native_function(0x30, js_test_vm_test_executor);
+    let opts = js.compile("test_vm_test_executor.js".to_string(), "function call(x) { return x; }; function wait() { var cb = callbacks.register(function(x) { return x; }); NativeObject.call(0x30, [cb]); };".to_string());
+    assert!(opts.is_some());
+    let code = opts.unwrap();
+
+    let factory = VMFactory::new("test vm executor", 1, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(Arc::new(code));
+    factory.produce(1).unwrap();
+
+    //虚拟机工厂的任务只在测试执行器执行时运行
+    let func = Box::new(move |js: Arc<JS>| {
+        js.new_u32(1);
+        1usize
+    });
+    assert!(factory.call(None, Atom::from("call"), func, Atom::from("test executor task")).is_ok());
+    assert_eq!(executor.ready_len(), 1);
+    assert!(executor.run_until_idle() >= 1);
+    assert_eq!(executor.ready_len(), 0);
+    assert!(executor.infos().contains(&Atom::from("test executor task")));
+    assert_eq!(factory.free_pool_size() + factory.free_buf_size(), 1);
+
+    //延迟任务只在推进虚拟时间后到期
+    let count = Arc::new(AtomicUsize::new(0));
+    let count_copy = count.clone();
+    let handle = scheduler::cast_js_delay_task(TaskType::Async(false), 0, None, Box::new(move |_lock| {
+        count_copy.fetch_add(1, Ordering::SeqCst);
+    }), 100, Atom::from("test executor delay task"));
+    assert!(handle.is_some());
+    assert_eq!(executor.run_until_idle(), 0);
+    assert_eq!(executor.delay_len(), 1);
+    assert_eq!(executor.advance(Duration::from_millis(99)), 0);
+    assert_eq!(count.load(Ordering::SeqCst), 0);
+    assert_eq!(executor.advance(Duration::from_millis(1)), 1);
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+    assert_eq!(executor.now(), Duration::from_millis(100));
+
+    //已到期的延迟任务的句柄不会被复用
+    let next = scheduler::cast_js_delay_task(TaskType::Async(false), 0, None, Box::new(|_lock| {}), 10, Atom::from("test executor delay task"));
+    assert!(next.is_some());
+    assert_ne!(next, handle);
+    assert_eq!(executor.advance(Duration::from_millis(10)), 1);
+
+    //取消的延迟任务不会执行，已到期的延迟任务无法取消
+    let count_copy = count.clone();
+    let cancelled = scheduler::cast_js_delay_task(TaskType::Async(false), 0, None, Box::new(move |_lock| {
+        count_copy.fetch_add(1, Ordering::SeqCst);
+    }), 10, Atom::from("test executor cancelled delay task"));
+    assert!(scheduler::cancel_js_delay_task(cancelled.unwrap()));
+    assert!(!scheduler::cancel_js_delay_task(cancelled.unwrap()));
+    assert!(!scheduler::cancel_js_delay_task(next.unwrap()));
+    assert_eq!(executor.delay_len(), 0);
+    assert_eq!(executor.advance(Duration::from_millis(10)), 0);
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+
+    //锁住的任务队列中的任务不会被执行，执行时锁住任务队列
+    let queue = scheduler::create_js_task_queue(0, true);
+    assert!(scheduler::lock_js_task_queue(queue));
+    let count_copy = count.clone();
+    scheduler::cast_js_task(TaskType::Sync(true), 0, Some(queue), Box::new(move |lock| {
+        assert_eq!(lock, Some(queue));
+        count_copy.fetch_add(1, Ordering::SeqCst);
+    }), Atom::from("test executor queue task"));
+    assert_eq!(executor.run_until_idle(), 0);
+    assert_eq!(executor.queue_len(queue), Some(1));
+    assert!(scheduler::unlock_js_task_queue(queue));
+    assert_eq!(executor.run_until_idle(), 1);
+    assert_eq!(count.load(Ordering::SeqCst), 2);
+    assert_eq!(executor.is_locked(queue), Some(true));
+    assert!(scheduler::remove_js_task_queue(queue));
+
+    //优先级高的任务先执行，优先级相同时按投递顺序执行
+    scheduler::cast_js_task(TaskType::Async(false), 0, None, Box::new(|_lock| {}), Atom::from("test executor low task"));
+    scheduler::cast_js_task(TaskType::Async(false), 100, None, Box::new(|_lock| {}), Atom::from("test executor high task 0"));
+    scheduler::cast_js_task(TaskType::Async(false), 100, None, Box::new(|_lock| {}), Atom::from("test executor high task 1"));
+    assert_eq!(executor.run_until_idle(), 3);
+    assert!(executor.infos().ends_with(&[Atom::from("test executor high task 0"), Atom::from("test executor high task 1"), Atom::from("test executor low task")]));
+
+    //观察虚拟机状态，任务完成时还有异步消息则保持运行，处理完异步消息后等待异步回调，移除异步回调后空闲
+    let vm = factory.take().unwrap();
+    executor.watch(vm.clone());
+    executor.assert_status(&vm, JSStatus::NoTask);
+    let vm_copy = vm.clone();
+    scheduler::cast_js_task(TaskType::Async(false), 0, None, Box::new(move |_lock| {
+        vm_copy.get_link_function("wait".to_string());
+        vm_copy.call(0);
+    }), Atom::from("test executor wait task"));
+    assert!(executor.run_once());
+    executor.assert_status(&vm, JSStatus::SingleTask);
+    assert!(executor.run_once());
+    executor.assert_status(&vm, JSStatus::WaitCallBack);
+    let callback = EXECUTOR_CALLBACK.lock().unwrap().take().unwrap();
+    JS::remove_callback(vm.clone(), TaskType::Sync(true), callback, Atom::from("test executor remove callback task"));
+    executor.run_until_idle();
+    executor.assert_transitions(&vm, &[JSStatus::NoTask, JSStatus::SingleTask, JSStatus::WaitCallBack, JSStatus::NoTask]);
+
+    assert!(TestExecutor::uninstall().is_some());
+    assert!(TestExecutor::current().is_none());
+}
+
+lazy_static! {
+    static ref EXECUTOR_CALLBACK: Mutex<Option<u32>> = Mutex::new(None);
+}
+
+fn js_test_vm_test_executor(js: Arc<JS>, args: Vec<JSType>) -> Option<CallResult> {
+    let callback = args[0].get_u32();
+    *EXECUTOR_CALLBACK.lock().unwrap() = Some(callback);
+
+    //向当前虚拟机推送异步消息，以保证当前任务完成时消息队列不为空
+    push_msg(js.clone(), callback, Box::new(|vm: Arc<JS>| {
+        vm.new_u32(1);
+        1usize
+    }), Atom::from("test executor msg task"));
+    js.new_undefined();
+    Some(CallResult::Ok)
+}
+
+//测试基于tokio的虚拟机任务调度器
+#[cfg(feature = "tokio")]
+#[test]
+fn test_vm_tokio_scheduler() {
+    let runtime = tokio::runtime::Builder::new().threaded_scheduler().enable_all().build().unwrap();
+    let scheduler = TokioScheduler::new(runtime.handle().clone());
+    let (sender, receiver) = std::sync::mpsc::channel();
+
+    //全局任务立即执行
+    let sender_copy = sender.clone();
+    assert!(scheduler.cast(TaskType::Async(false), 0, None, Box::new(move |lock| {
+        sender_copy.send(("global", lock)).unwrap();
+    }), Atom::from("test tokio global task")).is_some());
+    assert_eq!(receiver.recv_timeout(Duration::from_secs(1)).unwrap(), ("global", None));
+
+    //锁住的任务队列中的任务不会被执行，执行时锁住任务队列，解锁后执行下一个任务
+    let queue = scheduler.create_queue(0, true);
+    assert!(scheduler.lock_queue(queue));
+    for name in &["queue 0", "queue 1"] {
+        let sender_copy = sender.clone();
+        let name = *name;
+        assert!(scheduler.cast(TaskType::Sync(true), 0, Some(queue), Box::new(move |lock| {
+            sender_copy.send((name, lock)).unwrap();
+        }), Atom::from("test tokio queue task")).is_some());
+    }
+    assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+    assert_eq!(scheduler.queue_len(queue), Some(2));
+    assert!(scheduler.unlock_queue(queue));
+    assert_eq!(receiver.recv_timeout(Duration::from_secs(1)).unwrap(), ("queue 0", Some(queue)));
+    assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+    assert!(scheduler.unlock_queue(queue));
+    assert_eq!(receiver.recv_timeout(Duration::from_secs(1)).unwrap(), ("queue 1", Some(queue)));
+
+    //延迟任务在延迟到期后执行
+    let start = Instant::now();
+    let sender_copy = sender.clone();
+    assert!(scheduler.cast_delay(TaskType::Async(false), 0, None, Box::new(move |lock| {
+        sender_copy.send(("delay", lock)).unwrap();
+    }), 50, Atom::from("test tokio delay task")).is_some());
+    assert_eq!(receiver.recv_timeout(Duration::from_secs(1)).unwrap(), ("delay", None));
+    assert!(start.elapsed() >= Duration::from_millis(50));
+
+    //延迟任务的句柄唯一，取消的延迟任务不会执行
+    let sender_copy = sender.clone();
+    let first = scheduler.cast_delay(TaskType::Async(false), 0, None, Box::new(move |lock| {
+        sender_copy.send(("cancelled delay", lock)).unwrap();
+    }), 50, Atom::from("test tokio cancelled delay task"));
+    let second = scheduler.cast_delay(TaskType::Async(false), 0, None, Box::new(|_lock| {}), 50, Atom::from("test tokio delay task"));
+    assert!(first.is_some() && second.is_some());
+    assert_ne!(first, second);
+    assert!(scheduler.cancel_delay(first.unwrap()));
+    assert!(!scheduler.cancel_delay(first.unwrap()));
+    assert!(receiver.recv_timeout(Duration::from_millis(200)).is_err());
+    assert!(!scheduler.cancel_delay(second.unwrap()));
+
+    assert!(scheduler.remove_queue(queue));
+    assert!(!scheduler.unlock_queue(queue));
+}
+
+//测试健康报告
+#[test]
+fn test_vm_health() {
+    let executor = TestExecutor::install();
+    set_max_alloced_limit(1073741824);
+
+    register_native_object();
+    let opts = JS::new(1, Atom::from("test vm"), Arc::new(NativeObjsAuth::new(None, None)), None);
+    assert!(opts.is_some());
+    let js = opts.unwrap();
+    let opts = js.compile("test_vm_health.js".to_string(), "function call(x) { if (x > 0) { throw new Error(\"test health\"); }; return x; };".to_string());
+    assert!(opts.is_some());
+    let code = opts.unwrap();
+
+    let factory = VMFactory::new("test vm health", 1, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(Arc::new(code));
+    factory.produce(1).unwrap();
+
+    let report = pi_vm::health();
+    println!("!!!!!!health: {}", report);
+    let factory_health = report.factory("test vm health").unwrap();
+    assert!(factory_health.can_produce());
+    assert_eq!(factory_health.status(), HealthStatus::Pass);
+    assert_eq!(factory_health.init_panic_rate(), 0.0);
+    assert!(factory_health.checks().iter().any(|check| check.name() == "init_panic_rate"));
+    assert!(report.checks().iter().any(|check| check.name() == "collect_timer"));
+    assert!(report.checks().iter().any(|check| check.name() == "memory"));
+
+    //调用抛出异常，运行异常比率超过失败阈值
+    let func = Box::new(move |js: Arc<JS>| {
+        js.new_u32(1);
+        1usize
+    });
+    assert!(factory.call(None, Atom::from("call"), func, Atom::from("test health task")).is_ok());
+    executor.run_until_idle();
+
+    let report = health_with(&HealthThresholds::new().set_error_rate(0.1, 0.5));
+    let factory_health = report.factory("test vm health").unwrap();
+    assert!(factory_health.error_rate() > 0.5);
+    assert_eq!(factory_health.status(), HealthStatus::Fail);
+    assert_eq!(report.status(), HealthStatus::Fail);
+    assert!(!report.is_ready());
+
+    TestExecutor::uninstall();
+}
+
+//测试指标的Prometheus文本格式、json格式和指标服务
+#[test]
+fn test_vm_metrics_exposition() {
+    let executor = TestExecutor::install();
+    set_max_alloced_limit(1073741824);
+
+    register_native_object();
+    let opts = JS::new(1, Atom::from("test vm"), Arc::new(NativeObjsAuth::new(None, None)), None);
+    assert!(opts.is_some());
+    let js = opts.unwrap();
+    let opts = js.compile("test_vm_metrics_exposition.js".to_string(), "function call(x) { return x; };".to_string());
+    assert!(opts.is_some());
+    let code = opts.unwrap();
+
+    let factory = VMFactory::new("test vm exposition", 1, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(Arc::new(code));
+    factory.produce(1).unwrap();
+    let func = Box::new(move |js: Arc<JS>| {
+        js.new_u32(1);
+        1usize
+    });
+    assert!(factory.call(None, Atom::from("call"), func, Atom::from("test exposition task")).is_ok());
+    executor.run_until_idle();
+
+    let text = render_prometheus();
+    assert!(text.contains("# TYPE pi_vm_factory_size gauge\n"));
+    assert!(text.contains("pi_vm_factory_size{factory=\"test vm exposition\"} 1\n"));
+    assert!(text.contains("pi_vm_factory_calls_total{factory=\"test vm exposition\"} 1\n"));
+    assert!(text.contains("pi_vm_factory_execution_seconds_count{factory=\"test vm exposition\"} 1\n"));
+    assert!(text.contains("pi_vm_factory_execution_seconds_bucket{factory=\"test vm exposition\",le=\"+Inf\"} 1\n"));
+    assert!(text.contains("# TYPE pi_vm_vm_call_count_total counter\n"));
+
+    let json = render_json();
+    assert!(json.starts_with("{\"time\":"));
+    assert!(json.contains("{\"name\":\"pi_vm_factory_size\",\"labels\":{\"factory\":\"test vm exposition\"},\"value\":1}"));
+
+    //未发送请求的连接不会阻塞其它连接
+    let addr = listen_metrics(0).unwrap();
+    let idle = TcpStream::connect(addr).unwrap();
+    let now = Instant::now();
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(now.elapsed() < Duration::from_millis(1000));
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("pi_vm_factory_size{factory=\"test vm exposition\"} 1\n"));
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(b"GET /unknown HTTP/1.1\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    drop(idle);
+
+    TestExecutor::uninstall();
+}
+
+//测试整理空闲的同步任务队列
+#[test]
+fn test_vm_queue_sweep() {
+    let executor = TestExecutor::install();
+    set_max_alloced_limit(1073741824);
+
+    register_native_object();
+    let opts = JS::new(1, Atom::from("test vm"), Arc::new(NativeObjsAuth::new(None, None)), None);
+    assert!(opts.is_some());
+    let js = opts.unwrap();
+    let opts = js.compile("test_vm_queue_sweep.js".to_string(), "function call(x) { console.log(\"!!!!!!x: \" + x); };".to_string());
+    assert!(opts.is_some());
+    let code = opts.unwrap();
+
+    let factory = VMFactory::new("test vm queue sweep", 1, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(Arc::new(code));
+    factory.produce(1).unwrap();
+
+    //通过new_queue获取的同步任务队列不会被自动整理
+    let src = 0xf0000001;
+    let queue = new_queue(0xf0000002);
+    for index in 0..4 {
+        let func = Box::new(move |js: Arc<JS>| {
+            js.new_u32(index);
+            1usize
+        });
+        assert!(factory.call(Some(src), Atom::from("call"), func, Atom::from("test queue sweep task")).is_ok());
+    }
+    assert!(VM_FACTORY_QUEUES.read().unwrap().contains_key(&src));
+    executor.run_until_idle();
+
+    assert_eq!(VM_FACTORY_QUEUES.read().unwrap().get(&src).unwrap().pending(), 0);
+    assert!(sweep_idle_queues(Duration::from_millis(0)) >= 1);
+    assert!(!VM_FACTORY_QUEUES.read().unwrap().contains_key(&src));
+    assert!(VM_FACTORY_QUEUES.read().unwrap().contains_key(&0xf0000002));
+    assert_eq!(remove_queue(0xf0000002), Some(queue));
+
+    //被整理的源再次调用时，会构建新的同步任务队列
+    assert!(factory.call(Some(src), Atom::from("call"), Box::new(|js: Arc<JS>| { js.new_u32(4); 1usize }), Atom::from("test queue sweep task")).is_ok());
+    assert!(VM_FACTORY_QUEUES.read().unwrap().contains_key(&src));
+    executor.run_until_idle();
+
+    TestExecutor::uninstall();
+}
+
+//测试整理同步任务队列时，不会整理被阻塞调用锁住的同步任务队列
+#[test]
+fn test_vm_queue_sweep_blocked() {
+    let executor = TestExecutor::install();
+    set_max_alloced_limit(1073741824);
+
+    register_native_object();
+    register_native_function(0x20, js_test_vm_queue_sweep_blocked);
+    let opts = JS::new(1, Atom::from("test vm"), Arc::new(NativeObjsAuth::new(None, None)), None);
+    assert!(opts.is_some());
+    let js = opts.unwrap();
+    let opts = js.compile("test_vm_queue_sweep_blocked.js".to_string(), "function call(x) { NativeObject.call(0x20, [x]); var r = __thread_yield(); console.log(\"!!!!!!x: \" + x + \", r: \" + r); };".to_string());
+    assert!(opts.is_some());
+    let code = opts.unwrap();
+
+    let factory = VMFactory::new("test vm queue sweep blocked", 1, 27, 1073741824, 1073741824, Arc::new(NativeObjsAuth::new(None, None)))
+        .append(Arc::new(code));
+    factory.produce(1).unwrap();
+
+    let src = 0xf0000003;
+    assert!(factory.call(Some(src), Atom::from("call"), Box::new(|js: Arc<JS>| { js.new_u32(0); 1usize }), Atom::from("test queue sweep blocked task")).is_ok());
+    assert!(executor.run_once());
+
+    //虚拟机已阻塞，同步任务队列仍被锁住，则不会被整理
+    let blocked = BLOCKED_VM.lock().unwrap().take();
+    assert!(blocked.is_some());
+    assert_eq!(VM_FACTORY_QUEUES.read().unwrap().get(&src).unwrap().pending(), 1);
+    sweep_idle_queues(Duration::from_millis(0));
+    assert!(VM_FACTORY_QUEUES.read().unwrap().contains_key(&src));
+
+    //回应阻塞调用，任务完成并解锁同步任务队列后，才会被整理
+    block_reply(blocked.unwrap(), Box::new(|vm: Arc<JS>| { vm.new_u32(1); }), Atom::from("test queue sweep blocked reply"));
+    executor.run_until_idle();
+    assert_eq!(VM_FACTORY_QUEUES.read().unwrap().get(&src).unwrap().pending(), 0);
+    assert!(sweep_idle_queues(Duration::from_millis(0)) >= 1);
+    assert!(!VM_FACTORY_QUEUES.read().unwrap().contains_key(&src));
+
+    TestExecutor::uninstall();
+}
+
+lazy_static! {
+    static ref BLOCKED_VM: Mutex<Option<Arc<JS>>> = Mutex::new(None);
+}
+
+fn js_test_vm_queue_sweep_blocked(js: Arc<JS>, _args: Vec<JSType>) -> Option<CallResult> {
+    *BLOCKED_VM.lock().unwrap() = Some(js);
+    None
+}
+
 //测试从虚拟机工厂进行虚拟机阻塞调用
 #[test]
 fn test_vm_factory_block_call() {
@@ -414,10 +1816,10 @@ fn test_vm_factory_block_call() {
                     js.new_f32(0.999999);
                     2usize
                 });
-                factory.call(None,
-                             Atom::from("call"),
-                             func,
-                             Atom::from("test factory call task"));
+                assert!(factory.call(None,
+                                     Atom::from("call"),
+                                     func,
+                                     Atom::from("test factory call task")).is_ok());
                 thread::sleep(Duration::from_millis(1000));
             }
             println!("!!!!!!time: {:?}", Instant::now() - now);
@@ -480,10 +1882,10 @@ fn test_vm_factory_async_callback() {
                     js.new_f32(0.999999);
                     2usize
                 });
-                factory.call(None,
-                             Atom::from("call"),
-                             func,
-                             Atom::from("test factory call task"));
+                assert!(factory.call(None,
+                                     Atom::from("call"),
+                                     func,
+                                     Atom::from("test factory call task")).is_ok());
                 thread::sleep(Duration::from_millis(2000));
             }
             println!("!!!!!!time: {:?}", Instant::now() - now);
@@ -542,10 +1944,10 @@ fn test_vm_sync_load_mod() {
             let func = Box::new(move |js: Arc<JS>| {
                 0usize
             });
-            factory.call(None,
-                         Atom::from("test_call"),
-                         func,
-                         Atom::from("test sync load module task"));
+            assert!(factory.call(None,
+                                 Atom::from("test_call"),
+                                 func,
+                                 Atom::from("test sync load module task")).is_ok());
         },
     }
     thread::sleep(Duration::from_millis(100000));
@@ -596,10 +1998,10 @@ fn test_vm_async_load_mod() {
             let func = Box::new(move |js: Arc<JS>| {
                 0usize
             });
-            factory.call(None,
-                         Atom::from("test_call"),
-                         func,
-                         Atom::from("test async load module task"));
+            assert!(factory.call(None,
+                                 Atom::from("test_call"),
+                                 func,
+                                 Atom::from("test async load module task")).is_ok());
         },
     }
     thread::sleep(Duration::from_millis(100000));
@@ -682,10 +2084,10 @@ fn test_process() {
             let func = Box::new(move |js: Arc<JS>| {
                 0usize
             });
-            factory.call(None,
-                         Atom::from("test_call"),
-                         func,
-                         Atom::from("test sync load module task"));
+            assert!(factory.call(None,
+                                 Atom::from("test_call"),
+                                 func,
+                                 Atom::from("test sync load module task")).is_ok());
         },
     }
     thread::sleep(Duration::from_millis(100000));